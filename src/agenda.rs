@@ -0,0 +1,688 @@
+//! Code for drawing today's agenda from iCalendar (`.ics`) files.
+//!
+//! Only the parts of RFC 5545 that matter for an agenda are supported: the
+//! start, end and summary of events, recurrence rules with their most common
+//! parts, exception dates and modified or cancelled instances. Times with a
+//! time zone identifier are treated as local time.
+use std::fs::{read_dir, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+use svg2polylines::Polyline;
+use time::{self, Tm};
+
+use scaling::Bounds;
+use text::{self, Align, TextStyle};
+
+/// The smallest text size (in millimeters) used to fit the agenda on the board.
+const MIN_SIZE: f64 = 3.0;
+
+const WEEKDAYS: [&str; 7] = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+const WEEKDAY_NAMES: [&str; 7] = ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"];
+const MONTH_NAMES: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+
+fn default_size() -> f64 {
+    8.0
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgendaConfig {
+    /// An `.ics` file, or a directory containing `.ics` files.
+    pub path: String,
+    /// The largest text size, in millimeters. Long agendas are drawn smaller.
+    #[serde(default = "default_size")]
+    pub size: f64,
+}
+
+/// A calendar date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Date {
+    year: i32,
+    month: u32,
+    day: u32,
+}
+
+impl Date {
+    fn from_tm(tm: &Tm) -> Self {
+        Date { year: tm.tm_year + 1900, month: tm.tm_mon as u32 + 1, day: tm.tm_mday as u32 }
+    }
+
+    /// Return the number of days since 1970-01-01.
+    fn days(self) -> i64 {
+        let year = i64::from(if self.month <= 2 { self.year - 1 } else { self.year });
+        let era = if year >= 0 { year } else { year - 399 } / 400;
+        let year_of_era = year - era * 400;
+        let month = i64::from(self.month);
+        let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    /// Return the date that is the specified number of days after 1970-01-01.
+    fn from_days(days: i64) -> Self {
+        let days = days + 719_468;
+        let era = if days >= 0 { days } else { days - 146_096 } / 146_097;
+        let day_of_era = days - era * 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = (year_of_era + era * 400) as i32 + if month <= 2 { 1 } else { 0 };
+        Date { year, month, day }
+    }
+
+    /// Return the day of the week, starting with 0 for Monday.
+    fn weekday(self) -> u32 {
+        // 1970-01-01 was a Thursday
+        (self.days() + 3).rem_euclid(7) as u32
+    }
+
+    fn days_in_month(self) -> u32 {
+        let next = if self.month == 12 {
+            Date { year: self.year + 1, month: 1, day: 1 }
+        } else {
+            Date { year: self.year, month: self.month + 1, day: 1 }
+        };
+        (next.days() - Date { day: 1, ..self }.days()) as u32
+    }
+
+    fn days_in_year(self) -> u32 {
+        let first = Date { year: self.year, month: 1, day: 1 };
+        (Date { year: self.year + 1, ..first }.days() - first.days()) as u32
+    }
+
+    fn day_of_year(self) -> u32 {
+        (self.days() - Date { month: 1, day: 1, ..self }.days()) as u32 + 1
+    }
+}
+
+/// The start or end of an event, in local time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EventTime {
+    /// An all-day event.
+    Date(Date),
+    /// Date, hour and minute.
+    DateTime(Date, u32, u32),
+}
+
+impl EventTime {
+    fn date(&self) -> Date {
+        match *self {
+            EventTime::Date(date) | EventTime::DateTime(date, _, _) => date,
+        }
+    }
+}
+
+/// Parse a date (`20181024`) or a date with time (`20181024T093000`, with a
+/// trailing `Z` for UTC).
+fn parse_time(value: &str) -> Option<EventTime> {
+    let number = |range: ::std::ops::Range<usize>| value.get(range).and_then(|s| s.parse::<u32>().ok());
+    let date = Date {
+        year: number(0..4)? as i32,
+        month: number(4..6)?,
+        day: number(6..8)?,
+    };
+    if !(1..=12).contains(&date.month) || date.day == 0 || date.day > date.days_in_month() {
+        return None;
+    }
+    if value.len() == 8 {
+        return Some(EventTime::Date(date));
+    }
+    if value.get(8..9) != Some("T") {
+        return None;
+    }
+    let (hour, minute, _second) = (number(9..11)?, number(11..13)?, number(13..15)?);
+    if hour > 23 || minute > 59 {
+        return None;
+    }
+    match &value[15..] {
+        "" => Some(EventTime::DateTime(date, hour, minute)),
+        "Z" => {
+            // Convert from UTC to local time
+            let utc = Tm {
+                tm_year: date.year - 1900,
+                tm_mon: date.month as i32 - 1,
+                tm_mday: date.day as i32,
+                tm_hour: hour as i32,
+                tm_min: minute as i32,
+                ..time::empty_tm()
+            };
+            let local = time::at(utc.to_timespec());
+            Some(EventTime::DateTime(Date::from_tm(&local), local.tm_hour as u32, local.tm_min as u32))
+        },
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// A recurrence rule (`RRULE`).
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<usize>,
+    until: Option<Date>,
+    /// Weekdays, with an optional ordinal (e.g. `-1FR` for the last Friday).
+    by_day: Vec<(i32, u32)>,
+    /// Days of the month, negative values count from the end.
+    by_month_day: Vec<i32>,
+    by_month: Vec<u32>,
+}
+
+impl Rule {
+    fn parse(value: &str) -> Option<Self> {
+        let mut rule = Rule {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: vec![],
+            by_month_day: vec![],
+            by_month: vec![],
+        };
+        let mut frequency = None;
+        for part in value.split(';') {
+            let mut pair = part.splitn(2, '=');
+            let (key, value) = (pair.next()?, pair.next()?);
+            match key {
+                "FREQ" => frequency = Some(match value {
+                    "DAILY" => Frequency::Daily,
+                    "WEEKLY" => Frequency::Weekly,
+                    "MONTHLY" => Frequency::Monthly,
+                    "YEARLY" => Frequency::Yearly,
+                    _ => return None,
+                }),
+                "INTERVAL" => rule.interval = value.parse().ok().filter(|&i| i > 0)?,
+                "COUNT" => rule.count = Some(value.parse().ok()?),
+                "UNTIL" => rule.until = Some(parse_time(value)?.date()),
+                "BYDAY" => for day in value.split(',') {
+                    let split = day.len().checked_sub(2)?;
+                    let weekday = WEEKDAYS.iter().position(|&d| Some(d) == day.get(split..))? as u32;
+                    let ordinal = match day.get(..split)? {
+                        "" => 0,
+                        ordinal => ordinal.trim_start_matches('+').parse().ok()?,
+                    };
+                    rule.by_day.push((ordinal, weekday));
+                },
+                "BYMONTHDAY" => for day in value.split(',') {
+                    rule.by_month_day.push(day.parse().ok()?);
+                },
+                "BYMONTH" => for month in value.split(',') {
+                    rule.by_month.push(month.parse().ok()?);
+                },
+                // Other parts (e.g. WKST or BYSETPOS) are ignored
+                _ => {},
+            }
+        }
+        rule.frequency = frequency?;
+        Some(rule)
+    }
+
+    /// Return whether the rule matches the date, ignoring `COUNT` and `UNTIL`.
+    fn matches(&self, start: Date, date: Date) -> bool {
+        let interval = i64::from(self.interval);
+        let months = |date: Date| i64::from(date.year) * 12 + i64::from(date.month);
+        let periods = match self.frequency {
+            Frequency::Daily => date.days() - start.days(),
+            Frequency::Weekly => {
+                let monday = |date: Date| date.days() - i64::from(date.weekday());
+                (monday(date) - monday(start)) / 7
+            },
+            Frequency::Monthly => months(date) - months(start),
+            Frequency::Yearly => i64::from(date.year - start.year),
+        };
+        if periods % interval != 0 {
+            return false;
+        }
+
+        if !self.by_month.is_empty() && !self.by_month.contains(&date.month) {
+            return false;
+        }
+        if !self.by_month_day.is_empty() {
+            let from_end = date.day as i32 - date.days_in_month() as i32 - 1;
+            if !self.by_month_day.iter().any(|&day| day == date.day as i32 || day == from_end) {
+                return false;
+            }
+        }
+        if !self.by_day.is_empty() {
+            // Ordinals count within the month, or within the year for yearly
+            // rules without months
+            let (position, length) = if self.frequency == Frequency::Yearly && self.by_month.is_empty() {
+                (date.day_of_year(), date.days_in_year())
+            } else {
+                (date.day, date.days_in_month())
+            };
+            let ordinal = ((position - 1) / 7 + 1) as i32;
+            let ordinal_from_end = -(((length - position) / 7 + 1) as i32);
+            if !self.by_day.iter().any(|&(n, weekday)| {
+                weekday == date.weekday() && (n == 0 || n == ordinal || n == ordinal_from_end)
+            }) {
+                return false;
+            }
+        }
+
+        // Without any of the parts above, the rule repeats the start date
+        let unrestricted = self.by_day.is_empty() && self.by_month_day.is_empty();
+        match self.frequency {
+            Frequency::Daily => true,
+            Frequency::Weekly => !self.by_day.is_empty() || date.weekday() == start.weekday(),
+            Frequency::Monthly => !unrestricted || date.day == start.day,
+            Frequency::Yearly => {
+                (!self.by_month.is_empty() || date.month == start.month)
+                    && (!unrestricted || date.day == start.day)
+            },
+        }
+    }
+}
+
+/// An event (`VEVENT`).
+#[derive(Debug, Clone, PartialEq)]
+struct Event {
+    uid: Option<String>,
+    summary: String,
+    start: EventTime,
+    end: Option<EventTime>,
+    rule: Option<Rule>,
+    exception_dates: Vec<Date>,
+    /// If set, this event replaces an instance of a recurring event.
+    recurrence_id: Option<Date>,
+    cancelled: bool,
+}
+
+impl Event {
+    fn occurs_on(&self, date: Date) -> bool {
+        let start = self.start.date();
+        if date < start || self.exception_dates.contains(&date) {
+            return false;
+        }
+        let rule = match self.rule {
+            Some(ref rule) => rule,
+            None => {
+                // All-day events last until the (exclusive) end date
+                return match (self.start, self.end) {
+                    (EventTime::Date(_), Some(EventTime::Date(end))) => date == start || date < end,
+                    _ => date == start,
+                };
+            },
+        };
+        if rule.until.is_some_and(|until| date > until) {
+            return false;
+        }
+        let is_occurrence = |day: Date| day == start || rule.matches(start, day);
+        if !is_occurrence(date) {
+            return false;
+        }
+        match rule.count {
+            Some(count) => (start.days()..=date.days())
+                .filter(|&days| is_occurrence(Date::from_days(days)))
+                .count() <= count,
+            None => true,
+        }
+    }
+}
+
+/// Unfold the content lines of an iCalendar file.
+fn unfold(content: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for line in content.lines() {
+        let line = line.trim_end_matches('\r');
+        match (line.chars().next(), lines.last_mut()) {
+            (Some(' '), Some(last)) | (Some('\t'), Some(last)) => last.push_str(&line[1..]),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// Split a content line into name and value. Parameters (e.g. `TZID`) are
+/// dropped.
+fn split_property(line: &str) -> Option<(&str, &str)> {
+    // Parameter values may contain colons if quoted
+    let mut quoted = false;
+    let colon = line.char_indices().find(|&(_, c)| {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        c == ':' && !quoted
+    })?.0;
+    let name = line[..colon].split(';').next().unwrap_or("");
+    Some((name, &line[colon + 1..]))
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push(' '),
+            Some(c) => unescaped.push(c),
+            None => {},
+        }
+    }
+    unescaped
+}
+
+/// Parse all events in an iCalendar file. Invalid events are skipped.
+fn parse_events(content: &str) -> Vec<Event> {
+    let mut events = vec![];
+    // The event being parsed, and the depth of nested components (e.g. alarms)
+    let mut event: Option<Event> = None;
+    let mut valid = true;
+    let mut depth = 0;
+    for line in unfold(content) {
+        let (name, value) = match split_property(&line) {
+            Some(property) => property,
+            None => continue,
+        };
+        match (name, value) {
+            ("BEGIN", "VEVENT") if depth == 0 => {
+                event = Some(Event {
+                    uid: None,
+                    summary: String::new(),
+                    start: EventTime::Date(Date { year: 1970, month: 1, day: 1 }),
+                    end: None,
+                    rule: None,
+                    exception_dates: vec![],
+                    recurrence_id: None,
+                    cancelled: false,
+                });
+                valid = false;
+                continue;
+            },
+            ("END", "VEVENT") if depth == 0 => {
+                match event.take() {
+                    Some(event) if valid => events.push(event),
+                    Some(event) => warn!("Skipping calendar event without valid start: {:?}", event.summary),
+                    None => {},
+                }
+                continue;
+            },
+            ("BEGIN", _) if event.is_some() => depth += 1,
+            ("END", _) if event.is_some() => depth -= 1,
+            _ => {},
+        }
+        let event = match event {
+            Some(ref mut event) if depth == 0 => event,
+            _ => continue,
+        };
+        match name {
+            "UID" => event.uid = Some(value.to_string()),
+            "SUMMARY" => event.summary = unescape(value),
+            "DTSTART" => match parse_time(value) {
+                Some(start) => {
+                    event.start = start;
+                    valid = true;
+                },
+                None => valid = false,
+            },
+            "DTEND" => event.end = parse_time(value),
+            "RRULE" => {
+                event.rule = Rule::parse(value);
+                if event.rule.is_none() {
+                    warn!("Ignoring unsupported recurrence rule: {}", value);
+                }
+            },
+            "EXDATE" => event.exception_dates.extend(
+                value.split(',').filter_map(parse_time).map(|time| time.date())
+            ),
+            "RECURRENCE-ID" => event.recurrence_id = parse_time(value).map(|time| time.date()),
+            "STATUS" => event.cancelled = value == "CANCELLED",
+            _ => {},
+        }
+    }
+    events
+}
+
+/// Read all events from an `.ics` file or from all `.ics` files in a directory.
+fn load_events(path: &Path) -> io::Result<Vec<Event>> {
+    let mut files = vec![];
+    if path.is_dir() {
+        for entry in read_dir(path)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "ics") {
+                files.push(path);
+            }
+        }
+        files.sort();
+    } else {
+        files.push(path.to_path_buf());
+    }
+
+    let mut events = vec![];
+    for file in files {
+        let mut content = String::new();
+        File::open(file)?.read_to_string(&mut content)?;
+        events.extend(parse_events(&content));
+    }
+    Ok(events)
+}
+
+/// An entry of the agenda.
+#[derive(Debug, Clone, PartialEq)]
+struct AgendaItem {
+    /// Start and end time, or `None` for all-day events.
+    start: Option<(u32, u32)>,
+    end: Option<(u32, u32)>,
+    summary: String,
+}
+
+/// Return the events of a day: all-day events first, then by start time.
+fn agenda(events: &[Event], date: Date) -> Vec<AgendaItem> {
+    let is_replaced = |event: &Event| events.iter().any(|other| {
+        other.recurrence_id == Some(date) && other.uid.is_some() && other.uid == event.uid
+    });
+    let mut items: Vec<AgendaItem> = events.iter()
+        .filter(|event| !event.cancelled && event.occurs_on(date))
+        .filter(|event| event.recurrence_id.is_some() || event.rule.is_none() || !is_replaced(event))
+        .map(|event| {
+            let start = match event.start {
+                EventTime::Date(_) => None,
+                EventTime::DateTime(_, hour, minute) => Some((hour, minute)),
+            };
+            let end = match event.end {
+                // Only show end times on the same day
+                Some(EventTime::DateTime(end, hour, minute)) if end == event.start.date() => Some((hour, minute)),
+                _ => None,
+            };
+            AgendaItem { start, end: start.and(end), summary: event.summary.clone() }
+        })
+        .collect();
+    items.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.summary.cmp(&b.summary)));
+    items
+}
+
+/// Format the agenda as text, with a heading and one line per event.
+fn format_agenda(date: Date, items: &[AgendaItem]) -> String {
+    let mut lines = vec![format!(
+        "{}, {} {}",
+        WEEKDAY_NAMES[date.weekday() as usize], date.day, MONTH_NAMES[date.month as usize - 1],
+    )];
+    if items.is_empty() {
+        lines.push("No events today".to_string());
+    }
+    for item in items {
+        lines.push(match (item.start, item.end) {
+            (None, _) => format!("All day: {}", item.summary),
+            (Some((h, m)), None) => format!("{:02}:{:02} {}", h, m, item.summary),
+            (Some((h1, m1)), Some((h2, m2))) => format!("{:02}:{:02}-{:02}:{:02} {}", h1, m1, h2, m2, item.summary),
+        });
+    }
+    lines.join("\n")
+}
+
+/// Render the agenda of the specified day into polylines (in board
+/// coordinates) within the bounds.
+///
+/// The calendar files are read on every call, so that changes show up on the
+/// next print. If the agenda does not fit, the text is drawn smaller.
+pub fn render_agenda(config: &AgendaConfig, now: &Tm, bounds: &Bounds) -> Result<Vec<Polyline>, String> {
+    let events = load_events(Path::new(&config.path))
+        .map_err(|e| format!("Could not read calendar {}: {}", config.path, e))?;
+    let date = Date::from_tm(now);
+    let text = format_agenda(date, &agenda(&events, date));
+
+    let mut style = TextStyle { size: config.size, line_spacing: 1.5, align: Align::Left };
+    loop {
+        match text::render_text(&text, &style, bounds) {
+            Ok(polylines) => return Ok(polylines),
+            Err(_) if style.size * 0.9 >= MIN_SIZE => style.size *= 0.9,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Check that the calendar can be read. Used to fail early on startup.
+pub fn check_calendar(config: &AgendaConfig) -> io::Result<()> {
+    load_events(Path::new(&config.path)).map(|_| ())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use scaling::Range;
+
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> Date {
+        Date { year, month, day }
+    }
+
+    fn calendar(events: &str) -> String {
+        format!("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n{}END:VCALENDAR\r\n", events)
+    }
+
+    fn dates_in_october(event: &Event) -> Vec<u32> {
+        (1..=31).filter(|&day| event.occurs_on(date(2018, 10, day))).collect()
+    }
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(date(1970, 1, 1).days(), 0);
+        assert_eq!(date(2018, 10, 24).days(), 17828);
+        assert_eq!(Date::from_days(17828), date(2018, 10, 24));
+        assert_eq!(Date::from_days(-1), date(1969, 12, 31));
+        assert_eq!(date(2018, 10, 24).weekday(), 2);
+        assert_eq!(date(2020, 2, 1).days_in_month(), 29);
+        assert_eq!(date(2018, 12, 31).day_of_year(), 365);
+        assert_eq!(parse_time("20181024"), Some(EventTime::Date(date(2018, 10, 24))));
+        assert_eq!(parse_time("20181024T093000"), Some(EventTime::DateTime(date(2018, 10, 24), 9, 30)));
+        assert_eq!(parse_time("20181024T0930"), None);
+        assert_eq!(parse_time("20181032"), None);
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let content = calendar(concat!(
+            "BEGIN:VEVENT\r\n",
+            "UID:1\r\n",
+            "SUMMARY:Team meeting\\, weekly\r\n",
+            "DTSTART;TZID=Europe/Zurich:20181001T090000\r\n",
+            "DTEND;TZID=Europe/Zurich:20181001T093000\r\n",
+            "RRULE:FREQ=WEEKLY;BYDAY=MO,WE\r\n",
+            "EXDATE;TZID=Europe/Zurich:20181010T090000\r\n",
+            "BEGIN:VALARM\r\n",
+            "SUMMARY:Alarm\r\n",
+            "END:VALARM\r\n",
+            "END:VEVENT\r\n",
+            "BEGIN:VEVENT\r\n",
+            "SUMMARY:Broken\r\n",
+            "DTSTART:yesterday\r\n",
+            "END:VEVENT\r\n",
+            "BEGIN:VEVENT\r\n",
+            "SUMMARY:A very long sum\r\n",
+            " mary\r\n",
+            "DTSTART;VALUE=DATE:20181024\r\n",
+            "END:VEVENT\r\n",
+        ));
+        let events = parse_events(&content);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].summary, "Team meeting, weekly");
+        assert_eq!(events[0].end, Some(EventTime::DateTime(date(2018, 10, 1), 9, 30)));
+        assert_eq!(dates_in_october(&events[0]), vec![1, 3, 8, 15, 17, 22, 24, 29, 31]);
+        assert_eq!(events[1].summary, "A very long summary");
+        assert_eq!(events[1].start, EventTime::Date(date(2018, 10, 24)));
+    }
+
+    #[test]
+    fn test_recurrence() {
+        let event = |start: &str, rule: &str| parse_events(&calendar(&format!(
+            "BEGIN:VEVENT\r\nDTSTART:{}\r\nRRULE:{}\r\nEND:VEVENT\r\n", start, rule,
+        ))).remove(0);
+        assert_eq!(dates_in_october(&event("20181028", "FREQ=DAILY")), vec![28, 29, 30, 31]);
+        assert_eq!(dates_in_october(&event("20181001", "FREQ=DAILY;INTERVAL=10;COUNT=3")), vec![1, 11, 21]);
+        assert_eq!(dates_in_october(&event("20180905", "FREQ=WEEKLY;INTERVAL=2;UNTIL=20181020")), vec![3, 17]);
+        assert_eq!(dates_in_october(&event("20180103", "FREQ=MONTHLY")), vec![3]);
+        assert_eq!(dates_in_october(&event("20180103", "FREQ=MONTHLY;BYDAY=-1FR,1MO")), vec![1, 26]);
+        assert_eq!(dates_in_october(&event("20180103", "FREQ=MONTHLY;BYMONTHDAY=-1")), vec![31]);
+        assert_eq!(dates_in_october(&event("19901024", "FREQ=YEARLY")), vec![24]);
+        assert_eq!(dates_in_october(&event("20100101", "FREQ=YEARLY;BYMONTH=10;BYDAY=2TU")), vec![9]);
+    }
+
+    #[test]
+    fn test_parse_rule_non_ascii() {
+        assert!(Rule::parse("FREQ=WEEKLY;BYDAY=ÖM").is_none());
+        assert!(Rule::parse("FREQ=WEEKLY;BYDAY=1ÄMO,Ü").is_none());
+        assert!(Rule::parse("FREQ=WEEKLY;BYDAY=MO").is_some());
+    }
+
+    #[test]
+    fn test_agenda() {
+        let content = calendar(concat!(
+            "BEGIN:VEVENT\r\nUID:standup\r\nSUMMARY:Standup\r\n",
+            "DTSTART:20181001T091500\r\nDTEND:20181001T093000\r\nRRULE:FREQ=DAILY\r\nEND:VEVENT\r\n",
+            "BEGIN:VEVENT\r\nUID:standup\r\nSUMMARY:Late standup\r\nRECURRENCE-ID:20181025T091500\r\n",
+            "DTSTART:20181025T110000\r\nEND:VEVENT\r\n",
+            "BEGIN:VEVENT\r\nSUMMARY:Lunch\r\nDTSTART:20181024T120000\r\nEND:VEVENT\r\n",
+            "BEGIN:VEVENT\r\nSUMMARY:Off-site\r\nDTSTART;VALUE=DATE:20181024\r\n",
+            "DTEND;VALUE=DATE:20181026\r\nEND:VEVENT\r\n",
+            "BEGIN:VEVENT\r\nSUMMARY:Cancelled\r\nSTATUS:CANCELLED\r\nDTSTART:20181024T100000\r\nEND:VEVENT\r\n",
+        ));
+        let events = parse_events(&content);
+
+        let items = agenda(&events, date(2018, 10, 24));
+        assert_eq!(format_agenda(date(2018, 10, 24), &items), concat!(
+            "Wednesday, 24 October\n",
+            "All day: Off-site\n",
+            "09:15-09:30 Standup\n",
+            "12:00 Lunch",
+        ));
+        let items = agenda(&events, date(2018, 10, 25));
+        let summaries: Vec<_> = items.iter().map(|item| item.summary.as_str()).collect();
+        assert_eq!(summaries, vec!["Off-site", "Late standup"]);
+        assert_eq!(format_agenda(date(2018, 9, 30), &agenda(&events, date(2018, 9, 30))),
+                   "Sunday, 30 September\nNo events today");
+    }
+
+    #[test]
+    fn test_render_agenda() {
+        let path = ::std::env::temp_dir().join(format!("iboardbot-agenda-{}.ics", ::std::process::id()));
+        let events: String = (0..20)
+            .map(|i| format!("BEGIN:VEVENT\r\nSUMMARY:Event {}\r\nDTSTART:20181024T{:02}0000\r\nEND:VEVENT\r\n", i, i))
+            .collect();
+        ::std::fs::write(&path, calendar(&events)).unwrap();
+
+        let config = AgendaConfig { path: path.to_string_lossy().into_owned(), size: 8.0 };
+        let now = Tm { tm_year: 118, tm_mon: 9, tm_mday: 24, ..time::empty_tm() };
+        let bounds = Bounds { x: Range { min: 5.0, max: 353.0 }, y: Range { min: 5.0, max: 118.0 } };
+        let polylines = render_agenda(&config, &now, &bounds).unwrap();
+        assert!(polylines.iter().flatten().all(|p| p.y >= 5.0 && p.y <= 118.0));
+
+        ::std::fs::remove_file(&path).unwrap();
+        assert!(render_agenda(&config, &now, &bounds).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+//! Files with the raw blocks sent to the robot.
+//!
+//! A block file contains the blocks exactly as they are sent over serial, one
+//! after the other. Every command is three bytes long and every block starts
+//! with a block start command, so the file can be split into blocks again. (No
+//! move can be confused with a block start, as its coordinates would be off
+//! the board.)
+use std::io::{self, Read, Write};
+
+use robot::{Block, Command};
+
+/// Write the blocks to a block file.
+pub fn write_blocks<W: Write>(mut writer: W, blocks: &[Block]) -> io::Result<()> {
+    for block in blocks {
+        writer.write_all(block)?;
+    }
+    writer.flush()
+}
+
+/// Read the blocks from a block file.
+///
+/// The commands are not checked, so that even invalid blocks can be sent to
+/// the robot again.
+pub fn read_blocks<R: Read>(mut reader: R) -> io::Result<Vec<Block>> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+
+    let invalid = |details: &str| io::Error::new(io::ErrorKind::InvalidData, details);
+    if bytes.is_empty() {
+        return Err(invalid("Block file is empty"));
+    }
+    if bytes.len() % 3 != 0 {
+        return Err(invalid("Block file does not consist of whole commands"));
+    }
+
+    let block_start = Command::BlockStart.to_bytes();
+    if bytes[..3] != block_start {
+        return Err(invalid("Block file does not start with a block"));
+    }
+    let mut blocks: Vec<Block> = vec![];
+    for command in bytes.chunks(3) {
+        if command == block_start {
+            blocks.push(vec![]);
+        }
+        blocks.last_mut().expect("No block started").extend_from_slice(command);
+    }
+    Ok(blocks)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use svg2polylines::CoordinatePair;
+
+    use robot::Sketch;
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        // Enough points for several blocks
+        let polylines = vec![(0..1000)
+            .map(|i| CoordinatePair { x: f64::from(i % 300), y: f64::from(i % 100) })
+            .collect()];
+        let blocks = Sketch::new(&polylines).into_blocks(None);
+        assert!(blocks.len() > 1);
+
+        let mut file = vec![];
+        write_blocks(&mut file, &blocks).unwrap();
+        assert_eq!(file.len(), blocks.iter().map(|b| b.len()).sum::<usize>());
+        assert_eq!(read_blocks(&file[..]).unwrap(), blocks);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(read_blocks(&[][..]).is_err());
+        assert!(read_blocks(&[0xfa, 0x9f, 0xa1, 0xfa][..]).is_err());
+        assert!(read_blocks(&[0xfa, 0x30, 0x00][..]).is_err());
+
+        // Unknown commands are kept
+        let blocks = read_blocks(&[0xfa, 0x9f, 0xa1, 0xfa, 0xff, 0xff][..]).unwrap();
+        assert_eq!(blocks, vec![vec![0xfa, 0x9f, 0xa1, 0xfa, 0xff, 0xff]]);
+    }
+}
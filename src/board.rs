@@ -0,0 +1,171 @@
+//! Geometry of the board: The drawable area, the padding around drawings that
+//! are fitted to the board and the corner where the robot has its origin.
+//!
+//! Drawings use board coordinates (millimeters, origin in the top left
+//! corner, like SVG). The robot uses robot coordinates (0.1 mm, origin in the
+//! configured corner).
+use svg2polylines::CoordinatePair;
+
+use scaling::{Bounds, Range};
+
+/// Robot coordinates of moves must be below these limits, otherwise they
+/// can't be told apart from other commands.
+const MAX_ROBOT_X: f64 = 4000.0;
+const MAX_ROBOT_Y: f64 = 4096.0;
+
+/// The corner of the board where the robot has its origin, as seen from the
+/// front.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Origin {
+    #[default]
+    BottomLeft,
+    BottomRight,
+    TopLeft,
+    TopRight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Board {
+    /// Width of the drawable area in millimeters.
+    pub width: f64,
+    /// Height of the drawable area in millimeters.
+    pub height: f64,
+    /// Space in millimeters that is left free when a drawing is fitted to the
+    /// board.
+    pub padding: f64,
+    pub origin: Origin,
+}
+
+impl Default for Board {
+    /// The original iBoardBot.
+    fn default() -> Self {
+        Board {
+            width: 358.0,
+            height: 123.0,
+            padding: 5.0,
+            origin: Origin::BottomLeft,
+        }
+    }
+}
+
+impl Board {
+    /// Check whether the robot can draw on a board of this size.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.width > 0.0 && self.height > 0.0) {
+            return Err("Board width and height must be positive".to_string());
+        }
+        if self.width * 10.0 >= MAX_ROBOT_X || self.height * 10.0 >= MAX_ROBOT_Y {
+            return Err(format!(
+                "Board must be smaller than {} x {} mm",
+                MAX_ROBOT_X / 10.0, MAX_ROBOT_Y / 10.0,
+            ));
+        }
+        if !(self.padding >= 0.0 && self.padding * 2.0 < self.width.min(self.height)) {
+            return Err("Board padding must be less than half of the board size".to_string());
+        }
+        Ok(())
+    }
+
+    /// The drawable area in board coordinates.
+    pub fn bounds(&self) -> Bounds {
+        Bounds {
+            x: Range { min: 0.0, max: self.width },
+            y: Range { min: 0.0, max: self.height },
+        }
+    }
+
+    /// The area that drawings are fitted to: The drawable area without the
+    /// padding.
+    pub fn fit_bounds(&self) -> Bounds {
+        let mut bounds = self.bounds();
+        bounds.add_padding(self.padding);
+        bounds
+    }
+
+    /// The size of the drawable area in robot units.
+    pub fn robot_size(&self) -> (u16, u16) {
+        ((self.width * 10.0) as u16, (self.height * 10.0) as u16)
+    }
+
+    /// Convert a point in board coordinates to robot coordinates. Points
+    /// outside of the board are clamped to its edges (drawings are clipped
+    /// before, so this only catches rounding errors and erase margins).
+    pub fn robot_coords(&self, point: &CoordinatePair) -> (u16, u16) {
+        let x = point.x.clamp(0.0, self.width);
+        let y = point.y.clamp(0.0, self.height);
+        let (x, y) = match self.origin {
+            Origin::BottomLeft => (x, self.height - y),
+            Origin::BottomRight => (self.width - x, self.height - y),
+            Origin::TopLeft => (x, y),
+            Origin::TopRight => (self.width - x, y),
+        };
+        ((x * 10.0) as u16, (y * 10.0) as u16)
+    }
+
+    /// Mirror robot coordinates so that the origin is in the top left corner,
+    /// like in board coordinates and images. The unit stays 0.1 mm.
+    pub fn robot_to_image(&self, (x, y): (u16, u16)) -> (u16, u16) {
+        let (width, height) = self.robot_size();
+        match self.origin {
+            Origin::BottomLeft => (x, height.saturating_sub(y)),
+            Origin::BottomRight => (width.saturating_sub(x), height.saturating_sub(y)),
+            Origin::TopLeft => (x, y),
+            Origin::TopRight => (width.saturating_sub(x), y),
+        }
+    }
+
+    /// The position where the robot starts and ends a drawing, in board
+    /// coordinates.
+    pub fn home(&self) -> CoordinatePair {
+        let (x, y) = self.robot_to_image((0, 0));
+        CoordinatePair { x: f64::from(x) / 10.0, y: f64::from(y) / 10.0 }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use test_util::point;
+
+    use super::*;
+
+    #[test]
+    fn test_robot_coords() {
+        let board = Board::default();
+        assert_eq!(board.robot_coords(&point(10.0, 20.0)), (100, 1030));
+        assert_eq!(board.robot_coords(&point(-5.0, 200.0)), (0, 0));
+        assert_eq!(board.robot_coords(&point(400.0, -1.0)), (3580, 1230));
+
+        let board = Board { width: 200.0, height: 100.0, origin: Origin::TopRight, ..Board::default() };
+        assert_eq!(board.robot_coords(&point(10.0, 20.0)), (1900, 200));
+        assert_eq!(board.robot_coords(&point(250.0, 150.0)), (0, 1000));
+    }
+
+    #[test]
+    fn test_home() {
+        let corners = [
+            (Origin::BottomLeft, (0.0, 100.0)),
+            (Origin::BottomRight, (200.0, 100.0)),
+            (Origin::TopLeft, (0.0, 0.0)),
+            (Origin::TopRight, (200.0, 0.0)),
+        ];
+        for &(origin, (x, y)) in &corners {
+            let board = Board { width: 200.0, height: 100.0, padding: 5.0, origin };
+            assert_eq!(board.home(), point(x, y));
+            assert_eq!(board.robot_coords(&board.home()), (0, 0));
+            assert_eq!(board.robot_to_image(board.robot_coords(&point(30.0, 40.0))), (300, 400));
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Board::default().validate().is_ok());
+        assert!(Board { width: 399.9, height: 409.5, ..Board::default() }.validate().is_ok());
+        assert!(Board { width: 400.0, ..Board::default() }.validate().is_err());
+        assert!(Board { height: 0.0, ..Board::default() }.validate().is_err());
+        assert!(Board { padding: 61.5, ..Board::default() }.validate().is_err());
+        assert!(Board { padding: -1.0, ..Board::default() }.validate().is_err());
+    }
+}
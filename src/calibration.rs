@@ -0,0 +1,289 @@
+//! Calibration for robots that draw skewed, stretched or distorted.
+//!
+//! The calibration is applied to every point (in board coordinates) just
+//! before it is converted to robot coordinates. First an affine correction is
+//! applied around the center of the board, then the corrections measured on a
+//! grid are interpolated bilinearly and added.
+//!
+//! To measure the errors, print the calibration pattern: The grid lines
+//! through the measuring points, with a mark around every point.
+use std::f64::consts::PI;
+
+use svg2polylines::{CoordinatePair, Polyline};
+
+use board::Board;
+use scaling::Bounds;
+
+/// Radius of the marks around the measuring points, in millimeters.
+const MARK_RADIUS: f64 = 2.0;
+
+/// Number of segments of a mark.
+const MARK_SEGMENTS: usize = 24;
+
+/// Maximum number of columns and rows of the grid and of the calibration
+/// pattern.
+pub const MAX_GRID_SIZE: usize = 50;
+
+/// An affine correction around the center of the board.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Affine {
+    /// Shift to the right in millimeters.
+    pub offset_x: f64,
+    /// Shift downwards in millimeters.
+    pub offset_y: f64,
+    pub scale_x: f64,
+    pub scale_y: f64,
+    /// Clockwise rotation in degrees.
+    pub rotation: f64,
+    /// Angle in degrees by which vertical lines are tilted to the right at
+    /// the bottom.
+    pub skew: f64,
+}
+
+impl Default for Affine {
+    fn default() -> Self {
+        Affine {
+            offset_x: 0.0,
+            offset_y: 0.0,
+            scale_x: 1.0,
+            scale_y: 1.0,
+            rotation: 0.0,
+            skew: 0.0,
+        }
+    }
+}
+
+impl Affine {
+    /// Check that all values are finite, that the scale is not zero and that
+    /// the skew is less than 90 degrees.
+    fn validate(&self) -> Result<(), String> {
+        let values = [self.offset_x, self.offset_y, self.scale_x, self.scale_y, self.rotation, self.skew];
+        if !values.iter().all(|value| value.is_finite()) {
+            return Err("Calibration values must be finite".to_string());
+        }
+        if !self.scale_x.is_normal() || !self.scale_y.is_normal() {
+            return Err("Calibration scale must not be zero".to_string());
+        }
+        if self.skew.abs() >= 90.0 {
+            return Err("Calibration skew must be less than 90 degrees".to_string());
+        }
+        Ok(())
+    }
+
+    fn apply(&self, point: &CoordinatePair, center: &CoordinatePair) -> CoordinatePair {
+        let x = (point.x - center.x) * self.scale_x;
+        let y = (point.y - center.y) * self.scale_y;
+        let x = x + y * self.skew.to_radians().tan();
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        CoordinatePair {
+            x: center.x + self.offset_x + x * cos - y * sin,
+            y: center.y + self.offset_y + x * sin + y * cos,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Calibration {
+    pub affine: Affine,
+    /// Corrections `[x, y]` in millimeters, row by row from the top. They are
+    /// measured at evenly spaced points between the top left and the bottom
+    /// right corner of the board without the padding.
+    pub grid: Option<Vec<Vec<(f64, f64)>>>,
+}
+
+impl Calibration {
+    /// Check the affine correction, that the grid has at least two and at
+    /// most `MAX_GRID_SIZE` rows and columns, that all rows have the same
+    /// length and that all corrections are finite.
+    pub fn validate(&self) -> Result<(), String> {
+        self.affine.validate()?;
+        if let Some(ref grid) = self.grid {
+            let columns = grid.first().map(Vec::len).unwrap_or(0);
+            if grid.len() < 2 || columns < 2 {
+                return Err("Calibration grid needs at least 2 rows and 2 columns".to_string());
+            }
+            if grid.len() > MAX_GRID_SIZE || columns > MAX_GRID_SIZE {
+                return Err(format!("Calibration grid must not exceed {} rows and columns", MAX_GRID_SIZE));
+            }
+            if grid.iter().any(|row| row.len() != columns) {
+                return Err("All rows of the calibration grid must have the same length".to_string());
+            }
+            if grid.iter().flatten().any(|&(dx, dy)| !dx.is_finite() || !dy.is_finite()) {
+                return Err("Calibration values must be finite".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of columns and rows of the grid.
+    pub fn grid_size(&self) -> Option<(usize, usize)> {
+        self.grid.as_ref().map(|grid| (grid[0].len(), grid.len()))
+    }
+
+    /// Return the point (in board coordinates) the robot must be sent to, so
+    /// that it draws at `point`.
+    pub fn apply(&self, point: &CoordinatePair, board: &Board) -> CoordinatePair {
+        let center = CoordinatePair { x: board.width / 2.0, y: board.height / 2.0 };
+        let mut corrected = self.affine.apply(point, &center);
+        if let Some(ref grid) = self.grid {
+            let (dx, dy) = interpolate(grid, &board.fit_bounds(), point);
+            corrected.x += dx;
+            corrected.y += dy;
+        }
+        corrected
+    }
+
+    /// Apply the calibration to all points of the polylines.
+    pub fn apply_polylines(&self, polylines: &[Polyline], board: &Board) -> Vec<Polyline> {
+        polylines.iter()
+            .map(|polyline| polyline.iter().map(|point| self.apply(point, board)).collect())
+            .collect()
+    }
+}
+
+/// Interpolate the grid (spanning `area`) bilinearly at `point`. Outside of
+/// the area, the corrections at its edge are used.
+fn interpolate(grid: &[Vec<(f64, f64)>], area: &Bounds, point: &CoordinatePair) -> (f64, f64) {
+    let (columns, rows) = (grid[0].len(), grid.len());
+    let position = |value: f64, min: f64, spread: f64, count: usize| {
+        let last = (count - 1) as f64;
+        let position = ((value - min) / spread * last).clamp(0.0, last);
+        // The last cell also contains the points on its far edge
+        let cell = (position.floor() as usize).min(count - 2);
+        (cell, position - cell as f64)
+    };
+    let (column, tx) = position(point.x, area.x.min, area.x.spread(), columns);
+    let (row, ty) = position(point.y, area.y.min, area.y.spread(), rows);
+
+    let lerp = |a: (f64, f64), b: (f64, f64), t: f64| (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t);
+    let top = lerp(grid[row][column], grid[row][column + 1], tx);
+    let bottom = lerp(grid[row + 1][column], grid[row + 1][column + 1], tx);
+    lerp(top, bottom, ty)
+}
+
+/// Return a circle around the center.
+fn circle(center: &CoordinatePair, radius: f64) -> Polyline {
+    (0..=MARK_SEGMENTS)
+        .map(|i| {
+            let angle = 2.0 * PI * i as f64 / MARK_SEGMENTS as f64;
+            CoordinatePair { x: center.x + radius * angle.cos(), y: center.y + radius * angle.sin() }
+        })
+        .collect()
+}
+
+/// Return the calibration pattern for a grid with at least two columns and
+/// rows: Lines through the measuring points with a circle around every point.
+/// A second circle around the top left point shows the orientation.
+pub fn pattern(board: &Board, columns: usize, rows: usize) -> Vec<Polyline> {
+    let area = board.fit_bounds();
+    let x = |column: usize| area.x.min + area.x.spread() * column as f64 / (columns - 1) as f64;
+    let y = |row: usize| area.y.min + area.y.spread() * row as f64 / (rows - 1) as f64;
+
+    let mut polylines = vec![];
+    for row in 0..rows {
+        polylines.push(vec![
+            CoordinatePair { x: area.x.min, y: y(row) },
+            CoordinatePair { x: area.x.max, y: y(row) },
+        ]);
+    }
+    for column in 0..columns {
+        polylines.push(vec![
+            CoordinatePair { x: x(column), y: area.y.min },
+            CoordinatePair { x: x(column), y: area.y.max },
+        ]);
+    }
+    for row in 0..rows {
+        for column in 0..columns {
+            polylines.push(circle(&CoordinatePair { x: x(column), y: y(row) }, MARK_RADIUS));
+        }
+    }
+    polylines.push(circle(&CoordinatePair { x: x(0), y: y(0) }, MARK_RADIUS * 2.0));
+    polylines
+}
+
+
+#[cfg(test)]
+mod tests {
+    use test_util::{assert_close, point};
+
+    use super::*;
+
+    /// A 200 x 100 mm board, the grid spans (10, 10) to (190, 90).
+    fn board() -> Board {
+        Board { width: 200.0, height: 100.0, padding: 10.0, ..Board::default() }
+    }
+
+    #[test]
+    fn test_identity() {
+        let calibration = Calibration::default();
+        assert_eq!(calibration.apply(&point(12.5, 34.5), &board()), point(12.5, 34.5));
+    }
+
+    #[test]
+    fn test_affine() {
+        let affine = |affine: Affine| Calibration { affine, grid: None };
+
+        // Around the center of the board
+        let scaled = affine(Affine { scale_x: 1.1, scale_y: 0.5, ..Affine::default() });
+        assert_close(scaled.apply(&point(200.0, 0.0), &board()), point(210.0, 25.0));
+
+        let rotated = affine(Affine { rotation: 90.0, offset_x: 1.0, ..Affine::default() });
+        assert_close(rotated.apply(&point(110.0, 50.0), &board()), point(101.0, 60.0));
+
+        let skewed = affine(Affine { skew: 45.0, ..Affine::default() });
+        assert_close(skewed.apply(&point(100.0, 60.0), &board()), point(110.0, 60.0));
+        assert_close(skewed.apply(&point(100.0, 40.0), &board()), point(90.0, 40.0));
+    }
+
+    #[test]
+    fn test_grid() {
+        let calibration = Calibration {
+            affine: Affine::default(),
+            grid: Some(vec![
+                vec![(0.0, 0.0), (1.0, 0.0), (2.0, 0.0)],
+                vec![(0.0, 2.0), (1.0, 2.0), (2.0, 4.0)],
+            ]),
+        };
+        assert!(calibration.validate().is_ok());
+        assert_eq!(calibration.grid_size(), Some((3, 2)));
+
+        // At the measuring points
+        assert_close(calibration.apply(&point(10.0, 10.0), &board()), point(10.0, 10.0));
+        assert_close(calibration.apply(&point(190.0, 90.0), &board()), point(192.0, 94.0));
+        // In between
+        assert_close(calibration.apply(&point(145.0, 50.0), &board()), point(146.5, 51.5));
+        // Outside of the grid
+        assert_close(calibration.apply(&point(0.0, 0.0), &board()), point(0.0, 0.0));
+        assert_close(calibration.apply(&point(200.0, 100.0), &board()), point(202.0, 104.0));
+    }
+
+    #[test]
+    fn test_validate() {
+        let grid = |grid: Vec<Vec<(f64, f64)>>| Calibration { affine: Affine::default(), grid: Some(grid) };
+        assert!(Calibration::default().validate().is_ok());
+        assert!(grid(vec![vec![(0.0, 0.0), (0.0, 0.0)]]).validate().is_err());
+        assert!(grid(vec![vec![(0.0, 0.0)], vec![(0.0, 0.0)]]).validate().is_err());
+        assert!(grid(vec![vec![(0.0, 0.0); 2], vec![(0.0, 0.0); 3]]).validate().is_err());
+        assert!(grid(vec![vec![(0.0, 0.0); 2]; MAX_GRID_SIZE + 1]).validate().is_err());
+        assert!(grid(vec![vec![(0.0, f64::NAN); 2]; 2]).validate().is_err());
+
+        let affine = |affine: Affine| Calibration { affine, grid: None };
+        assert!(affine(Affine { scale_x: 0.0, ..Affine::default() }).validate().is_err());
+        assert!(affine(Affine { offset_y: f64::INFINITY, ..Affine::default() }).validate().is_err());
+        assert!(affine(Affine { skew: 90.0, ..Affine::default() }).validate().is_err());
+        assert!(affine(Affine { skew: 10.0, rotation: -3.0, ..Affine::default() }).validate().is_ok());
+    }
+
+    #[test]
+    fn test_pattern() {
+        let polylines = pattern(&board(), 3, 2);
+        // Grid lines, marks and the orientation mark
+        assert_eq!(polylines.len(), 2 + 3 + 6 + 1);
+        assert_eq!(polylines[0], vec![point(10.0, 10.0), point(190.0, 10.0)]);
+        assert_eq!(polylines[3], vec![point(100.0, 10.0), point(100.0, 90.0)]);
+        assert_close(polylines[10][0], point(192.0, 90.0));
+        assert_close(polylines[11][0], point(14.0, 10.0));
+    }
+}
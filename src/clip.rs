@@ -0,0 +1,176 @@
+//! Clipping of polylines to the board.
+//!
+//! Segments that cross the edge of the board are cut where they cross it
+//! (Liang–Barsky), and polylines that leave the board are split, so that the
+//! robot lifts the pen instead of drawing along the edge.
+use svg2polylines::{CoordinatePair, Polyline};
+
+use scaling::Bounds;
+
+/// How much of a drawing was clipped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ClipStats {
+    /// Length of all polylines in millimeters.
+    pub total_length: f64,
+    /// Length of the parts outside of the board in millimeters.
+    pub clipped_length: f64,
+    /// Number of polylines that are partially or entirely outside of the
+    /// board.
+    pub clipped_polylines: usize,
+}
+
+/// Clip the segment from `a` to `b` to the bounds. Return the parameters
+/// `(t0, t1)` of the visible part, or `None` if the segment is outside.
+fn clip_segment(a: &CoordinatePair, b: &CoordinatePair, bounds: &Bounds) -> Option<(f64, f64)> {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let edges = [
+        (-dx, a.x - bounds.x.min),
+        (dx, bounds.x.max - a.x),
+        (-dy, a.y - bounds.y.min),
+        (dy, bounds.y.max - a.y),
+    ];
+    let (mut t0, mut t1) = (0.0_f64, 1.0_f64);
+    for &(p, q) in &edges {
+        if p == 0.0 {
+            // Parallel to the edge
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            // Entering
+            t0 = t0.max(q / p);
+        } else {
+            // Leaving
+            t1 = t1.min(q / p);
+        }
+    }
+    if t0 > t1 { None } else { Some((t0, t1)) }
+}
+
+fn lerp(a: &CoordinatePair, b: &CoordinatePair, t: f64) -> CoordinatePair {
+    CoordinatePair { x: a.x + (b.x - a.x) * t, y: a.y + (b.y - a.y) * t }
+}
+
+fn contains(bounds: &Bounds, point: &CoordinatePair) -> bool {
+    point.x >= bounds.x.min && point.x <= bounds.x.max && point.y >= bounds.y.min && point.y <= bounds.y.max
+}
+
+/// Clip the polylines to the bounds. A polyline that leaves and reenters the
+/// bounds is split into several polylines.
+pub fn clip_polylines(polylines: &[Polyline], bounds: &Bounds) -> (Vec<Polyline>, ClipStats) {
+    let mut clipped = vec![];
+    let mut stats = ClipStats::default();
+    for polyline in polylines {
+        // Single points are kept or dropped as a whole
+        if polyline.len() < 2 {
+            if polyline.iter().all(|point| contains(bounds, point)) {
+                clipped.push(polyline.clone());
+            } else {
+                stats.clipped_polylines += 1;
+            }
+            continue;
+        }
+
+        let mut current: Polyline = vec![];
+        let mut length = 0.0;
+        let mut visible_length = 0.0;
+        for segment in polyline.windows(2) {
+            let (a, b) = (&segment[0], &segment[1]);
+            let segment_length = (b.x - a.x).hypot(b.y - a.y);
+            length += segment_length;
+            let (t0, t1) = match clip_segment(a, b, bounds) {
+                Some(range) => range,
+                None => {
+                    if current.len() > 1 {
+                        clipped.push(current);
+                    }
+                    current = vec![];
+                    continue;
+                },
+            };
+            visible_length += segment_length * (t1 - t0);
+
+            // Use the original points where possible, so that polylines
+            // inside of the bounds stay unchanged
+            let start = if t0 > 0.0 { lerp(a, b, t0) } else { *a };
+            let end = if t1 < 1.0 { lerp(a, b, t1) } else { *b };
+            if t0 > 0.0 || current.is_empty() {
+                if current.len() > 1 {
+                    clipped.push(current);
+                }
+                current = vec![start];
+            }
+            current.push(end);
+            if t1 < 1.0 {
+                clipped.push(current);
+                current = vec![];
+            }
+        }
+        if current.len() > 1 {
+            clipped.push(current);
+        }
+
+        stats.total_length += length;
+        stats.clipped_length += length - visible_length;
+        if visible_length < length {
+            stats.clipped_polylines += 1;
+        }
+    }
+    (clipped, stats)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use scaling::Range;
+    use test_util::point;
+
+    use super::*;
+
+    fn bounds() -> Bounds {
+        Bounds {
+            x: Range { min: 0.0, max: 100.0 },
+            y: Range { min: 0.0, max: 50.0 },
+        }
+    }
+
+    #[test]
+    fn test_inside() {
+        let polylines = vec![vec![point(10.0, 10.0), point(40.0, 50.0), point(0.0, 20.0)]];
+        let (clipped, stats) = clip_polylines(&polylines, &bounds());
+        assert_eq!(clipped, polylines);
+        assert_eq!(stats, ClipStats { total_length: 100.0, clipped_length: 0.0, clipped_polylines: 0 });
+    }
+
+    #[test]
+    fn test_split() {
+        // Leaves the board to the right and comes back
+        let polylines = vec![vec![
+            point(80.0, 10.0),
+            point(120.0, 10.0),
+            point(120.0, 30.0),
+            point(80.0, 30.0),
+        ]];
+        let (clipped, stats) = clip_polylines(&polylines, &bounds());
+        assert_eq!(clipped, vec![
+            vec![point(80.0, 10.0), point(100.0, 10.0)],
+            vec![point(100.0, 30.0), point(80.0, 30.0)],
+        ]);
+        assert_eq!(stats, ClipStats { total_length: 100.0, clipped_length: 60.0, clipped_polylines: 1 });
+    }
+
+    #[test]
+    fn test_crossing() {
+        // Both ends outside, crossing a corner region
+        let polylines = vec![
+            vec![point(-10.0, 25.0), point(110.0, 25.0)],
+            vec![point(-10.0, -10.0), point(-10.0, 60.0)],
+            vec![point(120.0, 20.0)],
+        ];
+        let (clipped, stats) = clip_polylines(&polylines, &bounds());
+        assert_eq!(clipped, vec![vec![point(0.0, 25.0), point(100.0, 25.0)]]);
+        assert_eq!(stats.clipped_polylines, 3);
+        assert!((stats.clipped_length - 90.0).abs() < 1e-9);
+        assert!((stats.total_length - 190.0).abs() < 1e-9);
+    }
+}
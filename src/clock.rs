@@ -0,0 +1,199 @@
+//! Code for drawing the current time, so the board can act as a wall clock.
+use std::f64::consts::PI;
+
+use svg2polylines::{CoordinatePair, Polyline};
+use time::Tm;
+
+use hershey::CAP_HEIGHT;
+use scaling::{Bounds, Range};
+use text::{self, Align, TextStyle};
+
+/// Number of segments used to approximate the circle of the analog face.
+const CIRCLE_SEGMENTS: usize = 72;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClockFace {
+    /// The time as text, e.g. `13:05`.
+    Digital,
+    /// A clock face with hands.
+    Analog,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClockConfig {
+    pub face: ClockFace,
+    /// Use the 12 hour format (only for the digital face).
+    pub twelve_hour: bool,
+    /// How often the time is drawn, in minutes.
+    pub interval_minutes: u64,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        ClockConfig {
+            face: ClockFace::Digital,
+            twelve_hour: false,
+            interval_minutes: 5,
+        }
+    }
+}
+
+impl ClockConfig {
+    /// Check that the time is drawn at a sensible interval.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interval_minutes == 0 {
+            return Err("Clock interval must be at least one minute".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Format the time for the digital face.
+fn format_time(time: &Tm, twelve_hour: bool) -> String {
+    if twelve_hour {
+        let hour = match time.tm_hour % 12 {
+            0 => 12,
+            hour => hour,
+        };
+        let suffix = if time.tm_hour < 12 { "AM" } else { "PM" };
+        format!("{}:{:02} {}", hour, time.tm_min, suffix)
+    } else {
+        format!("{:02}:{:02}", time.tm_hour, time.tm_min)
+    }
+}
+
+/// Render the time as text, as large as possible and centered in the bounds.
+fn render_digital(time: &Tm, twelve_hour: bool, bounds: &Bounds) -> Result<Vec<Polyline>, String> {
+    let text = format_time(time, twelve_hour);
+
+    // Leave some room, so rounding never causes a line break
+    let width = f64::from(text::text_width(&text));
+    let size = 0.9 * (bounds.x.spread() * CAP_HEIGHT / width).min(bounds.y.spread());
+    let top = bounds.y.min + (bounds.y.spread() - size) / 2.0;
+
+    // The text consists of capitals and digits only, so the room reserved for
+    // descenders below the baseline may exceed the bounds.
+    let text_bounds = Bounds {
+        x: Range { min: bounds.x.min, max: bounds.x.max },
+        y: Range { min: top, max: top + 2.0 * size },
+    };
+    let style = TextStyle { size, line_spacing: 1.0, align: Align::Center };
+    text::render_text(&text, &style, &text_bounds)
+}
+
+/// Return a point on a circle. The angle is measured clockwise from 12
+/// o'clock, in radians.
+fn point_on_circle(center: CoordinatePair, radius: f64, angle: f64) -> CoordinatePair {
+    CoordinatePair {
+        x: center.x + radius * angle.sin(),
+        y: center.y - radius * angle.cos(),
+    }
+}
+
+/// Render an analog clock face, centered in the bounds.
+fn render_analog(time: &Tm, bounds: &Bounds) -> Vec<Polyline> {
+    let center = CoordinatePair {
+        x: bounds.x.min + bounds.x.spread() / 2.0,
+        y: bounds.y.min + bounds.y.spread() / 2.0,
+    };
+    let radius = bounds.x.spread().min(bounds.y.spread()) / 2.0;
+
+    // Circle
+    let mut polylines = vec![(0..=CIRCLE_SEGMENTS)
+        .map(|i| point_on_circle(center, radius, i as f64 / CIRCLE_SEGMENTS as f64 * 2.0 * PI))
+        .collect()];
+
+    // Ticks, longer ones every three hours
+    for hour in 0..12 {
+        let angle = f64::from(hour) / 12.0 * 2.0 * PI;
+        let length = if hour % 3 == 0 { 0.2 } else { 0.1 };
+        polylines.push(vec![
+            point_on_circle(center, radius * (1.0 - length), angle),
+            point_on_circle(center, radius, angle),
+        ]);
+    }
+
+    // Hands
+    let minutes = f64::from(time.tm_min);
+    let hours = f64::from(time.tm_hour % 12) + minutes / 60.0;
+    polylines.push(vec![center, point_on_circle(center, radius * 0.5, hours / 12.0 * 2.0 * PI)]);
+    polylines.push(vec![center, point_on_circle(center, radius * 0.8, minutes / 60.0 * 2.0 * PI)]);
+    polylines
+}
+
+/// Render the time into polylines (in board coordinates) within the bounds.
+pub fn render_clock(time: &Tm, config: &ClockConfig, bounds: &Bounds) -> Result<Vec<Polyline>, String> {
+    match config.face {
+        ClockFace::Digital => render_digital(time, config.twelve_hour, bounds),
+        ClockFace::Analog => Ok(render_analog(time, bounds)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use time;
+
+    use super::*;
+
+    fn tm(hour: i32, min: i32) -> Tm {
+        Tm { tm_hour: hour, tm_min: min, ..time::empty_tm() }
+    }
+
+    fn bounds() -> Bounds {
+        Bounds {
+            x: Range { min: 5.0, max: 353.0 },
+            y: Range { min: 5.0, max: 118.0 },
+        }
+    }
+
+    fn assert_within(polylines: &[Polyline], bounds: &Bounds) {
+        for point in polylines.iter().flatten() {
+            assert!(point.x >= bounds.x.min - 1e-9 && point.x <= bounds.x.max + 1e-9, "{:?}", point);
+            assert!(point.y >= bounds.y.min - 1e-9 && point.y <= bounds.y.max + 1e-9, "{:?}", point);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(ClockConfig::default().validate().is_ok());
+        let config = ClockConfig { interval_minutes: 0, ..ClockConfig::default() };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(&tm(13, 5), false), "13:05");
+        assert_eq!(format_time(&tm(13, 5), true), "1:05 PM");
+        assert_eq!(format_time(&tm(0, 0), true), "12:00 AM");
+        assert_eq!(format_time(&tm(12, 30), true), "12:30 PM");
+    }
+
+    #[test]
+    fn test_digital() {
+        for &twelve_hour in &[false, true] {
+            let config = ClockConfig { twelve_hour, ..Default::default() };
+            let polylines = render_clock(&tm(23, 59), &config, &bounds()).unwrap();
+            assert!(!polylines.is_empty());
+            assert_within(&polylines, &bounds());
+        }
+    }
+
+    #[test]
+    fn test_analog() {
+        let config = ClockConfig { face: ClockFace::Analog, ..Default::default() };
+        let polylines = render_clock(&tm(15, 0), &config, &bounds()).unwrap();
+        assert_within(&polylines, &bounds());
+
+        // Circle, 12 ticks and 2 hands
+        assert_eq!(polylines.len(), 15);
+        let hour_hand = polylines[13][1];
+        let minute_hand = polylines[14][1];
+        assert!((hour_hand.x - (179.0 + 28.25)).abs() < 1e-9);
+        assert!((hour_hand.y - 61.5).abs() < 1e-9);
+        assert!((minute_hand.x - 179.0).abs() < 1e-9);
+        assert!((minute_hand.y - (61.5 - 45.2)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,247 @@
+//! Decoder for the byte stream sent to the robot, the inverse of
+//! `Command::to_bytes`.
+//!
+//! Besides unknown commands, the decoder reports commands that are out of
+//! place: Every block starts with a block start and a block number, and the
+//! block numbers are consecutive (or start at 1 again for the next drawing).
+use std::fmt;
+
+use robot::Command;
+
+/// A malformed sequence in the byte stream.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// Three bytes that don't form a known command.
+    UnknownCommand([u8; 3]),
+    /// The stream ends within a command.
+    Truncated(Vec<u8>),
+    /// A command before the first block start.
+    OutsideBlock(Command),
+    /// A block start that isn't followed by a block number.
+    MissingBlockNumber,
+    /// A block number that doesn't directly follow a block start.
+    MisplacedBlockNumber(u16),
+    /// A block number that doesn't follow the previous one.
+    UnexpectedBlockNumber { expected: u16, actual: u16 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnknownCommand(bytes) => write!(f, "Unknown command {:02x?}", bytes),
+            DecodeError::Truncated(bytes) => write!(f, "Stream ends within a command: {:02x?}", bytes),
+            DecodeError::OutsideBlock(command) => write!(f, "{:?} before the first block", command),
+            DecodeError::MissingBlockNumber => write!(f, "Block start without a block number"),
+            DecodeError::MisplacedBlockNumber(number) => {
+                write!(f, "Block number {} without a block start", number)
+            },
+            DecodeError::UnexpectedBlockNumber { expected, actual } => {
+                write!(f, "Block number {} instead of {}", actual, expected)
+            },
+        }
+    }
+}
+
+/// Decode a byte stream into commands.
+///
+/// Every command (or malformed sequence) is returned together with its offset
+/// in the stream. Decoding continues after malformed sequences.
+pub fn decode(bytes: &[u8]) -> Vec<(usize, Result<Command, DecodeError>)> {
+    let mut decoded = vec![];
+    let mut in_block = false;
+    let mut after_block_start = false;
+    let mut last_number: Option<u16> = None;
+
+    let chunks = bytes.chunks_exact(3);
+    let remainder = chunks.remainder();
+    for (i, chunk) in chunks.enumerate() {
+        let offset = i * 3;
+        let bytes = [chunk[0], chunk[1], chunk[2]];
+        let command = match Command::from_bytes(bytes) {
+            Some(command) => command,
+            None => {
+                decoded.push((offset, Err(DecodeError::UnknownCommand(bytes))));
+                after_block_start = false;
+                continue;
+            },
+        };
+
+        let is_block_number = matches!(command, Command::BlockNumber(_));
+        if after_block_start && !is_block_number {
+            decoded.push((offset, Err(DecodeError::MissingBlockNumber)));
+        }
+        let result = match command {
+            Command::BlockStart => {
+                in_block = true;
+                Ok(command)
+            },
+            Command::BlockNumber(number) if after_block_start => {
+                let previous = last_number.replace(number);
+                match previous {
+                    Some(previous) if number != previous + 1 && number != 1 => {
+                        Err(DecodeError::UnexpectedBlockNumber { expected: previous + 1, actual: number })
+                    },
+                    _ => Ok(command),
+                }
+            },
+            Command::BlockNumber(number) => Err(DecodeError::MisplacedBlockNumber(number)),
+            _ if !in_block => Err(DecodeError::OutsideBlock(command)),
+            _ => Ok(command),
+        };
+        decoded.push((offset, result));
+        after_block_start = command == Command::BlockStart;
+    }
+
+    if after_block_start {
+        decoded.push((bytes.len() - remainder.len(), Err(DecodeError::MissingBlockNumber)));
+    }
+    if !remainder.is_empty() {
+        decoded.push((bytes.len() - remainder.len(), Err(DecodeError::Truncated(remainder.to_vec()))));
+    }
+    decoded
+}
+
+
+#[cfg(test)]
+mod tests {
+    use svg2polylines::{CoordinatePair, Polyline};
+
+    use board::{Board, Origin};
+    use clip;
+    use erase::{EraseStrategy, SweepPattern};
+    use raster::XorShift;
+    use render::{self, Tool};
+    use robot::Sketch;
+    use super::*;
+
+    /// Return a random number below the maximum.
+    fn below(random: &mut XorShift, max: u16) -> u16 {
+        (random.next_f64() * f64::from(max)) as u16
+    }
+
+    fn random_command(random: &mut XorShift) -> Command {
+        match below(random, 9) {
+            0 => Command::BlockStart,
+            1 => Command::BlockNumber(below(random, 4000)),
+            2 => Command::StartDrawing,
+            3 => Command::StopDrawing,
+            4 => Command::PenLift,
+            5 => Command::PenDown,
+            6 => Command::EnableEraser,
+            // Larger x coordinates can't be told apart from other commands
+            7 => Command::Move(below(random, 4000), below(random, 4096)),
+            _ => Command::Wait(below(random, 31) as u8),
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        let bytes = [
+            0xfa, 0x9f, 0xa1, // Block start
+            0xfa, 0x90, 0x01, // Block number 1
+            0xfa, 0x1f, 0xa1, // Start drawing
+            0x01, 0x00, 0x20, // Move
+            0xfa, 0x60, 0x05, // Wait 5 seconds
+            0xfa, 0x20, 0x00, // Stop drawing
+        ];
+        let commands: Vec<_> = decode(&bytes).into_iter().map(|(_, c)| c.unwrap()).collect();
+        assert_eq!(commands, vec![
+            Command::BlockStart,
+            Command::BlockNumber(1),
+            Command::StartDrawing,
+            Command::Move(16, 32),
+            Command::Wait(5),
+            Command::StopDrawing,
+        ]);
+    }
+
+    #[test]
+    fn test_malformed() {
+        let bytes = [
+            0xfa, 0x30, 0x00, // Pen lift outside of a block
+            0xfa, 0x9f, 0xa1, // Block start...
+            0xfa, 0x40, 0x00, // ...without number
+            0xfa, 0x90, 0x02, // Block number without block start
+            0xfa, 0x61, 0x00, // Unknown
+            0xfa, 0x9f, 0xa1, // Block 3
+            0xfa, 0x90, 0x03,
+            0xfa, 0x9f, 0xa1, // Block 5
+            0xfa, 0x90, 0x05,
+            0xfa, 0x9f, 0xa1, // Block 1 of the next drawing
+            0xfa, 0x90, 0x01,
+            0xfa, 0x20, // Truncated
+        ];
+        let errors: Vec<_> = decode(&bytes).into_iter()
+            .filter_map(|(offset, result)| result.err().map(|e| (offset, e)))
+            .collect();
+        assert_eq!(errors, vec![
+            (0, DecodeError::OutsideBlock(Command::PenLift)),
+            (6, DecodeError::MissingBlockNumber),
+            (9, DecodeError::MisplacedBlockNumber(2)),
+            (12, DecodeError::UnknownCommand([0xfa, 0x61, 0x00])),
+            (24, DecodeError::UnexpectedBlockNumber { expected: 4, actual: 5 }),
+            (33, DecodeError::Truncated(vec![0xfa, 0x20])),
+        ]);
+    }
+
+    #[test]
+    fn test_round_trip_commands() {
+        let mut random = XorShift(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..10_000 {
+            let command = random_command(&mut random);
+            assert_eq!(Command::from_bytes(command.to_bytes()), Some(command));
+        }
+    }
+
+    #[test]
+    fn test_round_trip_sketch() {
+        let mut random = XorShift(0x2545_f491_4f6c_dd1d);
+        let origins = [Origin::BottomLeft, Origin::BottomRight, Origin::TopLeft, Origin::TopRight];
+        for round in 0..50 {
+            let board = Board { origin: origins[round % 4], ..Board::default() };
+
+            // Random polylines, partly outside of the board (clipped by the
+            // sketch)
+            let polylines: Vec<Polyline> = (0..below(&mut random, 40))
+                .map(|_| (0..below(&mut random, 30) + 1)
+                    .map(|_| CoordinatePair {
+                        x: random.next_f64() * (board.width + 20.0) - 10.0,
+                        y: random.next_f64() * (board.height + 20.0) - 10.0,
+                    })
+                    .collect())
+                .collect();
+            let erase = match round % 3 {
+                0 => None,
+                1 => Some(EraseStrategy::Full),
+                _ => Some(EraseStrategy::BoundingBox { margin: 5.0 }),
+            };
+            let blocks = Sketch::new(&polylines)
+                .with_erase_pattern(SweepPattern::default())
+                .with_board(board)
+                .into_blocks(erase);
+
+            let decoded = decode(&blocks.concat());
+            let commands: Vec<Command> = decoded.into_iter()
+                .map(|(offset, result)| result.unwrap_or_else(|e| panic!("{} at {}", e, offset)))
+                .collect();
+            let numbers: Vec<u16> = commands.iter()
+                .filter_map(|command| match *command {
+                    Command::BlockNumber(number) => Some(number),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(numbers, (1..=blocks.len() as u16).collect::<Vec<_>>());
+
+            let (clipped, _) = clip::clip_polylines(&polylines, &board.bounds());
+            let expected: Vec<Vec<(u16, u16)>> = clipped.iter()
+                .filter(|polyline| polyline.len() > 1)
+                .map(|polyline| polyline.iter().map(|point| board.robot_coords(point)).collect())
+                .collect();
+            let strokes: Vec<Vec<(u16, u16)>> = render::strokes(&commands).into_iter()
+                .filter(|stroke| stroke.tool == Tool::Pen)
+                .map(|stroke| stroke.points)
+                .collect();
+            assert_eq!(strokes, expected);
+        }
+    }
+}
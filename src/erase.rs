@@ -0,0 +1,355 @@
+//! Code for erasing the board before a drawing.
+use std::cmp;
+
+use svg2polylines::{CoordinatePair, Polyline};
+
+use board::Board;
+use robot::Command;
+
+fn default_margin() -> f64 {
+    5.0
+}
+
+/// Which part of the board is erased before drawing.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EraseStrategy {
+    /// Erase the entire board.
+    #[default]
+    Full,
+    /// Erase only the bounding box of the drawing, extended by a margin in
+    /// millimeters.
+    BoundingBox {
+        #[serde(default = "default_margin")]
+        margin: f64,
+    },
+}
+
+impl EraseStrategy {
+    /// Return the area to erase before drawing the polylines, or `None` if
+    /// nothing needs to be erased.
+    pub fn area(&self, polylines: &[Polyline], board: &Board) -> Option<EraseArea> {
+        match *self {
+            EraseStrategy::Full => Some(EraseArea::full(board)),
+            EraseStrategy::BoundingBox { margin } => EraseArea::around(polylines, margin, board),
+        }
+    }
+}
+
+/// A rectangular area on the board, in robot coordinates (0.1 mm, origin in
+/// the corner of the robot).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EraseArea {
+    pub x_min: u16,
+    pub y_min: u16,
+    pub x_max: u16,
+    pub y_max: u16,
+}
+
+impl EraseArea {
+    /// The entire board.
+    pub fn full(board: &Board) -> Self {
+        let (x_max, y_max) = board.robot_size();
+        EraseArea { x_min: 0, y_min: 0, x_max, y_max }
+    }
+
+    /// The bounding box of the polylines (in board coordinates), extended by
+    /// `margin` millimeters and limited to the board. Return `None` if there
+    /// are no points.
+    pub fn around(polylines: &[Polyline], margin: f64, board: &Board) -> Option<Self> {
+        let mut points = polylines.iter().flat_map(|polyline| polyline.iter());
+        let first = points.next()?;
+        let (mut min, mut max) = (*first, *first);
+        for point in points {
+            min.x = min.x.min(point.x);
+            min.y = min.y.min(point.y);
+            max.x = max.x.max(point.x);
+            max.y = max.y.max(point.y);
+        }
+
+        // Depending on the origin, the axes are inverted in robot coordinates
+        let (x1, y1) = board.robot_coords(&CoordinatePair { x: min.x - margin, y: min.y - margin });
+        let (x2, y2) = board.robot_coords(&CoordinatePair { x: max.x + margin, y: max.y + margin });
+        Some(EraseArea { x_min: x1.min(x2), y_min: y1.min(y2), x_max: x1.max(x2), y_max: y1.max(y2) })
+    }
+}
+
+/// The direction of the eraser sweeps.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SweepDirection {
+    /// Sweep left and right, stepping from top to bottom.
+    Horizontal,
+    /// Sweep up and down, stepping from left to right.
+    Vertical,
+}
+
+/// How the eraser moves over the area to erase.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SweepPattern {
+    /// Distance between two sweeps, in millimeters. Should be a bit less than
+    /// the width of the eraser pad.
+    pub step: f64,
+    pub direction: SweepDirection,
+    /// Sweep the area a second time, in the opposite order.
+    pub double_pass: bool,
+    /// Finally move the eraser along the edges of the area.
+    pub edge_pass: bool,
+}
+
+impl Default for SweepPattern {
+    fn default() -> Self {
+        SweepPattern {
+            step: 10.0,
+            direction: SweepDirection::Horizontal,
+            double_pass: false,
+            edge_pass: false,
+        }
+    }
+}
+
+impl SweepPattern {
+    /// Check that the sweeps are at least 0.1 mm (one robot step) apart.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.step.is_finite() && self.step >= 0.1) {
+            return Err("Erase step must be at least 0.1 mm".to_string());
+        }
+        Ok(())
+    }
+
+    /// Return the positions of the sweeps, from `first` to `last`. The
+    /// pattern must be valid.
+    fn positions(&self, first: u16, last: u16) -> Vec<u16> {
+        let step = (self.step * 10.0).round() as u16;
+        assert!(step > 0, "Invalid erase step {}", self.step);
+        let mut positions = vec![first];
+        let mut position = first;
+        while position != last {
+            position = if first < last {
+                cmp::min(position.saturating_add(step), last)
+            } else {
+                cmp::max(position.saturating_sub(step), last)
+            };
+            positions.push(position);
+        }
+        positions
+    }
+}
+
+/// Return the commands that erase the specified area.
+///
+/// The eraser sweeps back and forth, starting at the top left corner of the
+/// area. Afterwards, the pen is lifted and moved back to `(0, 0)`.
+/// Note that this does not contain the `StartDrawing` and `StopDrawing`
+/// commands!
+pub fn erase_commands(area: &EraseArea, pattern: &SweepPattern) -> Vec<Command> {
+    // Sweeps go from `start` to `end`, the positions are perpendicular to it
+    let (start, end, positions) = match pattern.direction {
+        SweepDirection::Horizontal => (area.x_min, area.x_max, pattern.positions(area.y_max, area.y_min)),
+        SweepDirection::Vertical => (area.y_max, area.y_min, pattern.positions(area.x_min, area.x_max)),
+    };
+    let point = |along: u16, position: u16| match pattern.direction {
+        SweepDirection::Horizontal => Command::Move(along, position),
+        SweepDirection::Vertical => Command::Move(position, along),
+    };
+
+    let mut commands = vec![
+        Command::PenLift,
+        point(start, positions[0]),
+        Command::EnableEraser,
+    ];
+    let passes: Vec<Vec<u16>> = if pattern.double_pass {
+        vec![positions.clone(), positions.into_iter().rev().collect()]
+    } else {
+        vec![positions]
+    };
+    let mut at_start = true;
+    for positions in passes {
+        for (i, &position) in positions.iter().enumerate() {
+            // Step to the next position, then sweep across the area
+            if i > 0 {
+                commands.push(point(if at_start { start } else { end }, position));
+            }
+            at_start = !at_start;
+            commands.push(point(if at_start { start } else { end }, position));
+        }
+    }
+
+    if pattern.edge_pass {
+        commands.extend_from_slice(&[
+            Command::Move(area.x_min, area.y_max),
+            Command::Move(area.x_max, area.y_max),
+            Command::Move(area.x_max, area.y_min),
+            Command::Move(area.x_min, area.y_min),
+            Command::Move(area.x_min, area.y_max),
+        ]);
+    }
+
+    commands.push(Command::PenLift);
+    commands.push(Command::Move(0, 0));
+    commands
+}
+
+
+#[cfg(test)]
+mod tests {
+    use board::Origin;
+    use super::*;
+
+    #[test]
+    fn test_area_around() {
+        let polylines = vec![
+            vec![CoordinatePair { x: 10.0, y: 20.0 }, CoordinatePair { x: 30.0, y: 25.0 }],
+            vec![CoordinatePair { x: 40.0, y: 100.0 }],
+        ];
+        assert_eq!(
+            EraseArea::around(&polylines, 5.0, &Board::default()),
+            Some(EraseArea { x_min: 50, y_min: 180, x_max: 450, y_max: 1080 })
+        );
+
+        // The area is limited to the board
+        assert_eq!(
+            EraseArea::around(&polylines, 50.0, &Board::default()),
+            Some(EraseArea { x_min: 0, y_min: 0, x_max: 900, y_max: 1230 })
+        );
+        assert_eq!(EraseArea::around(&[], 5.0, &Board::default()), None);
+
+        // With the origin in the top left corner, the y axis is not inverted
+        let board = Board { origin: Origin::TopLeft, ..Board::default() };
+        assert_eq!(
+            EraseArea::around(&polylines, 5.0, &board),
+            Some(EraseArea { x_min: 50, y_min: 150, x_max: 450, y_max: 1050 })
+        );
+    }
+
+    const AREA: EraseArea = EraseArea { x_min: 50, y_min: 100, x_max: 450, y_max: 250 };
+
+    #[test]
+    fn test_sweep_positions() {
+        let pattern = SweepPattern { step: 10.0, ..Default::default() };
+        assert_eq!(pattern.positions(250, 100), vec![250, 150, 100]);
+        assert_eq!(pattern.positions(50, 250), vec![50, 150, 250]);
+        assert_eq!(pattern.positions(50, 50), vec![50]);
+        let pattern = SweepPattern { step: 0.1, ..Default::default() };
+        assert_eq!(pattern.positions(50, 53), vec![50, 51, 52, 53]);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(SweepPattern::default().validate().is_ok());
+        assert!(SweepPattern { step: 0.1, ..Default::default() }.validate().is_ok());
+        for &step in &[0.0, -10.0, 0.01, f64::NAN, f64::INFINITY] {
+            assert!(SweepPattern { step, ..Default::default() }.validate().is_err(), "{}", step);
+        }
+    }
+
+    #[test]
+    fn test_erase_horizontal() {
+        assert_eq!(erase_commands(&AREA, &SweepPattern::default()), vec![
+            Command::PenLift,
+            Command::Move(50, 250),
+            Command::EnableEraser,
+            Command::Move(450, 250),
+            Command::Move(450, 150),
+            Command::Move(50, 150),
+            Command::Move(50, 100),
+            Command::Move(450, 100),
+            Command::PenLift,
+            Command::Move(0, 0),
+        ]);
+    }
+
+    #[test]
+    fn test_erase_vertical() {
+        let pattern = SweepPattern {
+            step: 20.0,
+            direction: SweepDirection::Vertical,
+            ..Default::default()
+        };
+        assert_eq!(erase_commands(&AREA, &pattern), vec![
+            Command::PenLift,
+            Command::Move(50, 250),
+            Command::EnableEraser,
+            Command::Move(50, 100),
+            Command::Move(250, 100),
+            Command::Move(250, 250),
+            Command::Move(450, 250),
+            Command::Move(450, 100),
+            Command::PenLift,
+            Command::Move(0, 0),
+        ]);
+    }
+
+    #[test]
+    fn test_erase_every_row() {
+        // With an odd and an even number of rows, the last row is swept too
+        for &(step, ref rows) in &[(10.0, vec![250, 150, 100]), (5.0, vec![250, 200, 150, 100])] {
+            let pattern = SweepPattern { step, ..Default::default() };
+            // The eraser is enabled at the first move
+            let moves: Vec<Command> = erase_commands(&AREA, &pattern).into_iter()
+                .filter(|command| matches!(*command, Command::Move(..)))
+                .collect();
+            for &y in rows {
+                assert!(
+                    moves.windows(2).any(|pair| {
+                        pair == [Command::Move(50, y), Command::Move(450, y)]
+                            || pair == [Command::Move(450, y), Command::Move(50, y)]
+                    }),
+                    "Row {} is not swept with step {}", y, step,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_erase_double_pass() {
+        let pattern = SweepPattern { step: 15.0, double_pass: true, ..Default::default() };
+        assert_eq!(erase_commands(&AREA, &pattern), vec![
+            Command::PenLift,
+            Command::Move(50, 250),
+            Command::EnableEraser,
+            Command::Move(450, 250),
+            Command::Move(450, 100),
+            Command::Move(50, 100),
+            // Second pass
+            Command::Move(450, 100),
+            Command::Move(450, 250),
+            Command::Move(50, 250),
+            Command::PenLift,
+            Command::Move(0, 0),
+        ]);
+    }
+
+    #[test]
+    fn test_erase_edge_pass() {
+        let pattern = SweepPattern { step: 15.0, edge_pass: true, ..Default::default() };
+        assert_eq!(erase_commands(&AREA, &pattern), vec![
+            Command::PenLift,
+            Command::Move(50, 250),
+            Command::EnableEraser,
+            Command::Move(450, 250),
+            Command::Move(450, 100),
+            Command::Move(50, 100),
+            // Edges
+            Command::Move(50, 250),
+            Command::Move(450, 250),
+            Command::Move(450, 100),
+            Command::Move(50, 100),
+            Command::Move(50, 250),
+            Command::PenLift,
+            Command::Move(0, 0),
+        ]);
+    }
+
+    #[test]
+    fn test_erase_full_board() {
+        let commands = erase_commands(&EraseArea::full(&Board::default()), &SweepPattern::default());
+        assert_eq!(commands[1], Command::Move(0, 1230));
+        assert_eq!(commands[commands.len() - 3], Command::Move(0, 0));
+        assert!(commands.iter().all(|command| match *command {
+            Command::Move(x, y) => x <= 3580 && y <= 1230,
+            _ => true,
+        }));
+    }
+}
@@ -0,0 +1,238 @@
+//! Estimation of how long the robot takes to print a drawing.
+//!
+//! The estimate follows the commands that are sent to the robot, so it
+//! includes erasing the board and the moves with the pen lifted. The timing
+//! model is a rough approximation of the robot (constant speeds, no
+//! acceleration). It can be calibrated with measured timings in the config.
+use svg2polylines::Polyline;
+
+use board::Board;
+use calibration::Calibration;
+use decode;
+use erase::{EraseStrategy, SweepPattern};
+use render::Tool;
+use robot::{Block, Command, Sketch};
+
+/// Speeds below this (in millimeters per second) are considered invalid.
+const MIN_SPEED: f64 = 1.0;
+
+/// How long the robot takes for the different commands.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimingModel {
+    /// Speed with the pen down, in millimeters per second.
+    pub draw_speed: f64,
+    /// Speed with the pen lifted, in millimeters per second.
+    pub travel_speed: f64,
+    /// Speed with the eraser down, in millimeters per second.
+    pub erase_speed: f64,
+    /// Additional time per move (for accelerating and braking), in seconds.
+    pub move_seconds: f64,
+    /// Time to lift or lower the pen or the eraser, in seconds.
+    pub servo_seconds: f64,
+    /// Time to request, transfer and acknowledge a block, in seconds.
+    pub block_seconds: f64,
+}
+
+impl Default for TimingModel {
+    fn default() -> Self {
+        TimingModel {
+            draw_speed: 40.0,
+            travel_speed: 80.0,
+            erase_speed: 60.0,
+            move_seconds: 0.02,
+            servo_seconds: 0.3,
+            block_seconds: 1.0,
+        }
+    }
+}
+
+impl TimingModel {
+    fn speed(&self, tool: Tool) -> f64 {
+        let speed = match tool {
+            Tool::Travel => self.travel_speed,
+            Tool::Pen => self.draw_speed,
+            Tool::Eraser => self.erase_speed,
+        };
+        // Invalid speeds (e.g. zero or negative) fall back to the minimum
+        speed.max(MIN_SPEED)
+    }
+}
+
+/// The estimated duration of a print in seconds, split up by activity.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Estimate {
+    /// The total duration.
+    pub seconds: f64,
+    /// Moving with the pen down.
+    pub drawing: f64,
+    /// Moving with the pen lifted.
+    pub travel: f64,
+    /// Moving with the eraser down.
+    pub erasing: f64,
+    /// Lifting and lowering the pen and the eraser.
+    pub servo: f64,
+    /// Explicit waits.
+    pub waiting: f64,
+    /// Transferring the blocks.
+    pub transfer: f64,
+    /// The duration of every block.
+    #[serde(skip)]
+    pub blocks: Vec<f64>,
+}
+
+/// Estimate how long the robot takes for the blocks. The robot is assumed to
+/// start at `(0, 0)` with the pen lifted.
+///
+/// Malformed commands are ignored.
+pub fn estimate(blocks: &[Block], model: &TimingModel) -> Estimate {
+    let mut estimate = Estimate::default();
+    let mut tool = Tool::Travel;
+    let mut position = (0, 0);
+    for block in blocks {
+        let mut seconds = model.block_seconds;
+        estimate.transfer += model.block_seconds;
+        for (_, result) in decode::decode(block) {
+            let next_tool = match result {
+                Ok(Command::PenLift) => Tool::Travel,
+                Ok(Command::PenDown) => Tool::Pen,
+                Ok(Command::EnableEraser) => Tool::Eraser,
+                Ok(Command::Move(x, y)) => {
+                    let dx = f64::from(x) - f64::from(position.0);
+                    let dy = f64::from(y) - f64::from(position.1);
+                    let distance = dx.hypot(dy) / 10.0;
+                    let duration = distance / model.speed(tool) + model.move_seconds;
+                    match tool {
+                        Tool::Travel => estimate.travel += duration,
+                        Tool::Pen => estimate.drawing += duration,
+                        Tool::Eraser => estimate.erasing += duration,
+                    }
+                    seconds += duration;
+                    position = (x, y);
+                    continue;
+                },
+                Ok(Command::Wait(wait)) => {
+                    estimate.waiting += f64::from(wait);
+                    seconds += f64::from(wait);
+                    continue;
+                },
+                _ => continue,
+            };
+            if next_tool != tool {
+                estimate.servo += model.servo_seconds;
+                seconds += model.servo_seconds;
+                tool = next_tool;
+            }
+        }
+        estimate.blocks.push(seconds);
+    }
+    estimate.seconds = estimate.blocks.iter().sum();
+    estimate
+}
+
+/// Estimate how long the robot takes to erase the board and draw the
+/// polylines (in board coordinates), corrected with the calibration.
+pub fn estimate_polylines(
+    polylines: &[Polyline],
+    board: &Board,
+    calibration: &Calibration,
+    erase: EraseStrategy,
+    pattern: SweepPattern,
+    model: &TimingModel,
+) -> Estimate {
+    let blocks = Sketch::new(polylines)
+        .with_erase_pattern(pattern)
+        .with_board(*board)
+        .with_calibration(calibration)
+        .into_blocks(Some(erase));
+    estimate(&blocks, model)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use svg2polylines::CoordinatePair;
+
+    use super::*;
+
+    fn block(number: u16, commands: &[Command]) -> Block {
+        let mut block = vec![];
+        for command in [Command::BlockStart, Command::BlockNumber(number)].iter().chain(commands) {
+            block.extend_from_slice(&command.to_bytes());
+        }
+        block
+    }
+
+    fn model() -> TimingModel {
+        TimingModel {
+            draw_speed: 10.0,
+            travel_speed: 20.0,
+            erase_speed: 5.0,
+            move_seconds: 0.0,
+            servo_seconds: 0.5,
+            block_seconds: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_estimate() {
+        let blocks = vec![
+            block(1, &[
+                Command::StartDrawing,
+                Command::EnableEraser,
+                Command::Move(0, 100), // 10 mm erasing
+                Command::PenLift,
+                Command::Move(0, 500), // 40 mm travel
+            ]),
+            block(2, &[
+                Command::PenDown,
+                Command::Move(300, 900), // 50 mm drawing
+                Command::Wait(3),
+                Command::PenLift,
+                Command::PenLift, // The pen is already lifted
+                Command::StopDrawing,
+            ]),
+        ];
+        let estimate = estimate(&blocks, &model());
+        assert_eq!(estimate, Estimate {
+            seconds: 16.0,
+            drawing: 5.0,
+            travel: 2.0,
+            erasing: 2.0,
+            servo: 2.0,
+            waiting: 3.0,
+            transfer: 2.0,
+            blocks: vec![6.0, 10.0],
+        });
+    }
+
+    #[test]
+    fn test_invalid_speed() {
+        let model = TimingModel { travel_speed: 0.0, ..model() };
+        let blocks = vec![block(1, &[Command::Move(100, 0)])];
+        assert_eq!(estimate(&blocks, &model).travel, 10.0);
+    }
+
+    #[test]
+    fn test_estimate_polylines() {
+        let polylines = vec![vec![
+            CoordinatePair { x: 10.0, y: 10.0 },
+            CoordinatePair { x: 110.0, y: 10.0 },
+        ]];
+        let board = Board::default();
+        let calibration = Calibration::default();
+        let full = estimate_polylines(&polylines, &board, &calibration, EraseStrategy::Full, SweepPattern::default(), &model());
+        let partial = estimate_polylines(
+            &polylines,
+            &board,
+            &calibration,
+            EraseStrategy::BoundingBox { margin: 5.0 },
+            SweepPattern::default(),
+            &model(),
+        );
+        assert!((full.drawing - 10.0).abs() < 1e-9);
+        assert_eq!(full.drawing, partial.drawing);
+        assert!(full.erasing > 5.0 * partial.erasing);
+        assert_eq!(full.blocks.len(), full.transfer as usize);
+    }
+}
@@ -0,0 +1,124 @@
+//! Events emitted by the robot thread.
+//!
+//! Subscribers (e.g. the `/events/` endpoint) receive every event as a
+//! server-sent event.
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use serde_json;
+
+use jobs::{JobId, JobState};
+
+pub type SharedEventBus = Arc<EventBus>;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The robot started printing a job.
+    JobStarted { job_id: JobId, blocks_total: usize },
+    /// A block was sent to the robot.
+    BlockSent { job_id: JobId, block: u32, blocks_total: usize },
+    /// The robot acknowledged a block.
+    AckReceived { block: u32 },
+    /// A job is done, failed or was cancelled.
+    JobFinished { job_id: JobId, state: JobState },
+    /// The robot started talking to us.
+    RobotConnected,
+    /// The robot stopped talking to us.
+    RobotDisconnected,
+    /// A scheduled print was skipped because of the time limits.
+    PrintSkipped,
+    /// Printing was paused.
+    Paused,
+    /// Printing was resumed.
+    Resumed,
+    /// The print schedule was stopped.
+    ScheduleStopped,
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match *self {
+            Event::JobStarted { .. } => "job_started",
+            Event::BlockSent { .. } => "block_sent",
+            Event::AckReceived { .. } => "ack_received",
+            Event::JobFinished { .. } => "job_finished",
+            Event::RobotConnected => "robot_connected",
+            Event::RobotDisconnected => "robot_disconnected",
+            Event::PrintSkipped => "print_skipped",
+            Event::Paused => "paused",
+            Event::Resumed => "resumed",
+            Event::ScheduleStopped => "schedule_stopped",
+        }
+    }
+
+    /// Format the event as a server-sent event.
+    pub fn to_sse(&self) -> String {
+        let data = serde_json::to_string(self).expect("Could not serialize event");
+        format!("event: {}\ndata: {}\n\n", self.name(), data)
+    }
+}
+
+/// Distributes events to all subscribers.
+#[derive(Debug, Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<UnboundedSender<Bytes>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribe to all future events. The events are formatted as
+    /// server-sent events.
+    pub fn subscribe(&self) -> UnboundedReceiver<Bytes> {
+        let (tx, rx) = unbounded();
+        match self.subscribers.lock() {
+            Ok(mut subscribers) => subscribers.push(tx),
+            Err(e) => error!("Could not unlock event subscribers mutex: {}", e),
+        }
+        rx
+    }
+
+    /// Send an event to all subscribers. Subscribers that went away are
+    /// removed.
+    pub fn publish(&self, event: Event) {
+        debug!("Event: {:?}", event);
+        let payload = Bytes::from(event.to_sse());
+        match self.subscribers.lock() {
+            Ok(mut subscribers) => {
+                subscribers.retain(|tx| tx.unbounded_send(payload.clone()).is_ok());
+            },
+            Err(e) => error!("Could not unlock event subscribers mutex: {}", e),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use futures::{Future, Stream};
+
+    use super::*;
+
+    #[test]
+    fn test_to_sse() {
+        let event = Event::BlockSent { job_id: 3, block: 2, blocks_total: 5 };
+        assert_eq!(
+            event.to_sse(),
+            "event: block_sent\ndata: {\"event\":\"block_sent\",\"job_id\":3,\"block\":2,\"blocks_total\":5}\n\n"
+        );
+    }
+
+    #[test]
+    fn test_publish() {
+        let bus = EventBus::new();
+        let rx = bus.subscribe();
+        bus.publish(Event::PrintSkipped);
+        drop(bus);
+        let received: Vec<Bytes> = rx.collect().wait().unwrap();
+        assert_eq!(received, vec![Bytes::from(Event::PrintSkipped.to_sse())]);
+    }
+}
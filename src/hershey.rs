@@ -0,0 +1,199 @@
+//! A single-stroke vector font in the format of the Hershey fonts.
+//!
+//! Every glyph is a string of coordinate pairs. Each coordinate is encoded as
+//! a character, relative to `R` (so `R` is 0, `S` is 1 and `Q` is -1). The
+//! first pair contains the left and right bound of the glyph, the following
+//! pairs are the points of the strokes. The pair `" R"` lifts the pen.
+//!
+//! The y axis points down. Capitals reach from -12 to 9 (the baseline),
+//! lowercase letters from -5 and descenders down to 16.
+
+/// Height of a capital letter, in font units.
+pub const CAP_HEIGHT: f64 = 21.0;
+
+/// Distance from the top of a capital letter to the origin, in font units.
+pub const CAP_TOP: f64 = -12.0;
+
+/// A parsed glyph, in font units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glyph {
+    pub left: i8,
+    pub right: i8,
+    pub strokes: Vec<Vec<(i8, i8)>>,
+}
+
+impl Glyph {
+    /// The horizontal space taken up by this glyph.
+    pub fn advance(&self) -> i8 {
+        self.right - self.left
+    }
+}
+
+fn decode(c: u8) -> i8 {
+    (i16::from(c) - i16::from(b'R')) as i8
+}
+
+/// Parse a glyph in the Hershey format.
+fn parse(data: &str) -> Glyph {
+    let bytes = data.as_bytes();
+    let mut strokes = vec![];
+    let mut stroke = vec![];
+    for pair in bytes[2..].chunks_exact(2) {
+        if pair == b" R" {
+            strokes.push(stroke);
+            stroke = vec![];
+        } else {
+            stroke.push((decode(pair[0]), decode(pair[1])));
+        }
+    }
+    if !stroke.is_empty() {
+        strokes.push(stroke);
+    }
+    Glyph { left: decode(bytes[0]), right: decode(bytes[1]), strokes }
+}
+
+/// Return the glyph for a character. Characters that are not in the font are
+/// replaced with a question mark.
+pub fn glyph(c: char) -> Glyph {
+    let index = match c as u32 {
+        code @ FIRST_CHAR..=LAST_CHAR => code - FIRST_CHAR,
+        _ => '?' as u32 - FIRST_CHAR,
+    };
+    parse(GLYPHS[index as usize])
+}
+
+const FIRST_CHAR: u32 = 0x20;
+const LAST_CHAR: u32 = 0x7e;
+
+/// The glyphs for the printable ASCII characters, starting with the space.
+const GLYPHS: [&str; 95] = [
+    r"LX",
+    r"OURFRV RRZR[",
+    r"MWPFPK RTFTK",
+    r"I[PFN[ RVFT[ RMMXM RLTWT",
+    r"JZWKTIPIMKMNPPTPWRWVTXPXMV RRFR[",
+    r"H\XFL[ RMFOHMJKHMF RWWYYW[UYWW",
+    r"J[X[OLOHQFSFUHUKMSMXP[S[XT",
+    r"OURFRK",
+    r"MWTDQIPPQWT]",
+    r"MWPDSITPSWP]",
+    r"KYRFRP RNHVN RVHNN",
+    r"I[RLRX RLRXR",
+    r"MURZR\P_",
+    r"JZMRWR",
+    r"OURZR[",
+    r"JZWDM^",
+    r"JZPFMIMXP[T[WXWITFPF",
+    r"LZOJSFS[ RO[W[",
+    r"JZMIPFTFWIWMM[W[",
+    r"JZMIPFTFWIWMTPQP RTPWSWXT[P[MX",
+    r"I[U[UFLUXU",
+    r"JZWFMFMPTOWRWXT[P[MX",
+    r"JZVGSFPFMIMXP[T[WXWSTPPPMS",
+    r"JZMFWFQ[",
+    r"JZPPMMMIPFTFWIWMTPPPMSMXP[T[WXWSTP",
+    r"JZWMTPPPMMMIPFTFWIWXT[Q[NZ",
+    r"OURNRO RRZR[",
+    r"MURNRO RRZR\P_",
+    r"I[XKLRXY",
+    r"I[LOXO RLUXU",
+    r"I[LKXRLY",
+    r"JZMIPFTFWIWLRPRU RRZR[",
+    r"G]VQTNQNOQOTQWTWVTVN RVTXWZTZNWIRGMIJNJUMZR\WZ",
+    r"I[L[RFX[ RNTVT",
+    r"I[LFL[ RLFUFXIXMUPLP RUPXSXXU[L[",
+    r"I[XIUFOFLILXO[U[XX",
+    r"I[LFL[T[XWXJTFLF",
+    r"I[XFLFL[X[ RLPUP",
+    r"I[XFLFL[ RLPUP",
+    r"I[XIUFOFLILXO[U[XXXRSR",
+    r"I[LFL[ RXFX[ RLPXP",
+    r"LXRFR[ ROFUF RO[U[",
+    r"JYVFVWS[P[MX",
+    r"I[LFL[ RXFLT RPPX[",
+    r"I[LFL[X[",
+    r"H\K[KFRUYFY[",
+    r"I[L[LFX[XF",
+    r"I[OFLILXO[U[XXXIUFOF",
+    r"I[L[LFUFXIXNUQLQ",
+    r"I\OFLILXO[U[XXXIUFOF RSVY\",
+    r"I[L[LFUFXIXNUQLQ RSQX[",
+    r"I[XIUFOFLILMOPUPXSXXU[O[LX",
+    r"H\KFYF RRFR[",
+    r"I[LFLXO[U[XXXF",
+    r"H\KFR[YF",
+    r"G]JFN[RMV[ZF",
+    r"I[LFX[ RXFL[",
+    r"H\KFRQYF RRQR[",
+    r"I[LFXFL[X[",
+    r"MWTDPDP]T]",
+    r"JZMDW^",
+    r"MWPDTDT]P]",
+    r"JZMLRFWL",
+    r"H\K_Y_",
+    r"NVQFSJ",
+    r"JYVMV[ RVPSMPMMPMXP[S[VX",
+    r"KZNFN[ RNPQMTMWPWXT[Q[NX",
+    r"JZWPTMPMMPMXP[T[WX",
+    r"JYVFV[ RVPSMPMMPMXP[S[VX",
+    r"JZMTWTWPTMPMMPMXP[T[WX",
+    r"KYVFSFQHQ[ RNMUM",
+    r"JYVMV_SbPbN` RVPSMPMMPMXP[S[VX",
+    r"KZNFN[ RNQRMUMWOW[",
+    r"OURMR[ RRGRH",
+    r"KVSMS_PbNb RSGSH",
+    r"KZNFN[ RWMNV RRRW[",
+    r"OURFR[",
+    r"H\KMK[ RKPNMPMRPR[ RRPUMWMYPY[",
+    r"KZNMN[ RNQRMUMWOW[",
+    r"JZPMMPMXP[T[WXWPTMPM",
+    r"KZNMNb RNPQMTMWPWXT[Q[NX",
+    r"JYVMVb RVPSMPMMPMXP[S[VX",
+    r"LYOMO[ ROQSMVM",
+    r"JZWOTMPMMOMRPTTTWVWYT[P[MY",
+    r"KXQHQXS[U[ RNMUM",
+    r"KYNMNXQ[S[VX RVMV[",
+    r"JZMMR[WM",
+    r"I[LMO[RQU[XM",
+    r"JZMMW[ RWMM[",
+    r"JZMMR[ RWMP`NbMb",
+    r"JZMMWMM[W[",
+    r"MXUDSERHRMPPRSRYS\U]",
+    r"OURDR^",
+    r"LWODQERHRMTPRSRYQ\O]",
+    r"I[LSNQPQTSVSXQ",
+];
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_glyph() {
+        assert_eq!(glyph('T'), Glyph {
+            left: -10,
+            right: 10,
+            strokes: vec![vec![(-7, -12), (7, -12)], vec![(0, -12), (0, 9)]],
+        });
+        assert_eq!(glyph(' ').strokes.len(), 0);
+        assert_eq!(glyph(' ').advance(), 12);
+    }
+
+    #[test]
+    fn test_unknown_char() {
+        assert_eq!(glyph('\u{2603}'), glyph('?'));
+    }
+
+    #[test]
+    fn test_all_glyphs() {
+        for c in (FIRST_CHAR..=LAST_CHAR).filter_map(::std::char::from_u32) {
+            let glyph = glyph(c);
+            assert!(glyph.advance() > 0, "Glyph {:?}", c);
+            for &(x, y) in glyph.strokes.iter().flatten() {
+                assert!((glyph.left..=glyph.right).contains(&x), "Glyph {:?}", c);
+                assert!((-14..=16).contains(&y), "Glyph {:?}", c);
+            }
+        }
+    }
+}
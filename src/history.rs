@@ -0,0 +1,366 @@
+//! A persistent history of print jobs.
+//!
+//! Every finished job is appended to a file with one JSON object per line.
+//! Besides the metadata, every line contains the printed polylines, so past
+//! jobs can be printed again.
+//!
+//! The file is written by a separate thread, so recording a job never blocks
+//! on disk I/O while the robot state is locked.
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+use serde_json;
+use svg2polylines::Polyline;
+use time::{self, Timespec};
+
+use jobs::{Job, JobState};
+use scaling::Orientation;
+
+pub type EntryId = usize;
+
+/// Maximum number of entries kept in memory. Older entries are only kept in
+/// the history file.
+pub const MAX_ENTRIES: usize = 1000;
+
+/// Where a print came from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Source {
+    /// An SVG uploaded through the web interface.
+    Upload,
+    /// A file from the SVG directory, printed in headless mode.
+    Headless { file: String },
+    /// A direct request to the print API.
+    Api,
+    /// Text rendered with the built-in font.
+    Text { text: String },
+    /// The current time, in clock mode.
+    Clock,
+    /// Today's agenda, in agenda mode.
+    Agenda,
+    /// The calibration pattern.
+    Calibration,
+    /// A block file replayed from the command line. The polylines of the
+    /// blocks are not known, so it can't be reprinted.
+    Replay { file: String },
+    /// A reprint of a history entry.
+    Reprint { entry: EntryId },
+}
+
+/// The transformation parameters of a print request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrintParams {
+    pub offset_x: f64,
+    pub offset_y: f64,
+    pub scale_x: f64,
+    pub scale_y: f64,
+    /// Rotation and mirroring, applied before scaling.
+    #[serde(flatten)]
+    pub orientation: Orientation,
+    /// Reorder polylines to reduce pen-up travel.
+    #[serde(default)]
+    pub optimize: bool,
+    /// Simplification tolerance in millimeters on the board.
+    #[serde(default)]
+    pub simplify_tolerance: Option<f64>,
+    /// Join polylines whose endpoints are closer than this (in millimeters).
+    #[serde(default)]
+    pub join_tolerance: Option<f64>,
+}
+
+/// Polylines (in board coordinates) to print, together with their origin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drawing {
+    pub polylines: Vec<Polyline>,
+    pub source: Source,
+    pub params: Option<PrintParams>,
+}
+
+impl Drawing {
+    pub fn new(polylines: Vec<Polyline>, source: Source, params: Option<PrintParams>) -> Self {
+        Drawing { polylines, source, params }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: EntryId,
+    pub source: Source,
+    pub params: Option<PrintParams>,
+    pub blocks_sent: usize,
+    pub blocks_total: usize,
+    /// Start and end time (RFC 3339, UTC). Jobs that were cancelled while
+    /// queued were never started.
+    pub started: Option<String>,
+    pub finished: String,
+    pub outcome: JobState,
+}
+
+/// A line in the history file.
+#[derive(Serialize)]
+struct Record<'a> {
+    #[serde(flatten)]
+    entry: &'a HistoryEntry,
+    polylines: &'a [Polyline],
+}
+
+/// The polylines of a line in the history file.
+#[derive(Deserialize)]
+struct StoredPolylines {
+    polylines: Vec<Polyline>,
+}
+
+fn format_time(at: Timespec) -> String {
+    time::at_utc(at).rfc3339().to_string()
+}
+
+/// Append an entry to the history file.
+fn append(path: &Path, entry: &HistoryEntry, polylines: &[Polyline]) -> io::Result<()> {
+    let line = serde_json::to_string(&Record { entry, polylines })?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)
+}
+
+/// A message to the thread writing the history file.
+#[derive(Debug)]
+enum WriterMessage {
+    /// Append an entry with its polylines.
+    Append(Box<HistoryEntry>, Vec<Polyline>),
+    /// Reply once all previous entries are written.
+    Flush(Sender<()>),
+}
+
+/// Spawn the thread that appends the recorded entries to the history file.
+fn spawn_writer(path: PathBuf) -> Sender<WriterMessage> {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        for message in rx {
+            match message {
+                WriterMessage::Append(entry, polylines) => {
+                    if let Err(e) = append(&path, &entry, &polylines) {
+                        error!("Could not write history entry: {}", e);
+                    }
+                },
+                WriterMessage::Flush(done) => {
+                    let _ = done.send(());
+                },
+            }
+        }
+    });
+    tx
+}
+
+/// A history entry that can be printed again, see `History::stored`.
+#[derive(Debug)]
+pub struct StoredEntry {
+    path: PathBuf,
+    id: EntryId,
+    params: Option<PrintParams>,
+}
+
+impl StoredEntry {
+    /// Read the polylines of the entry from the history file.
+    ///
+    /// Return `Ok(None)` if the entry is not in the file (yet).
+    pub fn load(self) -> io::Result<Option<Drawing>> {
+        let file = File::open(&self.path)?;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match serde_json::from_str::<HistoryEntry>(&line) {
+                Ok(ref stored) if stored.id == self.id => {
+                    let stored: StoredPolylines = serde_json::from_str(&line)?;
+                    return Ok(Some(Drawing::new(
+                        stored.polylines,
+                        Source::Reprint { entry: self.id },
+                        self.params,
+                    )));
+                },
+                _ => {},
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[derive(Debug)]
+pub struct History {
+    /// The history file. If `None`, the history is not persisted.
+    path: Option<PathBuf>,
+    /// The most recent entries, oldest first.
+    entries: VecDeque<HistoryEntry>,
+    /// The channel to the thread writing the history file.
+    writer: Option<Sender<WriterMessage>>,
+}
+
+impl History {
+    /// Create a history that is kept in memory only.
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        History { path: None, entries: VecDeque::new(), writer: None }
+    }
+
+    /// Load the history from the specified file. If the file does not exist
+    /// yet, it will be created when the first entry is added.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut entries = VecDeque::new();
+        match File::open(&path) {
+            Ok(file) => {
+                for (number, line) in BufReader::new(file).lines().enumerate() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_str(&line) {
+                        Ok(entry) => entries.push_back(entry),
+                        Err(e) => warn!("Skipping invalid history entry on line {}: {}", number + 1, e),
+                    }
+                    if entries.len() > MAX_ENTRIES {
+                        entries.pop_front();
+                    }
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+        info!("Loaded {} history entries from {}", entries.len(), path.display());
+        let writer = Some(spawn_writer(path.clone()));
+        Ok(History { path: Some(path), entries, writer })
+    }
+
+    /// Return all entries, newest first.
+    pub fn entries(&self) -> impl Iterator<Item=&HistoryEntry> {
+        self.entries.iter().rev()
+    }
+
+    /// Add a finished job with the specified polylines to the history.
+    ///
+    /// The entry is written to the history file in the background.
+    pub fn record(&mut self, job: &Job, polylines: Vec<Polyline>) {
+        let entry = HistoryEntry {
+            id: self.entries.back().map(|e| e.id + 1).unwrap_or(1),
+            source: job.drawing.source.clone(),
+            params: job.drawing.params.clone(),
+            blocks_sent: job.blocks_sent(),
+            blocks_total: job.blocks_total(),
+            started: job.started.map(format_time),
+            finished: format_time(job.finished.unwrap_or_else(time::get_time)),
+            outcome: job.state,
+        };
+        if let Some(ref writer) = self.writer {
+            if let Err(e) = writer.send(WriterMessage::Append(Box::new(entry.clone()), polylines)) {
+                error!("Could not send history entry to writer thread: {}", e);
+            }
+        }
+        self.entries.push_back(entry);
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Return a history entry, so that it can be printed again. Its
+    /// polylines are read from the file with `StoredEntry::load`, which
+    /// should be done without holding a lock.
+    ///
+    /// Return `None` if there is no such entry, if it was a replay, or if
+    /// the history is not persisted.
+    pub fn stored(&self, id: EntryId) -> Option<StoredEntry> {
+        let entry = match self.entries.iter().find(|e| e.id == id) {
+            Some(&HistoryEntry { source: Source::Replay { .. }, .. }) => return None,
+            Some(entry) => entry,
+            None => return None,
+        };
+        self.path.as_ref().map(|path| StoredEntry {
+            path: path.clone(),
+            id,
+            params: entry.params.clone(),
+        })
+    }
+
+    /// Wait until all recorded entries are written to the history file.
+    pub fn flush(&self) {
+        if let Some(ref writer) = self.writer {
+            let (tx, rx) = channel();
+            if writer.send(WriterMessage::Flush(tx)).is_ok() {
+                let _ = rx.recv();
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use svg2polylines::CoordinatePair;
+
+    use super::*;
+
+    fn job(state: JobState) -> Job {
+        let polylines = vec![vec![CoordinatePair { x: 1.0, y: 2.0 }, CoordinatePair { x: 3.0, y: 4.0 }]];
+        let drawing = Drawing::new(polylines, Source::Headless { file: "a.svg".into() }, None);
+        let mut job = Job::new(::jobs::JobKind::Once, drawing, vec![vec![1], vec![2]], &Default::default());
+        job.state = state;
+        job
+    }
+
+    fn record(history: &mut History, state: JobState) {
+        let job = job(state);
+        let polylines = job.drawing.polylines.clone();
+        history.record(&job, polylines);
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let mut history = History::in_memory();
+        record(&mut history, JobState::Done);
+        record(&mut history, JobState::Cancelled);
+        let ids: Vec<_> = history.entries().map(|e| (e.id, e.outcome)).collect();
+        assert_eq!(ids, vec![(2, JobState::Cancelled), (1, JobState::Done)]);
+        assert_eq!(history.entries().next().unwrap().started, None);
+        assert!(history.stored(1).is_none());
+    }
+
+    #[test]
+    fn test_max_entries() {
+        let mut history = History::in_memory();
+        for _ in 0..MAX_ENTRIES + 2 {
+            record(&mut history, JobState::Done);
+        }
+        assert_eq!(history.entries().count(), MAX_ENTRIES);
+        assert_eq!(history.entries().next().unwrap().id, MAX_ENTRIES + 2);
+        assert_eq!(history.entries().last().unwrap().id, 3);
+    }
+
+    #[test]
+    fn test_persistence() {
+        let path = env::temp_dir().join(format!("iboardbot-history-{}.jsonl", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut history = History::open(&path).unwrap();
+        record(&mut history, JobState::Done);
+        record(&mut history, JobState::Failed);
+        history.flush();
+
+        let history = History::open(&path).unwrap();
+        let entries: Vec<_> = history.entries().cloned().collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].outcome, JobState::Failed);
+        assert_eq!(entries[1].source, Source::Headless { file: "a.svg".into() });
+        assert_eq!(entries[1].blocks_total, 2);
+
+        let drawing = history.stored(1).unwrap().load().unwrap().unwrap();
+        assert_eq!(drawing.polylines, job(JobState::Done).drawing.polylines);
+        assert_eq!(drawing.source, Source::Reprint { entry: 1 });
+        assert!(history.stored(3).is_none());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
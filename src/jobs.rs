@@ -0,0 +1,520 @@
+//! The queue of print jobs.
+//!
+//! Every print (a one-off print or an iteration of a scheduled print) becomes
+//! a job. Jobs are printed one after another, so the blocks of different jobs
+//! never mix.
+use std::collections::VecDeque;
+use std::mem;
+use std::slice;
+
+use time::{self, Timespec};
+
+use estimate::{self, TimingModel};
+use events::{Event, SharedEventBus};
+use history::{Drawing, History};
+use robot::{self, Block};
+
+/// Number of finished jobs to remember.
+const MAX_FINISHED: usize = 20;
+
+pub type JobId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Printing,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobKind {
+    /// A one-off print.
+    Once,
+    /// An iteration of a scheduled print.
+    Scheduled,
+}
+
+/// The order in which queued jobs are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueuePolicy {
+    /// Print jobs in the order they were submitted.
+    Fifo,
+    /// Print one-off jobs before scheduled jobs.
+    OnceFirst,
+}
+
+#[derive(Debug)]
+pub struct Job {
+    pub id: JobId,
+    pub kind: JobKind,
+    pub state: JobState,
+    /// The polylines and their origin.
+    pub drawing: Drawing,
+    pub started: Option<Timespec>,
+    pub finished: Option<Timespec>,
+    /// The blocks to send. They are dropped when the job is finished.
+    blocks: Vec<Block>,
+    /// Estimated duration of every block in seconds. This is kept when the
+    /// job is finished, so it also counts the blocks.
+    block_seconds: Vec<f64>,
+    /// Number of blocks sent to the robot.
+    sent: usize,
+    /// Number of blocks acknowledged by the robot.
+    acked: usize,
+}
+
+impl Job {
+    /// Create a job and estimate the duration of its blocks. This takes a
+    /// while for large drawings, so it should be done before locking the
+    /// queue. The id is assigned when the job is queued.
+    pub fn new(kind: JobKind, drawing: Drawing, blocks: Vec<Block>, timing: &TimingModel) -> Self {
+        let block_seconds = estimate::estimate(&blocks, timing).blocks;
+        Job {
+            id: 0,
+            kind, drawing, blocks, block_seconds,
+            state: JobState::Queued,
+            started: None,
+            finished: None,
+            sent: 0,
+            acked: 0,
+        }
+    }
+
+    /// Number of blocks in this job.
+    pub fn blocks_total(&self) -> usize {
+        self.block_seconds.len()
+    }
+
+    /// Number of blocks sent to the robot.
+    pub fn blocks_sent(&self) -> usize {
+        self.sent
+    }
+
+    /// Number of blocks acknowledged by the robot.
+    pub fn blocks_acked(&self) -> usize {
+        self.acked
+    }
+
+    /// Estimated duration of the whole job in seconds.
+    pub fn estimated_seconds(&self) -> f64 {
+        self.block_seconds.iter().sum()
+    }
+
+    /// Estimated time in seconds until the robot acknowledges the last block.
+    pub fn remaining_seconds(&self) -> f64 {
+        self.block_seconds[self.acked.min(self.block_seconds.len())..].iter().sum()
+    }
+
+    /// Estimated duration in seconds of the block the robot is executing,
+    /// i.e. the block that was sent but not acknowledged yet.
+    pub fn running_block_seconds(&self) -> Option<f64> {
+        if self.sent > self.acked {
+            self.block_seconds.get(self.acked).cloned()
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct JobQueue {
+    policy: QueuePolicy,
+    next_id: JobId,
+    queued: VecDeque<Job>,
+    current: Option<Job>,
+    finished: VecDeque<Job>,
+    /// If set, no further blocks are sent to the robot.
+    paused: bool,
+    events: SharedEventBus,
+    history: History,
+    /// Used to estimate the duration of cancel blocks.
+    timing: TimingModel,
+}
+
+impl JobQueue {
+    pub fn new(policy: QueuePolicy, events: SharedEventBus, history: History, timing: TimingModel) -> Self {
+        JobQueue {
+            policy,
+            next_id: 1,
+            queued: VecDeque::new(),
+            current: None,
+            finished: VecDeque::new(),
+            paused: false,
+            events,
+            history,
+            timing,
+        }
+    }
+
+    /// Add a new job to the queue. Return the job id, or `None` if the job
+    /// was not queued.
+    ///
+    /// A scheduled job is skipped if another scheduled job is still waiting
+    /// in the queue, so that a busy robot does not pile up scheduled prints.
+    pub fn push(&mut self, mut job: Job) -> Option<JobId> {
+        if job.kind == JobKind::Scheduled && self.queued.iter().any(|j| j.kind == JobKind::Scheduled) {
+            warn!("Skipping scheduled job, another scheduled job is still queued");
+            return None;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        job.id = id;
+
+        // Determine position in queue
+        let position = match (self.policy, job.kind) {
+            (QueuePolicy::OnceFirst, JobKind::Once) => self.queued.iter()
+                .position(|j| j.kind == JobKind::Scheduled)
+                .unwrap_or(self.queued.len()),
+            _ => self.queued.len(),
+        };
+        self.queued.insert(position, job);
+        info!("Queued job {} ({} job(s) in queue)", id, self.queued.len());
+        Some(id)
+    }
+
+    /// Return whether there is a job that is either printing or queued.
+    pub fn has_work(&self) -> bool {
+        self.current.is_some() || !self.queued.is_empty()
+    }
+
+    /// Return the job that is currently printing.
+    pub fn current(&self) -> Option<&Job> {
+        self.current.as_ref()
+    }
+
+    /// Return the history of finished jobs.
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Return the number of queued jobs.
+    pub fn queued_count(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// Estimated time in seconds until the current and all queued jobs are
+    /// printed.
+    pub fn remaining_seconds(&self) -> f64 {
+        let current = self.current.as_ref().map(Job::remaining_seconds).unwrap_or(0.0);
+        current + self.queued.iter().map(Job::estimated_seconds).sum::<f64>()
+    }
+
+    /// Move a job to the list of finished jobs and record it in the history.
+    fn finish(&mut self, mut job: Job, state: JobState) {
+        info!("Job {} finished: {:?}", job.id, state);
+        self.events.publish(Event::JobFinished { job_id: job.id, state });
+        job.state = state;
+        job.finished = Some(time::get_time());
+        // Only the metadata of finished jobs is kept in memory
+        let polylines = mem::take(&mut job.drawing.polylines);
+        self.history.record(&job, polylines);
+        job.blocks.clear();
+        self.finished.push_front(job);
+        self.finished.truncate(MAX_FINISHED);
+    }
+
+    /// Move the current job to the list of finished jobs.
+    fn finish_current(&mut self, state: JobState) {
+        if let Some(job) = self.current.take() {
+            self.finish(job, state);
+        }
+    }
+
+    /// Return the next block to send to the robot, together with its block
+    /// number.
+    ///
+    /// This should be called whenever the robot requests a new block. If all
+    /// blocks of the current job have been sent, the job is done and the next
+    /// queued job is started.
+    ///
+    /// While paused, no blocks are returned, except for the final block of a
+    /// cancelled job.
+    pub fn next_block(&mut self) -> Option<(u32, &Block)> {
+        let exhausted = matches!(self.current, Some(ref job) if job.sent >= job.blocks.len());
+        if exhausted {
+            let cancelled = matches!(self.current, Some(ref job) if job.state == JobState::Cancelled);
+            self.finish_current(if cancelled { JobState::Cancelled } else { JobState::Done });
+        }
+        let cancelling = matches!(self.current, Some(ref job) if job.state == JobState::Cancelled);
+        if self.paused && !cancelling {
+            return None;
+        }
+        if self.current.is_none() {
+            let mut job = self.queued.pop_front()?;
+            info!("Starting job {}", job.id);
+            self.events.publish(Event::JobStarted { job_id: job.id, blocks_total: job.blocks.len() });
+            job.state = JobState::Printing;
+            job.started = Some(time::get_time());
+            self.current = Some(job);
+        }
+        let job = self.current.as_mut()?;
+        if job.sent >= job.blocks.len() {
+            // Job without any blocks
+            return None;
+        }
+        job.sent += 1;
+        Some((job.sent as u32, &job.blocks[job.sent - 1]))
+    }
+
+    /// Record that the robot acknowledged a block of the current job.
+    pub fn block_acked(&mut self, number: u32) {
+        if let Some(ref mut job) = self.current {
+            job.acked = job.acked.max(number as usize).min(job.sent);
+        }
+    }
+
+    /// Mark the current job as failed.
+    pub fn fail_current(&mut self) {
+        self.finish_current(JobState::Failed);
+    }
+
+    /// Return whether printing is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Pause printing after the current block.
+    pub fn pause(&mut self) {
+        if !self.paused {
+            info!("Pausing print");
+            self.paused = true;
+            self.events.publish(Event::Paused);
+        }
+    }
+
+    /// Resume printing.
+    pub fn resume(&mut self) {
+        if self.paused {
+            info!("Resuming print");
+            self.paused = false;
+            self.events.publish(Event::Resumed);
+        }
+    }
+
+    /// Cancel the current job and drop its pending blocks. Return the id of
+    /// the cancelled job.
+    ///
+    /// If no job is printing, the next queued job is cancelled instead. If
+    /// the robot already started drawing the job, a final block is sent
+    /// that lifts the pen, moves back to the start and stops drawing. This
+    /// is also done if all blocks were sent already, because the robot may
+    /// still be drawing them.
+    pub fn cancel_current(&mut self) -> Option<JobId> {
+        if self.current.is_none() {
+            let job = self.queued.pop_front()?;
+            info!("Cancelling job {}", job.id);
+            let id = job.id;
+            self.finish(job, JobState::Cancelled);
+            return Some(id);
+        }
+        let (id, sent) = {
+            let job = self.current.as_mut()?;
+            if job.state == JobState::Cancelled {
+                return Some(job.id);
+            }
+            info!("Cancelling job {}", job.id);
+            job.state = JobState::Cancelled;
+            if job.sent > 0 {
+                let cancel_block = robot::cancel_block(job.sent as u16 + 1);
+                job.block_seconds.truncate(job.sent);
+                job.block_seconds.extend(estimate::estimate(slice::from_ref(&cancel_block), &self.timing).blocks);
+                job.blocks.truncate(job.sent);
+                job.blocks.push(cancel_block);
+            }
+            (job.id, job.sent)
+        };
+        if sent == 0 {
+            // The robot didn't start drawing yet
+            self.finish_current(JobState::Cancelled);
+        }
+        Some(id)
+    }
+
+    /// Cancel all queued jobs of the specified kind.
+    pub fn cancel_queued(&mut self, kind: JobKind) {
+        let (cancelled, queued): (VecDeque<Job>, VecDeque<Job>) = self.queued.drain(..)
+            .partition(|job| job.kind == kind);
+        self.queued = queued;
+        for job in cancelled {
+            self.finish(job, JobState::Cancelled);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use events::EventBus;
+    use history::Source;
+    use super::*;
+
+    fn new_queue(policy: QueuePolicy) -> JobQueue {
+        JobQueue::new(policy, Arc::new(EventBus::new()), History::in_memory(), TimingModel::default())
+    }
+
+    fn drawing() -> Drawing {
+        Drawing::new(vec![], Source::Api, None)
+    }
+
+    fn blocks(count: u8) -> Vec<Block> {
+        (1..=count).map(|i| vec![i]).collect()
+    }
+
+    fn job(kind: JobKind, count: u8) -> Job {
+        Job::new(kind, drawing(), blocks(count), &TimingModel::default())
+    }
+
+    #[test]
+    fn test_jobs_do_not_mix() {
+        let mut queue = new_queue(QueuePolicy::Fifo);
+        let first = queue.push(job(JobKind::Once, 2)).unwrap();
+        let second = queue.push(job(JobKind::Once, 1)).unwrap();
+        assert_eq!(queue.next_block(), Some((1, &vec![1])));
+        assert_eq!(queue.current.as_ref().unwrap().id, first);
+        assert_eq!(queue.next_block(), Some((2, &vec![2])));
+        assert_eq!(queue.next_block(), Some((1, &vec![1])));
+        assert_eq!(queue.current.as_ref().unwrap().id, second);
+        assert_eq!(queue.next_block(), None);
+        assert!(!queue.has_work());
+        let states: Vec<_> = queue.finished.iter().map(|j| (j.id, j.state)).collect();
+        assert_eq!(states, vec![(second, JobState::Done), (first, JobState::Done)]);
+    }
+
+    #[test]
+    fn test_policy_once_first() {
+        let mut queue = new_queue(QueuePolicy::OnceFirst);
+        let scheduled = queue.push(job(JobKind::Scheduled, 1)).unwrap();
+        let once = queue.push(job(JobKind::Once, 1)).unwrap();
+        let ids: Vec<_> = queue.queued.iter().map(|j| j.id).collect();
+        assert_eq!(ids, vec![once, scheduled]);
+
+        let mut queue = new_queue(QueuePolicy::Fifo);
+        let scheduled = queue.push(job(JobKind::Scheduled, 1)).unwrap();
+        let once = queue.push(job(JobKind::Once, 1)).unwrap();
+        let ids: Vec<_> = queue.queued.iter().map(|j| j.id).collect();
+        assert_eq!(ids, vec![scheduled, once]);
+    }
+
+    #[test]
+    fn test_scheduled_jobs_do_not_pile_up() {
+        let mut queue = new_queue(QueuePolicy::Fifo);
+        assert!(queue.push(job(JobKind::Scheduled, 1)).is_some());
+        assert!(queue.push(job(JobKind::Scheduled, 1)).is_none());
+        queue.next_block();
+        assert!(queue.push(job(JobKind::Scheduled, 1)).is_some());
+    }
+
+    #[test]
+    fn test_fail_current() {
+        let mut queue = new_queue(QueuePolicy::Fifo);
+        let id = queue.push(job(JobKind::Once, 3)).unwrap();
+        queue.next_block();
+        queue.fail_current();
+        assert!(queue.current.is_none());
+        let job = &queue.finished[0];
+        assert_eq!((job.id, job.state, job.sent), (id, JobState::Failed, 1));
+    }
+
+    #[test]
+    fn test_pause_resume() {
+        let mut queue = new_queue(QueuePolicy::Fifo);
+        queue.push(job(JobKind::Once, 2));
+        assert_eq!(queue.next_block(), Some((1, &vec![1])));
+        queue.pause();
+        assert_eq!(queue.next_block(), None);
+        assert!(queue.has_work());
+        queue.resume();
+        assert_eq!(queue.next_block(), Some((2, &vec![2])));
+    }
+
+    #[test]
+    fn test_cancel_current() {
+        let mut queue = new_queue(QueuePolicy::Fifo);
+        let id = queue.push(job(JobKind::Once, 3)).unwrap();
+        queue.next_block();
+        queue.pause();
+        assert_eq!(queue.cancel_current(), Some(id));
+
+        // The remaining blocks are replaced by a final block, even if paused
+        assert_eq!(queue.next_block(), Some((2, &robot::cancel_block(2))));
+        assert_eq!(queue.current.as_ref().unwrap().state, JobState::Cancelled);
+        assert_eq!(queue.next_block(), None);
+        assert!(queue.current.is_none());
+        assert_eq!((queue.finished[0].id, queue.finished[0].state), (id, JobState::Cancelled));
+    }
+
+    #[test]
+    fn test_cancel_current_all_sent() {
+        let mut queue = new_queue(QueuePolicy::Fifo);
+        let id = queue.push(job(JobKind::Once, 2)).unwrap();
+        queue.next_block();
+        queue.next_block();
+        assert_eq!(queue.cancel_current(), Some(id));
+
+        // The pen is parked after the last block
+        assert_eq!(queue.next_block(), Some((3, &robot::cancel_block(3))));
+        assert_eq!(queue.next_block(), None);
+        let job = &queue.finished[0];
+        assert_eq!((job.id, job.state, job.blocks_total()), (id, JobState::Cancelled, 3));
+        assert!(job.blocks.is_empty());
+    }
+
+    #[test]
+    fn test_remaining_seconds() {
+        let timing = TimingModel { block_seconds: 2.0, ..TimingModel::default() };
+        let mut queue = JobQueue::new(QueuePolicy::Fifo, Arc::new(EventBus::new()), History::in_memory(), timing);
+        queue.push(Job::new(JobKind::Once, drawing(), blocks(3), &timing));
+        queue.push(Job::new(JobKind::Once, drawing(), blocks(2), &timing));
+        assert_eq!(queue.remaining_seconds(), 10.0);
+        queue.next_block();
+        queue.block_acked(1);
+        let job = queue.current().unwrap();
+        assert_eq!((job.estimated_seconds(), job.remaining_seconds()), (6.0, 4.0));
+        assert_eq!(queue.remaining_seconds(), 8.0);
+    }
+
+    #[test]
+    fn test_cancel_current_not_started() {
+        let mut queue = new_queue(QueuePolicy::Fifo);
+        assert_eq!(queue.cancel_current(), None);
+        let first = queue.push(job(JobKind::Once, 2)).unwrap();
+        let second = queue.push(job(JobKind::Once, 1)).unwrap();
+        assert_eq!(queue.cancel_current(), Some(first));
+
+        // No cancel block is sent, the next job starts right away
+        assert_eq!((queue.finished[0].id, queue.finished[0].state), (first, JobState::Cancelled));
+        assert_eq!((queue.finished[0].blocks_sent(), queue.finished[0].blocks_total()), (0, 2));
+        assert_eq!(queue.next_block(), Some((1, &blocks(1)[0])));
+        assert_eq!(queue.current().unwrap().id, second);
+    }
+
+    #[test]
+    fn test_next_job_starts_after_finished() {
+        let mut queue = new_queue(QueuePolicy::Fifo);
+        let first = queue.push(job(JobKind::Once, 1)).unwrap();
+        let second = queue.push(job(JobKind::Once, 1)).unwrap();
+        queue.next_block();
+        queue.next_block();
+        assert_eq!(queue.current.as_ref().unwrap().id, second);
+        assert_eq!(queue.finished[0].id, first);
+    }
+
+    #[test]
+    fn test_cancel_queued() {
+        let mut queue = new_queue(QueuePolicy::Fifo);
+        let once = queue.push(job(JobKind::Once, 1)).unwrap();
+        let scheduled = queue.push(job(JobKind::Scheduled, 1)).unwrap();
+        queue.cancel_queued(JobKind::Scheduled);
+        let ids: Vec<_> = queue.queued.iter().map(|j| j.id).collect();
+        assert_eq!(ids, vec![once]);
+        assert_eq!((queue.finished[0].id, queue.finished[0].state), (scheduled, JobState::Cancelled));
+    }
+}
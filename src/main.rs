@@ -1,7 +1,10 @@
 extern crate actix_web;
+extern crate base64;
 extern crate bufstream;
+extern crate bytes;
 extern crate docopt;
 extern crate futures;
+extern crate image;
 extern crate scheduled_executor;
 #[macro_use] extern crate log;
 extern crate regex;
@@ -12,8 +15,33 @@ extern crate simplelog;
 extern crate svg2polylines;
 extern crate time;
 
+mod agenda;
+mod blockfile;
+mod board;
+mod calibration;
+mod clip;
+mod clock;
+mod decode;
+mod erase;
+mod estimate;
+mod events;
+mod hershey;
+mod history;
+mod jobs;
+mod optimize;
+mod plotfile;
+mod raster;
+mod render;
 mod robot;
 mod scaling;
+mod sim;
+mod simplify;
+mod status;
+#[cfg(test)]
+mod test_util;
+mod text;
+mod trace;
+mod transport;
 
 use std::convert::From;
 use std::ffi::OsStr;
@@ -22,7 +50,7 @@ use std::fs::{File, DirEntry, read_dir};
 use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::sync::mpsc::Sender;
 use std::time::Duration;
 use std::thread::sleep;
@@ -30,17 +58,30 @@ use std::thread::sleep;
 use actix_web::{AsyncResponder, HttpMessage};
 use actix_web::{App, HttpRequest, HttpResponse, Json, Result as ActixResult, ResponseError};
 use actix_web::fs::{StaticFiles, NamedFile};
-use actix_web::http::{Method, StatusCode};
+use actix_web::http::Method;
 use actix_web::server::HttpServer;
 use docopt::Docopt;
-use futures::Future;
+use futures::{Future, Stream};
 use serial::BaudRate;
 use simplelog::{TermLogger, SimpleLogger, LevelFilter, Config as LogConfig, TerminalMode};
 use svg2polylines::Polyline;
 use time::Tm;
 
-use robot::PrintTask;
-use scaling::{Bounds, Range};
+use agenda::AgendaConfig;
+use board::Board;
+use calibration::{Calibration, MAX_GRID_SIZE};
+use clip::ClipStats;
+use clock::ClockConfig;
+use erase::{EraseStrategy, SweepPattern};
+use estimate::{Estimate, TimingModel};
+use jobs::{JobState, QueuePolicy};
+use events::EventBus;
+use history::{Drawing, EntryId, History, HistoryEntry, PrintParams, Source, StoredEntry};
+use raster::RasterStyle;
+use robot::{Block, Command, PrintTask, ScheduledContent, Sketch};
+use scaling::{Orientation, Transform};
+use status::{RobotState, SharedState, Status};
+use text::{Align, TextStyle};
 
 type RobotQueue = Arc<Mutex<Sender<PrintTask>>>;
 
@@ -84,6 +125,18 @@ struct RawConfig {
     static_dir: Option<String>,
     interval_seconds: Option<u64>,
     time_limits: Option<TimeLimits>,
+    optimize_paths: Option<bool>,
+    simplify_tolerance: Option<f64>,
+    join_tolerance: Option<f64>,
+    queue_policy: Option<QueuePolicy>,
+    history_file: Option<String>,
+    erase_strategy: Option<EraseStrategy>,
+    erase_pattern: Option<SweepPattern>,
+    timing: Option<TimingModel>,
+    board: Option<Board>,
+    calibration: Option<Calibration>,
+    clock: Option<ClockConfig>,
+    agenda: Option<AgendaConfig>,
 }
 
 /// Note: This struct can be queried over HTTP,
@@ -96,6 +149,24 @@ struct Config {
     static_dir: String,
     interval_seconds: u64,
     time_limits: Option<TimeLimits>,
+    optimize_paths: bool,
+    simplify_tolerance: Option<f64>,
+    join_tolerance: Option<f64>,
+    queue_policy: QueuePolicy,
+    history_file: String,
+    erase_strategy: EraseStrategy,
+    erase_pattern: SweepPattern,
+    /// Used to estimate how long printing takes.
+    timing: TimingModel,
+    /// The drawable area. Clamping, erasing, fitting and the frontend use
+    /// this geometry.
+    board: Board,
+    /// Corrects the drawings for distortions of the robot.
+    calibration: Calibration,
+    /// If set, the time is drawn in headless mode.
+    clock: Option<ClockConfig>,
+    /// If set, today's agenda is drawn in headless mode.
+    agenda: Option<AgendaConfig>,
 }
 
 impl Config {
@@ -130,14 +201,40 @@ impl Config {
             }
         };
         let time_limits = config.time_limits;
-        Some(Self { listen, device, svg_dir, static_dir, interval_seconds, time_limits })
+        let optimize_paths = config.optimize_paths.unwrap_or(false);
+        let simplify_tolerance = config.simplify_tolerance;
+        let join_tolerance = config.join_tolerance;
+        let queue_policy = config.queue_policy.unwrap_or(QueuePolicy::Fifo);
+        let history_file = match config.history_file {
+            Some(ref val) => val.clone(),
+            None => "history.jsonl".to_string(),
+        };
+        let erase_strategy = config.erase_strategy.unwrap_or_default();
+        let erase_pattern = config.erase_pattern.unwrap_or_default();
+        let timing = config.timing.unwrap_or_default();
+        let board = config.board.unwrap_or_default();
+        let calibration = config.calibration.clone().unwrap_or_default();
+        let clock = config.clock;
+        let agenda = config.agenda.clone();
+        Some(Self {
+            listen, device, svg_dir, static_dir, interval_seconds, time_limits, optimize_paths,
+            simplify_tolerance, join_tolerance, queue_policy, history_file, erase_strategy,
+            erase_pattern, timing, board, calibration, clock, agenda,
+        })
     }
 }
 
-#[derive(Debug, Clone)]
+/// Note: This struct can be queried over HTTP,
+/// so be careful with sensitive data.
+#[derive(Debug, Serialize, Clone)]
 struct PreviewConfig {
     listen: String,
     static_dir: String,
+    erase_strategy: EraseStrategy,
+    erase_pattern: SweepPattern,
+    timing: TimingModel,
+    board: Board,
+    calibration: Calibration,
 }
 
 impl PreviewConfig {
@@ -145,6 +242,11 @@ impl PreviewConfig {
         Self {
             listen: config.listen.clone().unwrap_or_else(|| "listen".to_string()),
             static_dir: config.static_dir.clone().unwrap_or_else(|| "static".to_string()),
+            erase_strategy: config.erase_strategy.unwrap_or_default(),
+            erase_pattern: config.erase_pattern.unwrap_or_default(),
+            timing: config.timing.unwrap_or_default(),
+            board: config.board.unwrap_or_default(),
+            calibration: config.calibration.clone().unwrap_or_default(),
         }
     }
 }
@@ -155,13 +257,14 @@ impl PreviewConfig {
 struct State {
     config: Config,
     robot_queue: RobotQueue,
+    robot_state: SharedState,
 }
 
 #[derive(Debug)]
 enum HeadlessError {
     NoFiles,
     Io(io::Error),
-    SvgParse(String),
+    Parse(String),
     PolylineScale(String),
     Queue(String),
 }
@@ -175,9 +278,9 @@ impl From<io::Error> for HeadlessError {
 impl fmt::Display for HeadlessError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            HeadlessError::NoFiles => write!(f, "No SVG files found"),
+            HeadlessError::NoFiles => write!(f, "No SVG, G-code or HPGL files found"),
             HeadlessError::Io(e) => write!(f, "I/O Error: {}", e),
-            HeadlessError::SvgParse(e) => write!(f, "SVG Parse Error: {}", e),
+            HeadlessError::Parse(e) => write!(f, "Parse Error: {}", e),
             HeadlessError::PolylineScale(e) => write!(f, "Polyline Scaling Error: {}", e),
             HeadlessError::Queue(e) => write!(f, "Queue Error: {}", e),
         }
@@ -185,17 +288,29 @@ impl fmt::Display for HeadlessError {
 }
 
 const NAME: &str = "iboardbot-web";
+/// Maximum size of preview and print requests. Raster images are large.
+const MAX_PAYLOAD_SIZE: usize = 16 * 1024 * 1024;
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const USAGE: &str = "
 iBoardBot Web: Cloudless drawing fun.
 
 Usage:
     iboardbot-web [-h] [-v] [-c <configfile>] [--headless] [--debug]
+    iboardbot-web export [-c <configfile>] [--debug] <drawing> <blockfile>
+    iboardbot-web replay [-c <configfile>] [--debug] <blockfile>
+    iboardbot-web decode <capture>
 
 Example:
 
     iboardbot-web -c config.json
 
+Commands:
+    export           Write the blocks for a drawing (SVG, G-code or HPGL) to
+                     a block file, instead of printing it.
+    replay           Send the blocks of a block file to the robot.
+    decode           Print the commands of a captured byte stream (e.g. a
+                     block file) and report malformed sequences.
+
 Options:
     -h --help        Show this screen.
     -v --version     Show version.
@@ -206,6 +321,12 @@ Options:
 
 #[derive(Debug, Deserialize)]
 struct Args {
+    cmd_export: bool,
+    cmd_replay: bool,
+    cmd_decode: bool,
+    arg_drawing: String,
+    arg_blockfile: String,
+    arg_capture: String,
     flag_c: String,
     flag_headless: bool,
     flag_debug: bool,
@@ -216,7 +337,7 @@ fn index_handler_active(_req: HttpRequest<State>) -> ActixResult<NamedFile> {
     Ok(NamedFile::open("static/index.html")?)
 }
 
-fn index_handler_preview(_req: HttpRequest) -> ActixResult<NamedFile> {
+fn index_handler_preview(_req: HttpRequest<PreviewConfig>) -> ActixResult<NamedFile> {
     Ok(NamedFile::open("static/index-preview.html")?)
 }
 
@@ -230,9 +351,44 @@ fn config_handler(req: HttpRequest<State>) -> String {
         .to_string()
 }
 
-/// Return a list of SVG files from the SVG dir.
-fn get_svg_files(dir: &str) -> Result<Vec<String>, io::Error> {
-    let mut svg_files = read_dir(dir)
+fn config_handler_preview(req: HttpRequest<PreviewConfig>) -> String {
+    serde_json::to_value(req.state())
+        .expect("Could not serialize PreviewConfig object")
+        .to_string()
+}
+
+/// A vector file format that can be printed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VectorFormat {
+    Svg,
+    Gcode,
+    Hpgl,
+}
+
+impl VectorFormat {
+    /// Determine the format from the file extension.
+    fn from_filename(filename: &str) -> Option<Self> {
+        let extension = Path::new(filename).extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "svg" => Some(VectorFormat::Svg),
+            "gcode" | "gc" | "nc" | "ngc" => Some(VectorFormat::Gcode),
+            "hpgl" | "hpg" | "plt" => Some(VectorFormat::Hpgl),
+            _ => None,
+        }
+    }
+
+    fn parse(self, contents: &str) -> Result<Vec<Polyline>, String> {
+        match self {
+            VectorFormat::Svg => svg2polylines::parse(contents),
+            VectorFormat::Gcode => plotfile::parse_gcode(contents),
+            VectorFormat::Hpgl => plotfile::parse_hpgl(contents),
+        }
+    }
+}
+
+/// Return a list of printable files (SVG, G-code or HPGL) from the SVG dir.
+fn get_drawing_files(dir: &str) -> Result<Vec<String>, io::Error> {
+    let mut files = read_dir(dir)
         // The `read_dir` function returns an iterator over results.
         // If any iterator entry fails, fail the whole iterator.
         .and_then(|iter| iter.collect::<Result<Vec<DirEntry>, io::Error>>())
@@ -244,26 +400,102 @@ fn get_svg_files(dir: &str) -> Result<Vec<String>, io::Error> {
             .filter(|path| path.is_file())
             // Map to filename
             .filter_map(|ref path| path.file_name().map(OsStr::to_os_string).and_then(|oss| oss.into_string().ok()))
-            // We only want .svg files
-            .filter(|filename| filename.ends_with(".svg"))
+            // We only want files in a known format
+            .filter(|filename| VectorFormat::from_filename(filename).is_some())
             // Collect vector of strings
             .collect::<Vec<String>>()
         )?;
-    svg_files.sort();
-    Ok(svg_files)
+    files.sort();
+    Ok(files)
 }
 
 fn list_handler(req: HttpRequest<State>) -> Result<Json<Vec<String>>, JsonError> {
-    let svg_files = get_svg_files(&req.state().config.svg_dir)
+    let files = get_drawing_files(&req.state().config.svg_dir)
         .map_err(|_e| JsonError::ServerError(
             ErrorDetails::from("Could not read files in SVG directory")
         ))?;
-    Ok(Json(svg_files))
+    Ok(Json(files))
 }
 
+fn default_scale() -> f64 {
+    1.0
+}
+
+/// The drawing of a preview or print request. One of the formats must be
+/// sent, the SVG is used if none is.
 #[derive(Deserialize, Debug)]
-struct PreviewRequest {
+struct DrawingInput {
+    #[serde(default)]
     svg: String,
+    /// G-code, as used by many pen plotters.
+    #[serde(default)]
+    gcode: Option<String>,
+    /// HPGL, as used by many pen plotters.
+    #[serde(default)]
+    hpgl: Option<String>,
+    /// A PNG or JPEG image (base64 encoded), converted instead of the SVG.
+    #[serde(default)]
+    image: Option<String>,
+    #[serde(default)]
+    raster_style: RasterStyle,
+}
+
+impl DrawingInput {
+    /// Parse the drawing into polylines. Raster images are fitted to the
+    /// board.
+    fn parse(&self, board: &Board) -> Result<Vec<Polyline>, String> {
+        match (&self.gcode, &self.hpgl, &self.image) {
+            (Some(gcode), _, _) => VectorFormat::Gcode.parse(gcode),
+            (None, Some(hpgl), _) => VectorFormat::Hpgl.parse(hpgl),
+            (None, None, Some(image)) => {
+                raster::trace_image(&raster::decode_base64(image)?, &self.raster_style, &board.fit_bounds())
+            },
+            (None, None, None) => VectorFormat::Svg.parse(&self.svg),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct PreviewRequest {
+    #[serde(flatten)]
+    input: DrawingInput,
+    /// Simplification tolerance in millimeters on the board.
+    #[serde(default)]
+    simplify_tolerance: Option<f64>,
+    /// Join polylines whose endpoints are closer than this (in
+    /// millimeters), for the estimate.
+    #[serde(default)]
+    join_tolerance: Option<f64>,
+    /// Reorder polylines to reduce pen-up travel, for the estimate.
+    #[serde(default)]
+    optimize: bool,
+    /// The transformation that will be used for printing. It's needed to
+    /// apply the simplification tolerance in board coordinates.
+    #[serde(default)]
+    offset_x: f64,
+    #[serde(default)]
+    offset_y: f64,
+    #[serde(default = "default_scale")]
+    scale_x: f64,
+    #[serde(default = "default_scale")]
+    scale_y: f64,
+    /// Rotation and mirroring, applied to the polylines of the preview.
+    #[serde(flatten)]
+    orientation: Orientation,
+    /// Which part of the board to erase, for the estimate. Defaults to the
+    /// configured strategy.
+    #[serde(default)]
+    erase_strategy: Option<EraseStrategy>,
+}
+
+#[derive(Serialize, Debug)]
+struct PreviewResponse {
+    polylines: Vec<Polyline>,
+    /// How long printing takes with the transformation of the request.
+    estimate: Estimate,
+    /// How much of the drawing lies outside of the board with the
+    /// transformation of the request.
+    clipping: ClipStats,
 }
 
 #[derive(Deserialize, Debug)]
@@ -277,25 +509,141 @@ enum PrintMode {
 }
 
 impl PrintMode {
-    fn to_print_task(&self, polylines: Vec<Polyline>) -> PrintTask {
+    fn to_print_task(&self, drawing: Drawing, erase: EraseStrategy) -> PrintTask {
         match *self {
-            PrintMode::Once => PrintTask::Once(polylines),
-            PrintMode::Schedule5 => PrintTask::Scheduled(Duration::from_secs(5 * 60), vec![polylines]),
-            PrintMode::Schedule15 => PrintTask::Scheduled(Duration::from_secs(15 * 60), vec![polylines]),
-            PrintMode::Schedule30 => PrintTask::Scheduled(Duration::from_secs(30 * 60), vec![polylines]),
-            PrintMode::Schedule60 => PrintTask::Scheduled(Duration::from_secs(60 * 60), vec![polylines]),
+            PrintMode::Once => PrintTask::Once(drawing, erase),
+            PrintMode::Schedule5 => PrintTask::Scheduled(Duration::from_secs(5 * 60), ScheduledContent::Drawings(vec![drawing]), erase),
+            PrintMode::Schedule15 => PrintTask::Scheduled(Duration::from_secs(15 * 60), ScheduledContent::Drawings(vec![drawing]), erase),
+            PrintMode::Schedule30 => PrintTask::Scheduled(Duration::from_secs(30 * 60), ScheduledContent::Drawings(vec![drawing]), erase),
+            PrintMode::Schedule60 => PrintTask::Scheduled(Duration::from_secs(60 * 60), ScheduledContent::Drawings(vec![drawing]), erase),
+        }
+    }
+}
+
+/// Where a print request comes from (recorded in the history).
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+enum RequestSource {
+    Upload,
+    #[default]
+    Api,
+}
+
+impl From<RequestSource> for Source {
+    fn from(source: RequestSource) -> Self {
+        match source {
+            RequestSource::Upload => Source::Upload,
+            RequestSource::Api => Source::Api,
         }
     }
 }
 
 #[derive(Deserialize, Debug)]
 struct PrintRequest {
-    svg: String,
-    offset_x: f64,
-    offset_y: f64,
-    scale_x: f64,
-    scale_y: f64,
+    #[serde(flatten)]
+    input: DrawingInput,
+    #[serde(flatten)]
+    params: PrintParams,
     mode: PrintMode,
+    #[serde(default)]
+    source: RequestSource,
+    /// Which part of the board to erase. Defaults to the configured strategy.
+    #[serde(default)]
+    erase_strategy: Option<EraseStrategy>,
+}
+
+#[derive(Serialize, Debug)]
+struct PrintResponse {
+    /// How long a single print takes.
+    estimate: Estimate,
+    /// How much of the drawing lies outside of the board and is not printed.
+    clipping: ClipStats,
+}
+
+/// Like a print request, but the blocks are returned instead of printed.
+#[derive(Deserialize, Debug)]
+struct ExportRequest {
+    #[serde(flatten)]
+    input: DrawingInput,
+    #[serde(flatten)]
+    params: PrintParams,
+    /// Which part of the board to erase. Defaults to the configured strategy.
+    #[serde(default)]
+    erase_strategy: Option<EraseStrategy>,
+}
+
+impl ExportRequest {
+    /// Return the blocks that would be sent to the robot.
+    fn blocks(&self, config: &Config) -> Result<Vec<Block>, String> {
+        let polylines = prepare_polylines(&self.input, &self.params, &config.board)?;
+        let erase = self.erase_strategy.unwrap_or(config.erase_strategy);
+        Ok(Sketch::new(&polylines)
+            .with_erase_pattern(config.erase_pattern)
+            .with_board(config.board)
+            .with_calibration(&config.calibration)
+            .into_blocks(Some(erase)))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+enum RenderFormat {
+    #[default]
+    Svg,
+    Png,
+}
+
+/// Like a print request, but the path of the robot is rendered instead of
+/// printed.
+#[derive(Deserialize, Debug)]
+struct RenderRequest {
+    #[serde(flatten)]
+    export: ExportRequest,
+    #[serde(default)]
+    format: RenderFormat,
+}
+
+fn default_text_size() -> f64 {
+    10.0
+}
+
+fn default_line_spacing() -> f64 {
+    1.5
+}
+
+#[derive(Deserialize, Debug)]
+struct TextPrintRequest {
+    text: String,
+    /// Height of a capital letter, in millimeters.
+    #[serde(default = "default_text_size")]
+    size: f64,
+    /// Distance between two lines, relative to the size.
+    #[serde(default = "default_line_spacing")]
+    line_spacing: f64,
+    #[serde(default)]
+    align: Align,
+    mode: PrintMode,
+    /// Reorder polylines to reduce pen-up travel.
+    #[serde(default)]
+    optimize: bool,
+    /// Which part of the board to erase. Defaults to the configured strategy.
+    #[serde(default)]
+    erase_strategy: Option<EraseStrategy>,
+}
+
+/// Number of measuring points of the calibration pattern, if the config has
+/// no calibration grid.
+const CALIBRATION_COLUMNS: usize = 5;
+const CALIBRATION_ROWS: usize = 3;
+
+#[derive(Deserialize, Debug)]
+struct CalibrationPrintRequest {
+    /// Number of measuring points in a row. Defaults to the size of the
+    /// configured calibration grid.
+    columns: Option<usize>,
+    /// Number of measuring points in a column. Defaults to the size of the
+    /// configured calibration grid.
+    rows: Option<usize>,
 }
 
 #[derive(Serialize, Debug)]
@@ -342,86 +690,422 @@ impl ResponseError for JsonError {
 
 type JsonResult<T> = Result<T, JsonError>;
 
-fn preview_handler(req: Json<PreviewRequest>) -> JsonResult<Json<Vec<Polyline>>> {
-    match svg2polylines::parse(&req.svg) {
-        Ok(polylines) => Ok(Json(polylines)),
-        Err(errmsg) => Err(JsonError::ClientError(ErrorDetails::from(errmsg))),
+/// Check the join and simplification tolerances of a request.
+fn validate_tolerances(join_tolerance: Option<f64>, simplify_tolerance: Option<f64>) -> Result<(), String> {
+    if let Some(tolerance) = join_tolerance {
+        optimize::validate_tolerance(tolerance)?;
+    }
+    if let Some(tolerance) = simplify_tolerance {
+        simplify::validate_tolerance(tolerance)?;
+    }
+    Ok(())
+}
+
+/// Join, simplify and reorder polylines (in board coordinates) as requested.
+fn process_polylines(
+    polylines: &mut Vec<Polyline>,
+    join_tolerance: Option<f64>,
+    simplify_tolerance: Option<f64>,
+    optimize: bool,
+    board: &Board,
+) {
+    // Join polylines that share endpoints
+    if let Some(tolerance) = join_tolerance {
+        optimize::join_polylines(polylines, tolerance);
+    }
+
+    // Simplify polylines
+    if let Some(tolerance) = simplify_tolerance {
+        simplify::simplify_polylines(polylines, tolerance, board);
+    }
+
+    // Reduce pen-up travel
+    if optimize {
+        optimize::optimize_order(polylines, board.home());
     }
 }
 
+/// Return the transformation that scales a drawing and moves it on the
+/// board. The scale factors must be positive.
+fn placement(scale_x: f64, scale_y: f64, offset_x: f64, offset_y: f64) -> Result<Transform, String> {
+    if !(scale_x.is_normal() && scale_x > 0.0 && scale_y.is_normal() && scale_y > 0.0) {
+        return Err("Scale factors must be positive".to_string());
+    }
+    let transform = Transform::scale(scale_x, scale_y)
+        .then(&Transform::translate(offset_x, offset_y));
+    match transform.inverse() {
+        Some(_) => Ok(transform),
+        None => Err("Invalid offset".to_string()),
+    }
+}
+
+/// Parse the drawing of a print request and transform it to board
+/// coordinates.
+fn prepare_polylines(input: &DrawingInput, params: &PrintParams, board: &Board) -> Result<Vec<Polyline>, String> {
+    validate_tolerances(params.join_tolerance, params.simplify_tolerance)?;
+    let mut polylines = input.parse(board)?;
+    let transform = params.orientation.transform(&polylines)?
+        .then(&placement(params.scale_x, params.scale_y, params.offset_x, params.offset_y)?);
+    scaling::transform_polylines(&mut polylines, &transform);
+    process_polylines(&mut polylines, params.join_tolerance, params.simplify_tolerance, params.optimize, board);
+    Ok(polylines)
+}
+
+/// Return the polylines of a preview request, rotated and mirrored and
+/// simplified if requested, and the estimated print time.
+fn preview(
+    req: &PreviewRequest,
+    board: &Board,
+    calibration: &Calibration,
+    erase_strategy: EraseStrategy,
+    erase_pattern: SweepPattern,
+    timing: &TimingModel,
+) -> JsonResult<Json<PreviewResponse>> {
+    validate_tolerances(req.join_tolerance, req.simplify_tolerance)
+        .map_err(|errmsg| JsonError::ClientError(ErrorDetails::from(errmsg)))?;
+    let mut polylines = req.input.parse(board)
+        .map_err(|errmsg| JsonError::ClientError(ErrorDetails::from(errmsg)))?;
+    let placement = placement(req.scale_x, req.scale_y, req.offset_x, req.offset_y)
+        .map_err(|errmsg| JsonError::ClientError(ErrorDetails::from(errmsg)))?;
+    let inverse = placement.inverse()
+        .ok_or_else(|| JsonError::ClientError(ErrorDetails::from("Invalid scale factor")))?;
+
+    // The preview shows the rotated and mirrored drawing, scaling and
+    // moving happen in the browser
+    let orientation = req.orientation.transform(&polylines)
+        .map_err(|errmsg| JsonError::ClientError(ErrorDetails::from(errmsg)))?;
+    scaling::transform_polylines(&mut polylines, &orientation);
+
+    // Process and estimate in board coordinates, like a print
+    let mut board_polylines = polylines.clone();
+    scaling::transform_polylines(&mut board_polylines, &placement);
+    process_polylines(&mut board_polylines, req.join_tolerance, req.simplify_tolerance, req.optimize, board);
+    if req.simplify_tolerance.is_some() {
+        // Transform back to drawing coordinates after simplifying
+        polylines = board_polylines.clone();
+        scaling::transform_polylines(&mut polylines, &inverse);
+    }
+    let erase = req.erase_strategy.unwrap_or(erase_strategy);
+    let estimate = estimate::estimate_polylines(&board_polylines, board, calibration, erase, erase_pattern, timing);
+    let calibrated = calibration.apply_polylines(&board_polylines, board);
+    let (_, clipping) = clip::clip_polylines(&calibrated, &board.bounds());
+
+    Ok(Json(PreviewResponse { polylines, estimate, clipping }))
+}
+
+fn preview_handler_active(
+    (req, preview_request): (HttpRequest<State>, Json<PreviewRequest>),
+) -> JsonResult<Json<PreviewResponse>> {
+    let config = &req.state().config;
+    preview(
+        &preview_request,
+        &config.board,
+        &config.calibration,
+        config.erase_strategy,
+        config.erase_pattern,
+        &config.timing,
+    )
+}
+
+fn preview_handler_preview(
+    (req, preview_request): (HttpRequest<PreviewConfig>, Json<PreviewRequest>),
+) -> JsonResult<Json<PreviewResponse>> {
+    let config = req.state();
+    preview(
+        &preview_request,
+        &config.board,
+        &config.calibration,
+        config.erase_strategy,
+        config.erase_pattern,
+        &config.timing,
+    )
+}
+
+/// Lock the shared robot state.
+fn lock_robot_state<'a>(req: &'a HttpRequest<State>) -> JsonResult<MutexGuard<'a, RobotState>> {
+    req.state().robot_state.lock()
+        .map_err(|e| JsonError::ServerError(ErrorDetails::from(
+            format!("Could not access robot state: {}", e)
+        )))
+}
+
+fn status_handler(req: HttpRequest<State>) -> JsonResult<Json<Status>> {
+    let robot_state = lock_robot_state(&req)?;
+    Ok(Json(robot_state.status(req.state().config.time_limits)))
+}
+
+fn events_handler(req: HttpRequest<State>) -> JsonResult<HttpResponse> {
+    let robot_state = lock_robot_state(&req)?;
+    let stream = robot_state.events.subscribe()
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Event stream failed"));
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .streaming(stream))
+}
+
+fn cancel_handler(req: HttpRequest<State>) -> JsonResult<HttpResponse> {
+    let mut robot_state = lock_robot_state(&req)?;
+    match robot_state.jobs.cancel_current() {
+        Some(_) => Ok(HttpResponse::NoContent().finish()),
+        None => Err(JsonError::ClientError(ErrorDetails::from("No job is printing or queued"))),
+    }
+}
+
+fn pause_handler(req: HttpRequest<State>) -> JsonResult<HttpResponse> {
+    lock_robot_state(&req)?.jobs.pause();
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn resume_handler(req: HttpRequest<State>) -> JsonResult<HttpResponse> {
+    lock_robot_state(&req)?.jobs.resume();
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn stop_schedule_handler(req: HttpRequest<State>) -> JsonResult<HttpResponse> {
+    if lock_robot_state(&req)?.stop_schedule() {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(JsonError::ClientError(ErrorDetails::from("No schedule is active")))
+    }
+}
+
+/// Send a print task to the robot thread.
+fn send_task(req: &HttpRequest<State>, task: PrintTask) -> JsonResult<()> {
+    let tx = req.state().robot_queue.lock()
+        .map_err(|e| JsonError::ServerError(ErrorDetails::from(
+            format!("Could not communicate with robot thread: {}", e)
+        )))?;
+    tx.send(task)
+        .map_err(|e| JsonError::ServerError(ErrorDetails::from(
+            format!("Could not send print request to robot thread: {}", e)
+        )))
+}
+
+fn history_handler(req: HttpRequest<State>) -> JsonResult<Json<Vec<HistoryEntry>>> {
+    let robot_state = lock_robot_state(&req)?;
+    Ok(Json(robot_state.jobs.history().entries().cloned().collect()))
+}
+
+fn reprint_handler(req: HttpRequest<State>) -> JsonResult<HttpResponse> {
+    let id: EntryId = req.match_info().query("id")
+        .map_err(|_| JsonError::ClientError(ErrorDetails::from("Invalid history entry id")))?;
+    // Read the file without holding the lock
+    let stored = lock_robot_state(&req)?.jobs.history().stored(id);
+    let drawing = stored
+        .map(StoredEntry::load)
+        .unwrap_or(Ok(None))
+        .map_err(|e| JsonError::ServerError(ErrorDetails::from(
+            format!("Could not read history: {}", e)
+        )))?
+        .ok_or_else(|| JsonError::ClientError(ErrorDetails::from(
+            format!("Cannot reprint history entry {}", id)
+        )))?;
+
+    send_task(&req, PrintTask::Once(drawing, req.state().config.erase_strategy))?;
+    info!("Reprinting history entry {}", id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
 fn print_handler(req: HttpRequest<State>) -> impl Future<Item=HttpResponse, Error=JsonError> {
     req.json()
+        .limit(MAX_PAYLOAD_SIZE)
         .map_err(|e| JsonError::ServerError(ErrorDetails::from(
             format!("Could not parse JSON payload: {}", e)
         )))
         .and_then(move |print_request: PrintRequest| {
-            // Parse SVG into list of polylines
             info!("Requested print mode: {:?}", print_request.mode);
-            let mut polylines = match svg2polylines::parse(&print_request.svg) {
-                Ok(polylines) => polylines,
-                Err(e) => return Err(JsonError::ClientError(ErrorDetails::from(e))),
-            };
-
-            // Scale polylines
-            scaling::scale_polylines(
-                &mut polylines,
-                (print_request.offset_x, print_request.offset_y),
-                (print_request.scale_x, print_request.scale_y),
+            let config = &req.state().config;
+            let polylines = prepare_polylines(&print_request.input, &print_request.params, &config.board)
+                .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+
+            let drawing = Drawing::new(
+                polylines,
+                print_request.source.into(),
+                Some(print_request.params),
+            );
+            let erase = print_request.erase_strategy.unwrap_or(config.erase_strategy);
+            let estimate = estimate::estimate_polylines(
+                &drawing.polylines,
+                &config.board,
+                &config.calibration,
+                erase,
+                config.erase_pattern,
+                &config.timing,
             );
+            // Like the sketch, clip the calibrated drawing
+            let calibrated = config.calibration.apply_polylines(&drawing.polylines, &config.board);
+            let (_, clipping) = clip::clip_polylines(&calibrated, &config.board.bounds());
+            if clipping.clipped_polylines > 0 {
+                warn!("Clipping {:.1} mm outside of the board", clipping.clipped_length);
+            }
+            send_task(&req, print_request.mode.to_print_task(drawing, erase))?;
 
-            // Get access to queue
-            let tx = req.state().robot_queue.lock()
-                .map_err(|e| JsonError::ClientError(ErrorDetails::from(
-                    format!("Could not communicate with robot thread: {}", e)
-                )))?;
-            let task = print_request.mode.to_print_task(polylines);
-            tx.send(task)
-                .map_err(|e| JsonError::ServerError(ErrorDetails::from(
-                    format!("Could not send print request to robot thread: {}", e)
-                )))?;
-
-            info!("Printing...");
-            Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+            info!("Printing, estimated duration {:.0} seconds...", estimate.seconds);
+            Ok(HttpResponse::Ok().json(PrintResponse { estimate, clipping }))
         })
         .responder()
 }
 
-fn headless_start(robot_queue: RobotQueue, config: &Config) -> Result<(), HeadlessError> {
-    // Get SVG files to be printed
-    let svg_files = get_svg_files(&config.svg_dir)?;
-    if svg_files.is_empty() {
+fn export_handler(req: HttpRequest<State>) -> impl Future<Item=HttpResponse, Error=JsonError> {
+    req.json()
+        .limit(MAX_PAYLOAD_SIZE)
+        .map_err(|e| JsonError::ServerError(ErrorDetails::from(
+            format!("Could not parse JSON payload: {}", e)
+        )))
+        .and_then(move |export_request: ExportRequest| {
+            let blocks = export_request.blocks(&req.state().config)
+                .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+
+            let mut body = vec![];
+            blockfile::write_blocks(&mut body, &blocks).expect("Could not write blocks to buffer");
+
+            info!("Exporting {} blocks", blocks.len());
+            Ok(HttpResponse::Ok()
+                .content_type("application/octet-stream")
+                .header("Content-Disposition", "attachment; filename=\"drawing.ibb\"")
+                .body(body))
+        })
+        .responder()
+}
+
+fn render_handler(req: HttpRequest<State>) -> impl Future<Item=HttpResponse, Error=JsonError> {
+    req.json()
+        .limit(MAX_PAYLOAD_SIZE)
+        .map_err(|e| JsonError::ServerError(ErrorDetails::from(
+            format!("Could not parse JSON payload: {}", e)
+        )))
+        .and_then(move |render_request: RenderRequest| {
+            let config = &req.state().config;
+            let blocks = render_request.export.blocks(config)
+                .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+
+            // Render what the robot will do, not what was requested
+            let commands = decode::decode(&blocks.concat()).into_iter()
+                .map(|(offset, result)| result.map_err(|e| JsonError::ServerError(ErrorDetails::from(
+                    format!("Generated invalid command at byte {}: {}", offset, e)
+                ))))
+                .collect::<JsonResult<Vec<Command>>>()?;
+            let strokes = render::strokes(&commands);
+            let eraser_width = config.erase_pattern.step;
+            match render_request.format {
+                RenderFormat::Svg => Ok(HttpResponse::Ok()
+                    .content_type("image/svg+xml")
+                    .body(render::render_svg(&strokes, &config.board, eraser_width))),
+                RenderFormat::Png => {
+                    let png = render::render_png(&strokes, &config.board, eraser_width)
+                        .map_err(|e| JsonError::ServerError(ErrorDetails::from(e)))?;
+                    Ok(HttpResponse::Ok().content_type("image/png").body(png))
+                },
+            }
+        })
+        .responder()
+}
+
+fn print_text_handler(
+    (req, text_request): (HttpRequest<State>, Json<TextPrintRequest>),
+) -> JsonResult<HttpResponse> {
+    info!("Requested text print mode: {:?}", text_request.mode);
+    let style = TextStyle {
+        size: text_request.size,
+        line_spacing: text_request.line_spacing,
+        align: text_request.align,
+    };
+    let config = &req.state().config;
+    let mut polylines = text::render_text(&text_request.text, &style, &config.board.fit_bounds())
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    if text_request.optimize {
+        optimize::optimize_order(&mut polylines, config.board.home());
+    }
+
+    let source = Source::Text { text: text_request.text.clone() };
+    let drawing = Drawing::new(polylines, source, None);
+    let erase = text_request.erase_strategy.unwrap_or(config.erase_strategy);
+    send_task(&req, text_request.mode.to_print_task(drawing, erase))?;
+
+    info!("Printing text...");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Print the calibration pattern once, with the configured calibration
+/// applied.
+fn print_calibration_handler(
+    (req, calibration_request): (HttpRequest<State>, Json<CalibrationPrintRequest>),
+) -> JsonResult<HttpResponse> {
+    let config = &req.state().config;
+    let (columns, rows) = config.calibration.grid_size().unwrap_or((CALIBRATION_COLUMNS, CALIBRATION_ROWS));
+    let columns = calibration_request.columns.unwrap_or(columns);
+    let rows = calibration_request.rows.unwrap_or(rows);
+    if columns < 2 || rows < 2 || columns > MAX_GRID_SIZE || rows > MAX_GRID_SIZE {
+        return Err(JsonError::ClientError(ErrorDetails::from(format!(
+            "Calibration pattern needs 2 to {} columns and rows", MAX_GRID_SIZE,
+        ))));
+    }
+
+    let polylines = calibration::pattern(&config.board, columns, rows);
+    let drawing = Drawing::new(polylines, Source::Calibration, None);
+    send_task(&req, PrintTask::Once(drawing, config.erase_strategy))?;
+
+    info!("Printing calibration pattern with {} x {} points...", columns, rows);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Read a drawing file and fit it to the board.
+fn load_file(path: &Path, board: &Board) -> Result<Vec<Polyline>, HeadlessError> {
+    let filename = path.file_name().and_then(OsStr::to_str).unwrap_or_default();
+    let format = VectorFormat::from_filename(filename)
+        .ok_or_else(|| HeadlessError::Parse(format!("{}: Unknown file format", filename)))?;
+    let mut content = String::new();
+    File::open(path)?.read_to_string(&mut content)?;
+
+    let mut polylines = format.parse(&content)
+        .map_err(|e| HeadlessError::Parse(format!("{}: {}", filename, e)))?;
+    scaling::fit_polylines(&mut polylines, &board.fit_bounds())
+        .map_err(HeadlessError::PolylineScale)?;
+    Ok(polylines)
+}
+
+/// Read, fit and process all files in the SVG directory.
+fn load_drawings(config: &Config) -> Result<Vec<Drawing>, HeadlessError> {
+    // Get files to be printed
+    let files = get_drawing_files(&config.svg_dir)?;
+    if files.is_empty() {
         return Err(HeadlessError::NoFiles);
     }
 
-    // Read SVG files
-    let mut svgs = vec![];
     let base_path = Path::new(&config.svg_dir);
-    for file in svg_files {
-        let mut svg = String::new();
-        let mut f = File::open(base_path.join(&file))?;
-        f.read_to_string(&mut svg)?;
-        svgs.push(svg);
-    }
+    files.into_iter()
+        .map(|file| {
+            let mut polylines = load_file(&base_path.join(&file), &config.board)?;
+            process_polylines(
+                &mut polylines,
+                config.join_tolerance,
+                config.simplify_tolerance,
+                config.optimize_paths,
+                &config.board,
+            );
+            Ok(Drawing::new(polylines, Source::Headless { file }, None))
+        })
+        .collect()
+}
 
-    // Specify target area bounds
-    let mut bounds = Bounds {
-        x: Range { min: 0.0, max: f64::from(robot::IBB_WIDTH) },
-        y: Range { min: 0.0, max: f64::from(robot::IBB_HEIGHT) },
+fn headless_start(robot_queue: RobotQueue, config: &Config) -> Result<(), HeadlessError> {
+    // In clock or agenda mode, the time or today's agenda are drawn instead
+    // of the SVG files
+    let interval = Duration::from_secs(config.interval_seconds);
+    let (interval_duration, content) = match (config.clock, &config.agenda) {
+        (Some(clock), _) => {
+            info!("Starting clock mode");
+            let interval = Duration::from_secs(clock.interval_minutes * 60);
+            (interval, ScheduledContent::Clock(clock, config.board.fit_bounds()))
+        },
+        (None, Some(agenda_config)) => {
+            info!("Starting agenda mode");
+            agenda::check_calendar(agenda_config)?;
+            (interval, ScheduledContent::Agenda(agenda_config.clone(), config.board.fit_bounds()))
+        },
+        (None, None) => (interval, ScheduledContent::Drawings(load_drawings(config)?)),
     };
-    bounds.add_padding(5.0);
-
-    // Parse SVG strings into lists of polylines
-    let polylines_set: Vec<Vec<Polyline>> = svgs.iter()
-        .map(|ref svg| {
-            svg2polylines::parse(svg)
-                .map_err(|e| HeadlessError::SvgParse(e))
-                .and_then(|mut polylines| {
-                    scaling::fit_polylines(&mut polylines, &bounds)
-                        .map_err(|e| HeadlessError::PolylineScale(e))?;
-                    Ok(polylines)
-                })
-        })
-        .collect::<Result<Vec<_>, HeadlessError>>()?;
 
     // Get access to queue
     let tx = robot_queue
@@ -431,8 +1115,7 @@ fn headless_start(robot_queue: RobotQueue, config: &Config) -> Result<(), Headle
         ))?;
 
     // Create print task
-    let interval_duration = Duration::from_secs(config.interval_seconds);
-    let task = PrintTask::Scheduled(interval_duration, polylines_set);
+    let task = PrintTask::Scheduled(interval_duration, content, config.erase_strategy);
 
     // Send task to robot
     tx.send(task)
@@ -458,12 +1141,17 @@ fn main() {
 
     // Init logger
     let log_level = if args.flag_debug { LevelFilter::Debug } else { LevelFilter::Info };
-    if let Err(_) = TermLogger::init(log_level, LogConfig::default(), TerminalMode::Mixed) {
+    if TermLogger::init(log_level, LogConfig::default(), TerminalMode::Mixed).is_err() {
         eprintln!("Could not initialize TermLogger. Falling back to SimpleLogger.");
         SimpleLogger::init(log_level, LogConfig::default())
             .expect("Could not initialize SimpleLogger");
     }
 
+    // Decoding doesn't need a config
+    if args.cmd_decode {
+        main_decode(&args.arg_capture);
+    }
+
     // Headless mode
     let headless_mode: bool = args.flag_headless;
 
@@ -476,12 +1164,188 @@ fn main() {
         error!("Could not parse configfile ({}): {}", &args.flag_c, e);
         abort(1);
     });
+    if let Err(e) = config.board.unwrap_or_default().validate() {
+        error!("Invalid board in configfile ({}): {}", &args.flag_c, e);
+        abort(1);
+    }
+    if let Some(Err(e)) = config.calibration.as_ref().map(Calibration::validate) {
+        error!("Invalid calibration in configfile ({}): {}", &args.flag_c, e);
+        abort(1);
+    }
+    if let Some(Err(e)) = config.erase_pattern.as_ref().map(SweepPattern::validate) {
+        error!("Invalid erase_pattern in configfile ({}): {}", &args.flag_c, e);
+        abort(1);
+    }
+    if let Some(Err(e)) = config.join_tolerance.map(optimize::validate_tolerance) {
+        error!("Invalid join_tolerance in configfile ({}): {}", &args.flag_c, e);
+        abort(1);
+    }
+    if let Some(Err(e)) = config.simplify_tolerance.map(simplify::validate_tolerance) {
+        error!("Invalid simplify_tolerance in configfile ({}): {}", &args.flag_c, e);
+        abort(1);
+    }
+    if let Some(Err(e)) = config.clock.as_ref().map(ClockConfig::validate) {
+        error!("Invalid clock in configfile ({}): {}", &args.flag_c, e);
+        abort(1);
+    }
+
+    // Block files don't need a robot for exporting
+    if args.cmd_export {
+        main_export(&config, &args.arg_drawing, &args.arg_blockfile);
+        return;
+    }
 
     // Check if this is an active config
-    match Config::from(&config) {
-        Some(c) => main_active(c, headless_mode),
-        None => main_preview(PreviewConfig::from(&config)),
+    match (Config::from(&config), args.cmd_replay) {
+        (Some(c), true) => main_replay(c, &args.arg_blockfile),
+        (None, true) => {
+            error!("Replaying needs a config with a device");
+            abort(1);
+        },
+        (Some(c), false) => main_active(c, headless_mode),
+        (None, false) => main_preview(PreviewConfig::from(&config)),
+    }
+}
+
+/// Check the device and launch the robot thread.
+fn start_robot(config: &Config) -> (Sender<PrintTask>, SharedState) {
+    let device_path = Path::new(&config.device);
+    if !transport::is_simulated(&config.device) && !device_path.exists() {
+        error!("Device {} does not exist", &config.device);
+        abort(2);
+    }
+
+    let baud_rate = BaudRate::Baud115200;
+    let events = Arc::new(EventBus::new());
+    let history = History::open(&config.history_file)
+        .unwrap_or_else(|e| {
+            error!("Could not open history file {}: {}", &config.history_file, e);
+            abort(2);
+        });
+    let robot_state = Arc::new(Mutex::new(RobotState::new(config.queue_policy, events, history, config.timing)));
+    let tx = robot::communicate(
+        &config.device,
+        baud_rate,
+        config.time_limits,
+        config.erase_pattern,
+        config.board,
+        config.calibration.clone(),
+        config.timing,
+        robot_state.clone(),
+    ).unwrap_or_else(|e| {
+        error!("Could not open device {}: {}", &config.device, e);
+        abort(2);
+    });
+    (tx, robot_state)
+}
+
+/// Write the blocks for a drawing file to a block file.
+fn main_export(config: &RawConfig, drawing: &str, blockfile: &str) {
+    let board = config.board.unwrap_or_default();
+    let mut polylines = load_file(Path::new(drawing), &board).unwrap_or_else(|e| {
+        error!("Could not load drawing: {}", e);
+        abort(2);
+    });
+    process_polylines(
+        &mut polylines,
+        config.join_tolerance,
+        config.simplify_tolerance,
+        config.optimize_paths.unwrap_or(false),
+        &board,
+    );
+    let calibration = config.calibration.clone().unwrap_or_default();
+    let blocks = Sketch::new(&polylines)
+        .with_erase_pattern(config.erase_pattern.unwrap_or_default())
+        .with_board(board)
+        .with_calibration(&calibration)
+        .into_blocks(Some(config.erase_strategy.unwrap_or_default()));
+
+    let result = File::create(blockfile)
+        .and_then(|file| blockfile::write_blocks(io::BufWriter::new(file), &blocks));
+    if let Err(e) = result {
+        error!("Could not write block file {}: {}", blockfile, e);
+        abort(2);
+    }
+    info!("Exported {} blocks to {}", blocks.len(), blockfile);
+}
+
+/// Print the commands of a captured byte stream, one per line. Exit with an
+/// error if the stream is malformed.
+fn main_decode(capture: &str) -> ! {
+    let mut bytes = vec![];
+    if let Err(e) = File::open(capture).and_then(|mut f| f.read_to_end(&mut bytes)) {
+        error!("Could not read {}: {}", capture, e);
+        abort(2);
+    }
+
+    let (mut blocks, mut commands, mut errors) = (0, 0, 0);
+    for (offset, result) in decode::decode(&bytes) {
+        let raw = bytes[offset..].iter().take(3)
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+        match result {
+            Ok(command) => {
+                if command == Command::BlockStart {
+                    blocks += 1;
+                    println!();
+                }
+                commands += 1;
+                println!("{:08x}  {:8}  {:?}", offset, raw, command);
+            },
+            Err(e) => {
+                errors += 1;
+                println!("{:08x}  {:8}  Error: {}", offset, raw, e);
+            },
+        }
+    }
+    println!();
+    println!("{} bytes, {} blocks, {} commands, {} errors", bytes.len(), blocks, commands, errors);
+    abort(if errors == 0 { 0 } else { 1 });
+}
+
+/// Send the blocks of a block file to the robot and wait until they are
+/// printed.
+fn main_replay(config: Config, blockfile: &str) {
+    let blocks = File::open(blockfile)
+        .and_then(blockfile::read_blocks)
+        .unwrap_or_else(|e| {
+            error!("Could not read block file {}: {}", blockfile, e);
+            abort(2);
+        });
+    info!("Replaying {} blocks from {}", blocks.len(), blockfile);
+
+    let (tx, robot_state) = start_robot(&config);
+    let last_id = robot_state.lock().expect("Could not lock robot state")
+        .jobs.history().entries().next().map(|entry| entry.id);
+    let drawing = Drawing::new(vec![], Source::Replay { file: blockfile.to_string() }, None);
+    if let Err(e) = tx.send(PrintTask::Blocks(drawing, blocks)) {
+        error!("Could not send blocks to robot thread: {}", e);
+        abort(3);
     }
+
+    // The job is finished once it's in the history
+    loop {
+        sleep(Duration::from_millis(500));
+        let state = robot_state.lock().expect("Could not lock robot state");
+        let history = state.jobs.history();
+        if let Some(entry) = finished_replay(history, last_id) {
+            info!("Replay finished: {:?}", entry.outcome);
+            history.flush();
+            if entry.outcome != JobState::Done {
+                abort(4);
+            }
+            return;
+        }
+    }
+}
+
+/// Return the history entry of a replay that was recorded after the entry
+/// with the id `last_id`.
+fn finished_replay(history: &History, last_id: Option<EntryId>) -> Option<&HistoryEntry> {
+    history.entries()
+        .take_while(|entry| Some(entry.id) > last_id)
+        .find(|entry| matches!(entry.source, Source::Replay { .. }))
 }
 
 /// Start the web server in active (printing) mode.
@@ -489,11 +1353,6 @@ fn main_active(config: Config, headless_mode: bool) {
     info!("Starting server in active mode (with robot attached)");
 
     // Check for presence of relevant paths
-    let device_path = Path::new(&config.device);
-    if !device_path.exists() {
-        error!("Device {} does not exist", &config.device);
-        abort(2);
-    }
     let static_dir_path = Path::new(&config.static_dir);
     if !static_dir_path.exists() || !static_dir_path.is_dir() {
         error!("Static files dir does not exist");
@@ -506,14 +1365,14 @@ fn main_active(config: Config, headless_mode: bool) {
     }
 
     // Launch robot thread
-    let baud_rate = BaudRate::Baud115200;
-    let tx = robot::communicate(&config.device, baud_rate, config.time_limits);
+    let (tx, robot_state) = start_robot(&config);
 
     // Initialize server state
     let robot_queue = Arc::new(Mutex::new(tx));
     let state = State {
         config: config.clone(),
         robot_queue: robot_queue.clone(),
+        robot_state,
     };
 
     // Print mode
@@ -539,8 +1398,22 @@ fn main_active(config: Config, headless_mode: bool) {
             .handler("/static", StaticFiles::new("static").unwrap())
             .route("/config/", Method::GET, config_handler)
             .route("/list/", Method::GET, list_handler)
-            .route("/preview/", Method::POST, preview_handler)
-            .resource("/print/", |r| r.method(Method::POST).with_async(print_handler));
+            .route("/status/", Method::GET, status_handler)
+            .route("/events/", Method::GET, events_handler)
+            .route("/cancel/", Method::POST, cancel_handler)
+            .route("/pause/", Method::POST, pause_handler)
+            .route("/resume/", Method::POST, resume_handler)
+            .route("/schedule/stop/", Method::POST, stop_schedule_handler)
+            .route("/history/", Method::GET, history_handler)
+            .route("/history/{id}/reprint/", Method::POST, reprint_handler)
+            .resource("/preview/", |r| r.method(Method::POST).with_config(preview_handler_active, |cfg| {
+                (cfg.0).1.limit(MAX_PAYLOAD_SIZE);
+            }))
+            .resource("/print/", |r| r.method(Method::POST).with_async(print_handler))
+            .resource("/export/", |r| r.method(Method::POST).with_async(export_handler))
+            .resource("/render/", |r| r.method(Method::POST).with_async(render_handler))
+            .resource("/print/text/", |r| r.method(Method::POST).with(print_text_handler))
+            .resource("/print/calibration/", |r| r.method(Method::POST).with(print_calibration_handler));
         if headless_mode {
             app = app.route("/", Method::GET, headless_handler);
         } else{
@@ -569,9 +1442,12 @@ fn main_preview(config: PreviewConfig) {
     let interface = config.listen.clone();
     info!("Listening on {}", interface);
     HttpServer::new(move || {
-        App::new()
+        App::with_state(config.clone())
             .handler("/static", StaticFiles::new(&config.static_dir).unwrap())
-            .route("/preview/", Method::POST, preview_handler)
+            .route("/config/", Method::GET, config_handler_preview)
+            .resource("/preview/", |r| r.method(Method::POST).with_config(preview_handler_preview, |cfg| {
+                (cfg.0).1.limit(MAX_PAYLOAD_SIZE);
+            }))
             .route("/", Method::GET, index_handler_preview)
     })
         .bind(interface)
@@ -591,32 +1467,254 @@ fn abort(exit_code: i32) -> ! {
 
 #[cfg(test)]
 mod tests {
+    use calibration::Affine;
+    use history::MAX_ENTRIES;
+    use jobs::{Job, JobKind};
+
     use super::*;
 
     #[test]
+    #[allow(clippy::redundant_pattern)]
     fn print_mode_to_print_task_once() {
         let mode = PrintMode::Once;
-        let polylines = vec![];
-        match mode.to_print_task(polylines.clone()) {
-            PrintTask::Once(p) => assert_eq!(p, polylines),
+        let drawing = Drawing::new(vec![], Source::Api, None);
+        match mode.to_print_task(drawing.clone(), EraseStrategy::Full) {
+            PrintTask::Once(d, e) => {
+                assert_eq!(d, drawing);
+                assert_eq!(e, EraseStrategy::Full);
+            },
             t @ _ => panic!("Task was {:?}", t),
         }
     }
 
     #[test]
+    #[allow(clippy::redundant_pattern)]
     fn print_mode_to_print_task_every() {
         let mode = PrintMode::Schedule5;
-        let polylines = vec![];
-        match mode.to_print_task(polylines.clone()) {
-            PrintTask::Scheduled(d, p) => {
+        let drawing = Drawing::new(vec![], Source::Api, None);
+        match mode.to_print_task(drawing.clone(), EraseStrategy::Full) {
+            PrintTask::Scheduled(d, ScheduledContent::Drawings(p), _) => {
                 assert_eq!(d, Duration::from_secs(60 * 5));
-                assert_eq!(p, vec![polylines]);
+                assert_eq!(p, vec![drawing]);
             },
             t @ _ => panic!("Task was {:?}", t),
         }
     }
 
     #[test]
+    fn export_request() {
+        let request: ExportRequest = serde_json::from_str(r#"{
+            "hpgl": "IN;PU0,0;PD400,0,400,400;",
+            "offset_x": 10,
+            "offset_y": 20,
+            "scale_x": 2,
+            "scale_y": 2,
+            "optimize": true
+        }"#).unwrap();
+        assert!(request.params.optimize);
+        assert_eq!(request.erase_strategy, None);
+        let polylines = prepare_polylines(&request.input, &request.params, &Board::default()).unwrap();
+        let ends: Vec<_> = polylines[0].iter().map(|p| (p.x, p.y)).collect();
+        assert_eq!(ends, vec![(10.0, 40.0), (30.0, 40.0), (30.0, 20.0)]);
+
+        let blocks = Sketch::new(&polylines).into_blocks(Some(EraseStrategy::Full));
+        let mut file = vec![];
+        blockfile::write_blocks(&mut file, &blocks).unwrap();
+        assert_eq!(blockfile::read_blocks(&file[..]).unwrap(), blocks);
+    }
+
+    #[test]
+    fn invalid_tolerance() {
+        for tolerance in &[r#""simplify_tolerance": -1"#, r#""join_tolerance": -1"#] {
+            let export: ExportRequest = serde_json::from_str(&format!(r#"{{
+                "hpgl": "IN;PU0,0;PD400,0,400,400;",
+                "offset_x": 0,
+                "offset_y": 0,
+                "scale_x": 1,
+                "scale_y": 1,
+                {}
+            }}"#, tolerance)).unwrap();
+            assert!(prepare_polylines(&export.input, &export.params, &Board::default()).is_err());
+        }
+
+        let preview_request: PreviewRequest = serde_json::from_str(r#"{
+            "hpgl": "IN;PU0,0;PD400,0,400,400;",
+            "simplify_tolerance": -1
+        }"#).unwrap();
+        let timing = TimingModel::default();
+        assert!(preview(&preview_request, &Board::default(), &Calibration::default(), EraseStrategy::Full, SweepPattern::default(), &timing).is_err());
+    }
+
+    #[test]
+    fn invalid_transform() {
+        let export = |params: &str| -> ExportRequest {
+            serde_json::from_str(&format!(r#"{{
+                "hpgl": "IN;PU0,0;PD400,0,400,400;",
+                "offset_x": 0,
+                "offset_y": 0,
+                {}
+            }}"#, params)).unwrap()
+        };
+        let board = Board::default();
+        for params in &[
+            r#""scale_x": 0, "scale_y": 1"#,
+            r#""scale_x": 1, "scale_y": -1"#,
+            r#""scale_x": 1, "scale_y": 1, "matrix": [[1, 2, 0], [2, 4, 0]]"#,
+        ] {
+            let request = export(params);
+            assert!(prepare_polylines(&request.input, &request.params, &board).is_err(), "{}", params);
+        }
+        assert!(placement(f64::NAN, 1.0, 0.0, 0.0).is_err());
+        assert!(placement(1.0, 1.0, f64::INFINITY, 0.0).is_err());
+
+        let preview_request: PreviewRequest = serde_json::from_str(r#"{
+            "hpgl": "IN;PU0,0;PD400,0,400,400;",
+            "scale_x": -1
+        }"#).unwrap();
+        let timing = TimingModel::default();
+        assert!(preview(&preview_request, &board, &Calibration::default(), EraseStrategy::Full, SweepPattern::default(), &timing).is_err());
+    }
+
+    #[test]
+    fn print_request_orientation() {
+        let request: ExportRequest = serde_json::from_str(r#"{
+            "hpgl": "IN;PU0,0;PD400,0,400,400;",
+            "offset_x": 10,
+            "offset_y": 20,
+            "scale_x": 2,
+            "scale_y": 2,
+            "rotation": 90
+        }"#).unwrap();
+        assert_eq!(request.params.orientation.rotation, 90.0);
+        let polylines = prepare_polylines(&request.input, &request.params, &Board::default()).unwrap();
+        let expected = [(10.0, 20.0), (10.0, 40.0), (30.0, 40.0)];
+        for (point, &(x, y)) in polylines[0].iter().zip(&expected) {
+            assert!((point.x - x).abs() < 1e-9 && (point.y - y).abs() < 1e-9, "{:?}", polylines);
+        }
+
+        // The preview shows the same orientation, before scaling
+        let preview_request: PreviewRequest = serde_json::from_str(r#"{
+            "hpgl": "IN;PU0,0;PD400,0,400,400;",
+            "mirror_y": true,
+            "matrix": [[1, 0, 5], [0, 1, 0]]
+        }"#).unwrap();
+        let timing = TimingModel::default();
+        let response = preview(&preview_request, &Board::default(), &Calibration::default(), EraseStrategy::Full, SweepPattern::default(), &timing).unwrap();
+        let ends: Vec<_> = response.polylines[0].iter().map(|p| (p.x, p.y)).collect();
+        assert_eq!(ends, vec![(5.0, 0.0), (15.0, 0.0), (15.0, 10.0)]);
+
+        let invalid: PreviewRequest = serde_json::from_str(r#"{
+            "hpgl": "IN;PU0,0;PD400,0,400,400;",
+            "matrix": [[0, 0, 0], [0, 1, 0]]
+        }"#).unwrap();
+        assert!(preview(&invalid, &Board::default(), &Calibration::default(), EraseStrategy::Full, SweepPattern::default(), &timing).is_err());
+    }
+
+    #[test]
+    fn preview_estimate() {
+        let request = |scale: f64| -> PreviewRequest {
+            serde_json::from_str(&format!(r#"{{
+                "hpgl": "IN;PU0,0;PD400,0,400,400;",
+                "scale_x": {0},
+                "scale_y": {0},
+                "erase_strategy": {{"type": "bounding_box"}}
+            }}"#, scale)).unwrap()
+        };
+        let timing = TimingModel::default();
+        let small = preview(&request(1.0), &Board::default(), &Calibration::default(), EraseStrategy::Full, SweepPattern::default(), &timing).unwrap();
+        let large = preview(&request(10.0), &Board::default(), &Calibration::default(), EraseStrategy::Full, SweepPattern::default(), &timing).unwrap();
+
+        // The polylines stay in drawing coordinates, the estimate depends on
+        // the transformation
+        assert_eq!(small.polylines, large.polylines);
+        assert!((large.estimate.drawing - small.estimate.drawing * 10.0).abs() < 1.0);
+        assert!(large.estimate.erasing > small.estimate.erasing);
+    }
+
+    #[test]
+    fn preview_estimate_matches_print() {
+        // Three strokes in an order with a lot of pen-up travel, two of them
+        // share an endpoint
+        let json = r#"{
+            "hpgl": "IN;PU0,0;PD400,0;PU4000,0;PD3600,0;PU400,0;PD400,400;",
+            "offset_x": 10,
+            "offset_y": 20,
+            "scale_x": 1,
+            "scale_y": 1,
+            "optimize": true,
+            "join_tolerance": 0.5
+        }"#;
+        let board = Board::default();
+        let calibration = Calibration::default();
+        let timing = TimingModel::default();
+        let preview_request: PreviewRequest = serde_json::from_str(json).unwrap();
+        let preview_estimate = preview(&preview_request, &board, &calibration, EraseStrategy::Full, SweepPattern::default(), &timing)
+            .unwrap().estimate.clone();
+
+        let print_request: ExportRequest = serde_json::from_str(json).unwrap();
+        let polylines = prepare_polylines(&print_request.input, &print_request.params, &board).unwrap();
+        let print_estimate = estimate::estimate_polylines(&polylines, &board, &calibration, EraseStrategy::Full, SweepPattern::default(), &timing);
+        assert_eq!(preview_estimate, print_estimate);
+
+        // Without joining and reordering, the pen travels more
+        let unprocessed: PreviewRequest = serde_json::from_str(&json.replace("true", "false")).unwrap();
+        let unprocessed = preview(&unprocessed, &board, &calibration, EraseStrategy::Full, SweepPattern::default(), &timing).unwrap();
+        assert!(unprocessed.estimate.travel > preview_estimate.travel);
+    }
+
+    #[test]
+    fn preview_clipping() {
+        // A 200 x 200 mm corner on the 358 x 123 mm board. HPGL is flipped,
+        // so the horizontal line is entirely below the board.
+        let request: PreviewRequest = serde_json::from_str(r#"{
+            "hpgl": "IN;PU0,0;PD400,0,400,400;",
+            "scale_x": 20,
+            "scale_y": 20
+        }"#).unwrap();
+        let timing = TimingModel::default();
+        let response = preview(&request, &Board::default(), &Calibration::default(), EraseStrategy::Full, SweepPattern::default(), &timing).unwrap();
+        assert_eq!(response.clipping.clipped_polylines, 1);
+        assert!((response.clipping.total_length - 400.0).abs() < 1e-6);
+        assert!((response.clipping.clipped_length - 277.0).abs() < 1e-6);
+
+        // Clipping applies to what the robot draws, after the calibration
+        let calibration = Calibration {
+            affine: Affine { offset_y: -100.0, ..Affine::default() },
+            grid: None,
+        };
+        let response = preview(&request, &Board::default(), &calibration, EraseStrategy::Full, SweepPattern::default(), &timing).unwrap();
+        assert!((response.clipping.clipped_length - 100.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn replay_with_full_history() {
+        let record = |history: &mut History, source: Source| {
+            let drawing = Drawing::new(vec![], source, None);
+            let mut job = Job::new(JobKind::Once, drawing, vec![vec![1]], &TimingModel::default());
+            job.state = JobState::Done;
+            history.record(&job, vec![]);
+        };
+        let mut history = History::in_memory();
+        for _ in 0..MAX_ENTRIES {
+            record(&mut history, Source::Api);
+        }
+        let last_id = history.entries().next().map(|entry| entry.id);
+        assert!(finished_replay(&history, last_id).is_none());
+
+        // Other jobs may finish before the replay
+        record(&mut history, Source::Api);
+        assert!(finished_replay(&history, last_id).is_none());
+        record(&mut history, Source::Replay { file: "a.ibb".into() });
+        assert_eq!(history.entries().count(), MAX_ENTRIES);
+        assert_eq!(finished_replay(&history, last_id).unwrap().id, MAX_ENTRIES + 2);
+
+        let mut history = History::in_memory();
+        record(&mut history, Source::Replay { file: "a.ibb".into() });
+        assert_eq!(finished_replay(&history, None).unwrap().id, 1);
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn time_limits_simple() {
         let limits = TimeLimits {
             start_time: (8, 0),
@@ -647,6 +1745,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn time_limits_complex() {
         let limits = TimeLimits {
             start_time: (22, 0),
@@ -0,0 +1,367 @@
+//! Code for reducing the pen-up travel between polylines.
+use std::cmp;
+use std::collections::HashMap;
+
+use svg2polylines::{CoordinatePair, Polyline};
+
+/// Maximum number of 2-opt improvement passes.
+const MAX_PASSES: usize = 20;
+
+/// Maximum length of a run of polylines reversed by a single 2-opt move.
+/// This keeps the runtime bounded for drawings with many polylines.
+const MAX_RUN: usize = 500;
+
+/// A polyline in the drawing order, possibly reversed.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    index: usize,
+    reversed: bool,
+}
+
+#[inline]
+fn distance(a: CoordinatePair, b: CoordinatePair) -> f64 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
+
+/// Return the first and last point of a non-empty polyline.
+fn endpoints(polyline: &Polyline) -> Option<(CoordinatePair, CoordinatePair)> {
+    match (polyline.first(), polyline.last()) {
+        (Some(first), Some(last)) => Some((*first, *last)),
+        _ => None,
+    }
+}
+
+/// Return the total distance travelled with the pen up, starting and ending
+/// at the home position (in board coordinates).
+pub fn pen_up_distance(polylines: &[Polyline], home: CoordinatePair) -> f64 {
+    let mut position = home;
+    let mut total = 0.0;
+    for (start, end) in polylines.iter().filter_map(endpoints) {
+        total += distance(position, start);
+        position = end;
+    }
+    total + distance(position, home)
+}
+
+/// Order the polylines greedily: Always continue with the polyline whose
+/// start or end point is closest to the current position.
+fn nearest_neighbour(ends: &[(CoordinatePair, CoordinatePair)], home: CoordinatePair) -> Vec<Entry> {
+    let mut visited = vec![false; ends.len()];
+    let mut order = Vec::with_capacity(ends.len());
+    let mut position = home;
+    for _ in 0..ends.len() {
+        let mut best: Option<(f64, Entry)> = None;
+        for (index, &(start, end)) in ends.iter().enumerate() {
+            if visited[index] {
+                continue;
+            }
+            for &(point, reversed) in &[(start, false), (end, true)] {
+                let d = distance(position, point);
+                match best {
+                    Some((best_d, _)) if best_d <= d => {},
+                    _ => best = Some((d, Entry { index, reversed })),
+                }
+            }
+        }
+        let (_, entry) = best.expect("Unvisited polyline must exist");
+        visited[entry.index] = true;
+        position = if entry.reversed { ends[entry.index].0 } else { ends[entry.index].1 };
+        order.push(entry);
+    }
+    order
+}
+
+/// Improve the order with 2-opt moves.
+///
+/// A move reverses a contiguous run of polylines, which also reverses the
+/// drawing direction of every polyline in that run.
+fn two_opt(ends: &[(CoordinatePair, CoordinatePair)], order: &mut [Entry], home: CoordinatePair) {
+    let start_of = |e: &Entry| if e.reversed { ends[e.index].1 } else { ends[e.index].0 };
+    let end_of = |e: &Entry| if e.reversed { ends[e.index].0 } else { ends[e.index].1 };
+    let n = order.len();
+    for _ in 0..MAX_PASSES {
+        let mut improved = false;
+        for i in 0..n {
+            let before = if i == 0 { home } else { end_of(&order[i - 1]) };
+            for j in i..cmp::min(n, i + MAX_RUN) {
+                let after = if j + 1 == n { home } else { start_of(&order[j + 1]) };
+                let old = distance(before, start_of(&order[i])) + distance(end_of(&order[j]), after);
+                let new = distance(before, end_of(&order[j])) + distance(start_of(&order[i]), after);
+                if new + 1e-9 < old {
+                    order[i..=j].reverse();
+                    for entry in &mut order[i..=j] {
+                        entry.reversed = !entry.reversed;
+                    }
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+}
+
+/// Reorder (and possibly reverse) the polylines to reduce the distance the
+/// robot travels with the pen up.
+///
+/// The robot starts and ends at `home` (in board coordinates). Polylines are
+/// first ordered using a nearest-neighbour heuristic, the result is then
+/// improved using 2-opt.
+pub fn optimize_order(polylines: &mut Vec<Polyline>, home: CoordinatePair) {
+    let before = pen_up_distance(polylines, home);
+
+    // Empty polylines are never drawn, so they may be dropped
+    polylines.retain(|p| !p.is_empty());
+    let ends: Vec<_> = polylines.iter().filter_map(endpoints).collect();
+    let mut order = nearest_neighbour(&ends, home);
+    two_opt(&ends, &mut order, home);
+
+    let mut unordered: Vec<Option<Polyline>> = polylines.drain(..).map(Some).collect();
+    for entry in order {
+        let mut polyline = unordered[entry.index].take().expect("Polyline used twice");
+        if entry.reversed {
+            polyline.reverse();
+        }
+        polylines.push(polyline);
+    }
+
+    let after = pen_up_distance(polylines, home);
+    info!("Optimized pen-up travel from {:.1} mm to {:.1} mm", before, after);
+}
+
+/// A spatial index over the endpoints of polylines.
+///
+/// Endpoints are sorted into square cells with the size of the tolerance, so
+/// only neighbouring cells need to be searched.
+struct EndpointIndex {
+    cell_size: f64,
+    cells: HashMap<(i64, i64), Vec<(usize, bool)>>,
+}
+
+impl EndpointIndex {
+    fn new(polylines: &[Polyline], tolerance: f64) -> Self {
+        let mut index = EndpointIndex {
+            // Avoid huge cell coordinates for tiny tolerances
+            cell_size: tolerance.max(1e-3),
+            cells: HashMap::new(),
+        };
+        for (i, (start, end)) in polylines.iter().filter_map(endpoints).enumerate() {
+            index.cells.entry(index.cell(start)).or_default().push((i, false));
+            index.cells.entry(index.cell(end)).or_default().push((i, true));
+        }
+        index
+    }
+
+    fn cell(&self, point: CoordinatePair) -> (i64, i64) {
+        ((point.x / self.cell_size).floor() as i64, (point.y / self.cell_size).floor() as i64)
+    }
+
+    /// Find the unused polyline with an endpoint closest to `point`.
+    ///
+    /// Return the index of the polyline and whether its end point (rather
+    /// than its start point) matched.
+    fn find(
+        &self,
+        point: CoordinatePair,
+        polylines: &[Polyline],
+        used: &[bool],
+        tolerance: f64,
+    ) -> Option<(usize, bool)> {
+        let (cx, cy) = self.cell(point);
+        let mut best: Option<(f64, (usize, bool))> = None;
+        for x in cx - 1..=cx + 1 {
+            for y in cy - 1..=cy + 1 {
+                let candidates = match self.cells.get(&(x, y)) {
+                    Some(candidates) => candidates,
+                    None => continue,
+                };
+                for &(i, is_end) in candidates {
+                    if used[i] {
+                        continue;
+                    }
+                    let polyline = &polylines[i];
+                    let candidate = if is_end { polyline[polyline.len() - 1] } else { polyline[0] };
+                    let d = distance(point, candidate);
+                    if d > tolerance {
+                        continue;
+                    }
+                    match best {
+                        Some((best_d, _)) if best_d <= d => {},
+                        _ => best = Some((d, (i, is_end))),
+                    }
+                }
+            }
+        }
+        best.map(|(_, found)| found)
+    }
+}
+
+/// Check that a join tolerance is a finite, non-negative number.
+pub fn validate_tolerance(tolerance: f64) -> Result<(), String> {
+    if !(tolerance.is_finite() && tolerance >= 0.0) {
+        return Err("Join tolerance must be a non-negative number".to_string());
+    }
+    Ok(())
+}
+
+/// Join polylines whose endpoints are within `tolerance` of each other into
+/// continuous polylines. Polylines are reversed where needed.
+pub fn join_polylines(polylines: &mut Vec<Polyline>, tolerance: f64) {
+    let before = polylines.len();
+    polylines.retain(|p| !p.is_empty());
+    let index = EndpointIndex::new(polylines, tolerance);
+    let mut used = vec![false; polylines.len()];
+
+    let mut joined = Vec::with_capacity(polylines.len());
+    for i in 0..polylines.len() {
+        if used[i] {
+            continue;
+        }
+        used[i] = true;
+        let mut chain = polylines[i].clone();
+
+        // Extend the chain at its end
+        while let Some((j, is_end)) = index.find(chain[chain.len() - 1], polylines, &used, tolerance) {
+            used[j] = true;
+            if is_end {
+                chain.extend(polylines[j].iter().rev().skip(1));
+            } else {
+                chain.extend(polylines[j].iter().skip(1));
+            }
+        }
+
+        // Extend the chain at its start
+        while let Some((j, is_end)) = index.find(chain[0], polylines, &used, tolerance) {
+            used[j] = true;
+            let mut prefix = polylines[j].clone();
+            if !is_end {
+                prefix.reverse();
+            }
+            prefix.pop();
+            prefix.extend(chain);
+            chain = prefix;
+        }
+
+        joined.push(chain);
+    }
+
+    info!("Joined {} polylines into {}", before, joined.len());
+    *polylines = joined;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The bottom left corner of the original iBoardBot.
+    const HOME: CoordinatePair = CoordinatePair { x: 0.0, y: 123.0 };
+
+    fn line(x1: f64, y1: f64, x2: f64, y2: f64) -> Polyline {
+        vec![CoordinatePair { x: x1, y: y1 }, CoordinatePair { x: x2, y: y2 }]
+    }
+
+    #[test]
+    fn test_pen_up_distance() {
+        let y = HOME.y;
+        let polylines = vec![line(3.0, y - 4.0, 10.0, y - 4.0)];
+        assert_eq!(pen_up_distance(&polylines, HOME), 5.0 + (100.0f64 + 16.0).sqrt());
+        assert_eq!(pen_up_distance(&[], HOME), 0.0);
+    }
+
+    #[test]
+    fn test_optimize_order() {
+        let y = HOME.y;
+        let mut polylines = vec![
+            line(30.0, y, 40.0, y),
+            line(20.0, y, 10.0, y),
+            line(50.0, y, 60.0, y),
+        ];
+        optimize_order(&mut polylines, HOME);
+        assert_eq!(polylines, vec![
+            line(10.0, y, 20.0, y),
+            line(30.0, y, 40.0, y),
+            line(50.0, y, 60.0, y),
+        ]);
+    }
+
+    #[test]
+    fn test_two_opt_single_flip() {
+        let y = HOME.y;
+        let polylines = [
+            line(0.0, y, 10.0, y),
+            line(10.0, y - 10.0, 10.0, y - 1.0),
+        ];
+        let ends: Vec<_> = polylines.iter().filter_map(endpoints).collect();
+        let mut order = vec![
+            Entry { index: 0, reversed: false },
+            Entry { index: 1, reversed: false },
+        ];
+
+        // Reversing both polylines doesn't help, only flipping the second
+        two_opt(&ends, &mut order, HOME);
+        let order: Vec<_> = order.iter().map(|e| (e.index, e.reversed)).collect();
+        assert_eq!(order, vec![(0, false), (1, true)]);
+    }
+
+    #[test]
+    fn test_optimize_order_never_worse() {
+        let mut polylines = vec![];
+        for i in 0..30 {
+            let x = f64::from((i * 37) % 300);
+            let y = f64::from((i * 53) % 120);
+            polylines.push(line(x, y, x + 5.0, y + 2.0));
+        }
+        let before = pen_up_distance(&polylines, HOME);
+        let count = polylines.len();
+        optimize_order(&mut polylines, HOME);
+        assert_eq!(polylines.len(), count);
+        assert!(pen_up_distance(&polylines, HOME) < before);
+    }
+
+    #[test]
+    fn test_join_polylines() {
+        let mut polylines = vec![
+            line(10.0, 0.0, 20.0, 0.0),
+            line(50.0, 50.0, 60.0, 50.0),
+            line(20.05, 0.0, 20.0, 10.0),
+            line(0.0, 0.0, 10.0, 0.0),
+        ];
+        join_polylines(&mut polylines, 0.1);
+        assert_eq!(polylines, vec![
+            vec![
+                CoordinatePair { x: 0.0, y: 0.0 },
+                CoordinatePair { x: 10.0, y: 0.0 },
+                CoordinatePair { x: 20.0, y: 0.0 },
+                CoordinatePair { x: 20.0, y: 10.0 },
+            ],
+            line(50.0, 50.0, 60.0, 50.0),
+        ]);
+    }
+
+    #[test]
+    fn test_join_polylines_reversed() {
+        let mut polylines = vec![
+            line(0.0, 0.0, 10.0, 0.0),
+            line(10.0, 10.0, 10.0, 0.0),
+        ];
+        join_polylines(&mut polylines, 0.1);
+        assert_eq!(polylines, vec![
+            vec![
+                CoordinatePair { x: 0.0, y: 0.0 },
+                CoordinatePair { x: 10.0, y: 0.0 },
+                CoordinatePair { x: 10.0, y: 10.0 },
+            ],
+        ]);
+    }
+
+    #[test]
+    fn test_validate_tolerance() {
+        assert!(validate_tolerance(0.0).is_ok());
+        assert!(validate_tolerance(0.5).is_ok());
+        assert!(validate_tolerance(-0.5).is_err());
+        assert!(validate_tolerance(f64::NAN).is_err());
+        assert!(validate_tolerance(f64::INFINITY).is_err());
+    }
+}
@@ -0,0 +1,443 @@
+//! Parsers for the file formats of pen plotters: G-code and HPGL.
+//!
+//! Only the pen movements are interpreted. Every pen-down stroke becomes a
+//! polyline, pen-up moves start a new one. Both formats use a y axis that
+//! points up, so the drawings are flipped vertically to match SVG input.
+use std::f64::consts::PI;
+use std::iter::Peekable;
+use std::mem;
+use std::str::Chars;
+
+use svg2polylines::{CoordinatePair, Polyline};
+
+use scaling;
+
+const MM_PER_INCH: f64 = 25.4;
+
+/// HPGL plotter units per millimeter.
+const HPGL_UNITS_PER_MM: f64 = 40.0;
+
+/// Maximum length of the segments that approximate a G-code arc, in
+/// millimeters.
+const ARC_SEGMENT_LENGTH: f64 = 0.5;
+
+/// Collects the strokes of the pen.
+struct Pen {
+    position: CoordinatePair,
+    polylines: Vec<Polyline>,
+    stroke: Polyline,
+}
+
+impl Pen {
+    fn new() -> Self {
+        Pen {
+            position: CoordinatePair { x: 0.0, y: 0.0 },
+            polylines: vec![],
+            stroke: vec![],
+        }
+    }
+
+    /// Lift the pen, ending the current stroke.
+    fn lift(&mut self) {
+        let stroke = mem::take(&mut self.stroke);
+        if stroke.len() > 1 {
+            self.polylines.push(stroke);
+        }
+    }
+
+    /// Move the pen, drawing a line if it is down.
+    fn move_to(&mut self, target: CoordinatePair, down: bool) {
+        if down {
+            if self.stroke.is_empty() {
+                self.stroke.push(self.position);
+            }
+            self.stroke.push(target);
+        } else {
+            self.lift();
+        }
+        self.position = target;
+    }
+
+    fn finish(mut self) -> Vec<Polyline> {
+        self.lift();
+        scaling::flip_vertically(&mut self.polylines);
+        self.polylines
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Motion {
+    Rapid,
+    Linear,
+    ArcClockwise,
+    ArcCounterclockwise,
+}
+
+/// Read a number, e.g. `-1.5`, skipping leading whitespace.
+fn read_number(chars: &mut Peekable<Chars>) -> Result<f64, String> {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+    let mut number = String::new();
+    while let Some(&c) = chars.peek() {
+        if !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+') {
+            break;
+        }
+        number.push(c);
+        chars.next();
+    }
+    number.parse().map_err(|_| format!("Invalid number {:?}", number))
+}
+
+/// Split a line of G-code into words, e.g. `G1 X10 (move) Y2.5` into
+/// `[('G', 1.0), ('X', 10.0), ('Y', 2.5)]`.
+fn gcode_words(line: &str) -> Result<Vec<(char, f64)>, String> {
+    let mut words = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // Comments
+            ';' => break,
+            '(' => {
+                chars.by_ref().find(|&c| c == ')');
+            },
+            // Checksum
+            '*' => break,
+            // Start and end of the program
+            '%' => {},
+            c if c.is_whitespace() => {},
+            c if c.is_ascii_alphabetic() => {
+                let value = read_number(&mut chars)
+                    .map_err(|e| format!("{} after {}", e, c))?;
+                words.push((c.to_ascii_uppercase(), value));
+            },
+            c => return Err(format!("Unexpected character {:?}", c)),
+        }
+    }
+    Ok(words)
+}
+
+/// Approximate an arc from the current position of the pen to the target
+/// with line segments. The returned points exclude the start.
+fn arc(start: CoordinatePair, target: CoordinatePair, center: CoordinatePair, clockwise: bool) -> Vec<CoordinatePair> {
+    let radius = (start.x - center.x).hypot(start.y - center.y);
+    let start_angle = (start.y - center.y).atan2(start.x - center.x);
+    let end_angle = (target.y - center.y).atan2(target.x - center.x);
+
+    // An arc that ends where it starts is a full circle
+    let full_circle = (target.x - start.x).hypot(target.y - start.y) < 1e-9;
+    let mut sweep = end_angle - start_angle;
+    if clockwise && (sweep >= 0.0 || full_circle) {
+        sweep -= 2.0 * PI;
+    } else if !clockwise && (sweep <= 0.0 || full_circle) {
+        sweep += 2.0 * PI;
+    }
+    if full_circle {
+        sweep = sweep.signum() * 2.0 * PI;
+    }
+
+    let segments = ((sweep.abs() * radius / ARC_SEGMENT_LENGTH).ceil() as usize).max(1);
+    let mut points: Vec<CoordinatePair> = (1..segments)
+        .map(|i| {
+            let angle = start_angle + sweep * i as f64 / segments as f64;
+            CoordinatePair {
+                x: center.x + radius * angle.cos(),
+                y: center.y + radius * angle.sin(),
+            }
+        })
+        .collect();
+    points.push(target);
+    points
+}
+
+/// Parse G-code into polylines (in millimeters).
+///
+/// Supported are linear moves (`G0`, `G1`), arcs with a center (`G2`, `G3`
+/// with `I` and `J`), homing (`G28`), inches and millimeters (`G20`, `G21`)
+/// and absolute and relative coordinates (`G90`, `G91`). Other modal codes
+/// (e.g. plane or work offset selection) are ignored. The pen is down if
+/// `Z` is at most 0 or after `M3` (until `M5`). If a file controls the pen
+/// neither way, `G1` moves draw and `G0` moves travel.
+pub fn parse_gcode(gcode: &str) -> Result<Vec<Polyline>, String> {
+    let mut pen = Pen::new();
+    let mut motion = Motion::Rapid;
+    let mut absolute = true;
+    let mut unit = 1.0;
+    let mut z = 0.0;
+    let mut pen_down = None;
+
+    for (number, line) in gcode.lines().enumerate() {
+        let words = gcode_words(line)
+            .map_err(|e| format!("Line {}: {}", number + 1, e))?;
+
+        let (mut x, mut y, mut i, mut j, mut new_z) = (None, None, None, None, None);
+        let mut home = false;
+        let mut skip_move = false;
+        for (letter, value) in words {
+            match letter {
+                'G' if value.fract() == 0.0 => match value as i64 {
+                    0 => motion = Motion::Rapid,
+                    1 => motion = Motion::Linear,
+                    2 => motion = Motion::ArcClockwise,
+                    3 => motion = Motion::ArcCounterclockwise,
+                    20 => unit = MM_PER_INCH,
+                    21 => unit = 1.0,
+                    28 => home = true,
+                    90 => absolute = true,
+                    91 => absolute = false,
+                    // Coordinates of dwelling, setting offsets, moving to
+                    // the second home and setting the position are not moves
+                    4 | 10 | 30 | 92 => skip_move = true,
+                    _ => {},
+                },
+                'M' if value.fract() == 0.0 => match value as i64 {
+                    3 | 4 => pen_down = Some(true),
+                    5 => {
+                        pen_down = Some(false);
+                        pen.lift();
+                    },
+                    _ => {},
+                },
+                'X' => x = Some(value),
+                'Y' => y = Some(value),
+                'Z' => new_z = Some(value),
+                'I' => i = Some(value),
+                'J' => j = Some(value),
+                'R' => return Err(format!("Line {}: Arcs with a radius are not supported", number + 1)),
+                _ => {},
+            }
+        }
+
+        // Units apply to the whole line, even if they are changed after the
+        // coordinates
+        let (x, y, i, j, new_z) = (
+            x.map(|v| v * unit), y.map(|v| v * unit),
+            i.map(|v| v * unit), j.map(|v| v * unit),
+            new_z.map(|v| v * unit),
+        );
+
+        if let Some(value) = new_z {
+            z = if absolute { value } else { z + value };
+            pen_down = Some(z <= 0.0);
+            if z > 0.0 {
+                pen.lift();
+            }
+        }
+        if home {
+            pen.move_to(CoordinatePair { x: 0.0, y: 0.0 }, false);
+            continue;
+        }
+        let is_arc = motion == Motion::ArcClockwise || motion == Motion::ArcCounterclockwise;
+        if skip_move || !(x.is_some() || y.is_some() || (is_arc && (i.is_some() || j.is_some()))) {
+            continue;
+        }
+
+        let start = pen.position;
+        let target = if absolute {
+            CoordinatePair { x: x.unwrap_or(start.x), y: y.unwrap_or(start.y) }
+        } else {
+            CoordinatePair { x: start.x + x.unwrap_or(0.0), y: start.y + y.unwrap_or(0.0) }
+        };
+        let down = pen_down.unwrap_or(motion != Motion::Rapid);
+        if is_arc {
+            let center = CoordinatePair { x: start.x + i.unwrap_or(0.0), y: start.y + j.unwrap_or(0.0) };
+            for point in arc(start, target, center, motion == Motion::ArcClockwise) {
+                pen.move_to(point, down);
+            }
+        } else {
+            pen.move_to(target, down);
+        }
+    }
+
+    Ok(pen.finish())
+}
+
+/// Parse HPGL into polylines (in millimeters).
+///
+/// Supported are pen up and down (`PU`, `PD`), absolute and relative
+/// coordinates (`PA`, `PR`), pen selection (`SP`, where pen 0 means no pen)
+/// and initialization (`IN`, `DF`). Other instructions are ignored.
+pub fn parse_hpgl(hpgl: &str) -> Result<Vec<Polyline>, String> {
+    let mut pen = Pen::new();
+    let mut absolute = true;
+    let mut down = false;
+
+    let mut chars = hpgl.chars().peekable();
+    loop {
+        // Instructions are separated by semicolons, but the separator is
+        // optional
+        while chars.peek().is_some_and(|&c| c == ';' || c == ',' || c.is_whitespace()) {
+            chars.next();
+        }
+        let first = match chars.next() {
+            Some(c) => c,
+            None => break,
+        };
+
+        // Device control sequences of plotters and cutters
+        if first == '\x1b' {
+            chars.by_ref().find(|&c| c == ':' || c == ';');
+            continue;
+        }
+
+        let second = chars.next().unwrap_or(' ');
+        if !first.is_ascii_alphabetic() || !second.is_ascii_alphabetic() {
+            return Err(format!("Invalid instruction {:?}", format!("{}{}", first, second)));
+        }
+        let mnemonic = format!("{}{}", first, second).to_ascii_uppercase();
+
+        // Labels contain text up to the terminator
+        if mnemonic == "LB" {
+            chars.by_ref().find(|&c| c == '\x03');
+            continue;
+        }
+
+        let mut parameters = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_ascii_alphabetic() || c == ';' || c == '\x1b' {
+                break;
+            }
+            parameters.push(c);
+            chars.next();
+        }
+
+        match mnemonic.as_str() {
+            "IN" | "DF" => {
+                pen.lift();
+                absolute = true;
+                down = false;
+            },
+            "PU" | "PD" | "PA" | "PR" => {
+                match mnemonic.as_str() {
+                    "PU" => {
+                        down = false;
+                        pen.lift();
+                    },
+                    "PD" => down = true,
+                    "PA" => absolute = true,
+                    _ => absolute = false,
+                }
+                let numbers = parameters
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|number| !number.is_empty())
+                    .map(|number| number.parse::<f64>()
+                        .map_err(|_| format!("Invalid number {:?} in {}", number, mnemonic)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if numbers.len() % 2 != 0 {
+                    return Err(format!("Odd number of coordinates in {}", mnemonic));
+                }
+                for pair in numbers.chunks(2) {
+                    let (x, y) = (pair[0] / HPGL_UNITS_PER_MM, pair[1] / HPGL_UNITS_PER_MM);
+                    let target = if absolute {
+                        CoordinatePair { x, y }
+                    } else {
+                        CoordinatePair { x: pen.position.x + x, y: pen.position.y + y }
+                    };
+                    pen.move_to(target, down);
+                }
+            },
+            // Putting the pen away
+            "SP" if parameters.trim().is_empty() || parameters.trim() == "0" => {
+                down = false;
+                pen.lift();
+            },
+            _ => {},
+        }
+    }
+
+    Ok(pen.finish())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use test_util::{assert_polylines_close, points};
+
+    use super::*;
+
+    #[test]
+    fn test_gcode_z() {
+        let gcode = "\
+            %\n\
+            G21 G90 ; millimeters\n\
+            G0 Z5\n\
+            G0 X0 Y10\n\
+            G1 Z-1 F300\n\
+            G1 X10 (top)\n\
+            Y0\n\
+            G0 Z5\n\
+            X20\n\
+            G1 Z0\n\
+            G91 G1 X5 Y10\n\
+            %\n";
+        let polylines = parse_gcode(gcode).unwrap();
+        // The y axis is flipped within the bounds (0 to 10)
+        assert_polylines_close(&polylines, &[
+            points(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]),
+            points(&[(20.0, 10.0), (25.0, 0.0)]),
+        ]);
+    }
+
+    #[test]
+    fn test_gcode_servo() {
+        // Pen plotters with a servo use M3 and M5, travel moves may be G1
+        let gcode = "G20\nM5\nG1 X1 Y1\nM3\nG1 X2\nG1 Y2\nM5\nG1 X3\n";
+        let polylines = parse_gcode(gcode).unwrap();
+        assert_polylines_close(&polylines, &[points(&[(25.4, 50.8), (50.8, 50.8), (50.8, 25.4)])]);
+
+        // Without pen control, G0 travels and G1 draws
+        let polylines = parse_gcode("G0 X1\nG1 X2\nG0 X3 Y1\nG1 Y2").unwrap();
+        assert_polylines_close(&polylines, &[
+            points(&[(1.0, 2.0), (2.0, 2.0)]),
+            points(&[(3.0, 1.0), (3.0, 0.0)]),
+        ]);
+    }
+
+    #[test]
+    fn test_gcode_modal() {
+        // Plane and work offset selection don't prevent moves, setting the
+        // position and dwelling are not moves, and units apply to the whole
+        // line
+        let gcode = "G0 X0 Y0\nG17 G1 X10 Y0\nG54 G1 X10 Y10\nG92 X0 Y0\nG4 X1\nX1 Y2 G20\n";
+        let polylines = parse_gcode(gcode).unwrap();
+        assert_polylines_close(&polylines, &[
+            points(&[(0.0, 50.8), (10.0, 50.8), (10.0, 40.8), (25.4, 0.0)]),
+        ]);
+    }
+
+    #[test]
+    fn test_gcode_arc() {
+        // A counterclockwise half circle and a full clockwise circle
+        let polylines = parse_gcode("G0 X10 Y0\nM3\nG3 X-10 Y0 I-10 J0\nM5\nG0 X0 Y0\nM3\nG2 I5\n").unwrap();
+        assert_eq!(polylines.len(), 2);
+        let half = &polylines[0];
+        assert!(half.len() > 10);
+        for point in half {
+            // Flipped, the start is at y = 0
+            let y = half[0].y - point.y;
+            assert!((point.x.hypot(y) - 10.0).abs() < 1e-9, "{:?}", point);
+            assert!(y >= -1e-9);
+        }
+        let circle = &polylines[1];
+        assert_eq!(circle.first(), circle.last());
+        let rightmost = circle.iter().map(|p| p.x).fold(0.0, f64::max);
+        assert!((rightmost - 10.0).abs() < 0.01);
+
+        assert!(parse_gcode("G2 X1 R1").is_err());
+        assert!(parse_gcode("G1 X1..2").is_err());
+    }
+
+    #[test]
+    fn test_hpgl() {
+        let hpgl = "IN;SP1;PU0,0;PD400,0,400 400;PU;PR 400,0;PD0,-400PU;LBlabel;\x03SP0;";
+        let polylines = parse_hpgl(hpgl).unwrap();
+        assert_polylines_close(&polylines, &[
+            points(&[(0.0, 10.0), (10.0, 10.0), (10.0, 0.0)]),
+            points(&[(20.0, 0.0), (20.0, 10.0)]),
+        ]);
+
+        assert!(parse_hpgl("PD1,2,3;").is_err());
+        assert!(parse_hpgl("PD1,a;").is_err());
+        assert!(parse_hpgl("12").is_err());
+    }
+}
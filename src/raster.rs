@@ -0,0 +1,530 @@
+//! Code for converting raster images (PNG or JPEG) into polylines.
+//!
+//! The polylines are returned in pixel coordinates, so they can be scaled and
+//! positioned like the polylines of an SVG. Line spacings are specified in
+//! millimeters on the board, assuming that the image is fitted to the board.
+use std::cmp;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::io::Cursor;
+
+use base64;
+use image::{self, GrayAlphaImage};
+use image::io::Reader;
+use svg2polylines::{CoordinatePair, Polyline};
+
+use scaling::Bounds;
+use simplify;
+use trace::{self, Bitmap};
+
+/// The smallest distance between two lines (or dots), in millimeters. Lines
+/// that are closer than the width of the pen merge into a black area.
+const MIN_SPACING: f64 = 1.0;
+
+/// Distance between two samples along a line, in millimeters. The robot moves
+/// in steps of 0.1 mm, so finer sampling would be lost.
+const SAMPLE_STEP: f64 = 0.25;
+
+/// Points that are closer than this to the simplified polylines (in
+/// millimeters) are dropped, this is one step of the robot.
+const TOLERANCE: f64 = 0.1;
+
+/// Length of the stroke that draws a single stipple dot, in millimeters. The
+/// robot can't draw a single point.
+const DOT_LENGTH: f64 = 0.2;
+
+/// Images that are wider or higher than this (in pixels) are rejected before
+/// decoding, so that a small compressed file can't take up all memory.
+const MAX_DIMENSION: u32 = 10_000;
+
+/// Images with more pixels than this are rejected before decoding.
+const MAX_PIXELS: u64 = 16_000_000;
+
+/// The hatching angles in degrees, from the lightest to the darkest layer.
+const HATCH_ANGLES: [f64; 4] = [0.0, 90.0, 45.0, 135.0];
+
+fn default_spacing() -> f64 {
+    2.0
+}
+
+fn default_stipple_spacing() -> f64 {
+    1.5
+}
+
+fn default_layers() -> u8 {
+    4
+}
+
+fn default_threshold() -> f64 {
+    0.5
+}
+
+/// How a raster image is converted into lines.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RasterStyle {
+    /// Parallel lines in up to four directions. Darker areas get more layers
+    /// of lines.
+    Hatching {
+        /// Distance between two lines, in millimeters.
+        #[serde(default = "default_spacing")]
+        spacing: f64,
+        #[serde(default = "default_layers")]
+        layers: u8,
+    },
+    /// A single continuous line, running back and forth in rows. The line
+    /// waves wider and faster in darker areas.
+    Squiggle {
+        /// Distance between two rows, in millimeters.
+        #[serde(default = "default_spacing")]
+        spacing: f64,
+    },
+    /// Dots whose density follows the darkness, connected into strokes.
+    Stipple {
+        /// Distance between two dots in the darkest areas, in millimeters.
+        #[serde(default = "default_stipple_spacing")]
+        spacing: f64,
+    },
+    /// The outlines of the dark areas, for logos and scanned drawings.
+    Outline {
+        /// Pixels darker than this (from 0 for white to 1 for black) are
+        /// traced.
+        #[serde(default = "default_threshold")]
+        threshold: f64,
+    },
+    /// The centerlines of the dark areas, so that every line of a drawing
+    /// becomes a single stroke.
+    Centerline {
+        #[serde(default = "default_threshold")]
+        threshold: f64,
+    },
+}
+
+impl Default for RasterStyle {
+    fn default() -> Self {
+        RasterStyle::Hatching { spacing: default_spacing(), layers: default_layers() }
+    }
+}
+
+/// The darkness of an image, averaged over square cells and stretched to
+/// the range from 0 (white) to 1 (black). Transparent pixels are white.
+struct Darkness {
+    /// Size of a cell, in pixels.
+    cell: u32,
+    columns: u32,
+    width: f64,
+    height: f64,
+    values: Vec<f64>,
+}
+
+impl Darkness {
+    fn new(image: &GrayAlphaImage, cell: u32) -> Self {
+        let cell = cmp::max(cell, 1);
+        let (width, height) = image.dimensions();
+        let columns = width.div_ceil(cell);
+        let rows = height.div_ceil(cell);
+        let mut sums = vec![0.0; (columns * rows) as usize];
+        let mut counts = vec![0u32; sums.len()];
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let i = ((y / cell) * columns + x / cell) as usize;
+            sums[i] += (1.0 - f64::from(pixel[0]) / 255.0) * f64::from(pixel[1]) / 255.0;
+            counts[i] += 1;
+        }
+        let mut values: Vec<f64> = sums.iter().zip(counts).map(|(sum, count)| sum / f64::from(count)).collect();
+
+        // Stretch the contrast, photos rarely use the full range
+        let min = values.iter().cloned().fold(1.0, f64::min);
+        let max = values.iter().cloned().fold(0.0, f64::max);
+        if max - min > 1e-3 {
+            for value in &mut values {
+                *value = (*value - min) / (max - min);
+            }
+        }
+
+        Darkness { cell, columns, width: f64::from(width), height: f64::from(height), values }
+    }
+
+    /// Return the darkness at a point (in pixels), or `None` if the point is
+    /// outside of the image.
+    fn at(&self, x: f64, y: f64) -> Option<f64> {
+        if x < 0.0 || y < 0.0 || x >= self.width || y >= self.height {
+            return None;
+        }
+        let column = x as u32 / self.cell;
+        let row = y as u32 / self.cell;
+        Some(self.values[(row * self.columns + column) as usize])
+    }
+}
+
+/// Draw parallel lines in up to four directions, every direction covers the
+/// areas that are darker than its threshold.
+fn hatch(darkness: &Darkness, spacing: f64, step: f64, layers: u8) -> Vec<Polyline> {
+    let layers = cmp::min(cmp::max(layers, 1), HATCH_ANGLES.len() as u8);
+    let corners = [(0.0, 0.0), (darkness.width, 0.0), (0.0, darkness.height), (darkness.width, darkness.height)];
+    let mut polylines = vec![];
+    for (layer, angle) in HATCH_ANGLES.iter().take(usize::from(layers)).enumerate() {
+        let threshold = (layer + 1) as f64 / f64::from(layers + 1);
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let direction = (cos, sin);
+        let normal = (-sin, cos);
+
+        // The range of the lines, covering the entire image
+        let range = |(vx, vy): (f64, f64)| corners.iter()
+            .map(|&(x, y)| x * vx + y * vy)
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), p| (min.min(p), max.max(p)));
+        let (normal_min, normal_max) = range(normal);
+        let (along_min, along_max) = range(direction);
+        let samples = ((along_max - along_min) / step).ceil() as usize;
+
+        let mut offset = normal_min + spacing / 2.0;
+        let mut reverse = false;
+        while offset < normal_max {
+            // Alternate the direction, so the pen doesn't travel back
+            let mut run: Option<(CoordinatePair, CoordinatePair)> = None;
+            for i in 0..=samples {
+                let i = if reverse { samples - i } else { i };
+                let along = along_min + i as f64 * step;
+                let point = CoordinatePair {
+                    x: normal.0 * offset + direction.0 * along,
+                    y: normal.1 * offset + direction.1 * along,
+                };
+                let dark = darkness.at(point.x, point.y).is_some_and(|d| d > threshold);
+                run = match (dark, run) {
+                    (true, Some((start, _))) => Some((start, point)),
+                    (true, None) => Some((point, point)),
+                    (false, Some((start, end))) => {
+                        polylines.push(vec![start, end]);
+                        None
+                    },
+                    (false, None) => None,
+                };
+            }
+            if let Some((start, end)) = run {
+                polylines.push(vec![start, end]);
+            }
+            offset += spacing;
+            reverse = !reverse;
+        }
+    }
+    polylines
+}
+
+/// Draw a single line back and forth in rows. The amplitude and frequency of
+/// the wave grow with the darkness.
+fn squiggle(darkness: &Darkness, spacing: f64, step: f64) -> Vec<Polyline> {
+    let samples = (darkness.width / step).ceil() as usize;
+    let mut polyline = vec![];
+    let mut phase: f64 = 0.0;
+    let mut y = spacing / 2.0;
+    let mut reverse = false;
+    while y < darkness.height {
+        for i in 0..=samples {
+            let i = if reverse { samples - i } else { i };
+            let x = (i as f64 * step).min(darkness.width);
+            let dark = darkness.at(x, y).unwrap_or(0.0);
+
+            // The wavelength shrinks from two spacings to one spacing
+            let wavelength = spacing * (2.0 - dark);
+            phase += 2.0 * PI * step / wavelength;
+            polyline.push(CoordinatePair { x, y: y + 0.45 * spacing * dark * phase.sin() });
+        }
+        y += spacing;
+        reverse = !reverse;
+    }
+    vec![polyline]
+}
+
+/// A small random number generator (xorshift), so that the same image always
+/// results in the same drawing.
+pub(crate) struct XorShift(pub(crate) u64);
+
+impl XorShift {
+    /// Return a number between 0 and 1.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Place dots on a grid using Floyd–Steinberg dithering, so that their
+/// density follows the darkness. The dots are jittered to hide the grid.
+fn stipple_dots(darkness: &Darkness, spacing: f64) -> Vec<CoordinatePair> {
+    let columns = (darkness.width / spacing).ceil() as usize;
+    let rows = (darkness.height / spacing).ceil() as usize;
+    let center = |column: usize, row: usize| {
+        ((column as f64 + 0.5) * spacing, (row as f64 + 0.5) * spacing)
+    };
+    let mut values: Vec<f64> = (0..rows * columns)
+        .map(|i| {
+            let (x, y) = center(i % columns, i / columns);
+            darkness.at(x.min(darkness.width - 1.0), y.min(darkness.height - 1.0)).unwrap_or(0.0)
+        })
+        .collect();
+
+    let mut random = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut dots = vec![];
+    for row in 0..rows {
+        for column in 0..columns {
+            let i = row * columns + column;
+            let dot = values[i] >= 0.5;
+            let error = values[i] - if dot { 1.0 } else { 0.0 };
+            if dot {
+                let (x, y) = center(column, row);
+                dots.push(CoordinatePair {
+                    x: x + (random.next_f64() - 0.5) * spacing / 2.0,
+                    y: y + (random.next_f64() - 0.5) * spacing / 2.0,
+                });
+            }
+            if column + 1 < columns {
+                values[i + 1] += error * 7.0 / 16.0;
+            }
+            if row + 1 < rows {
+                if column > 0 {
+                    values[i + columns - 1] += error * 3.0 / 16.0;
+                }
+                values[i + columns] += error * 5.0 / 16.0;
+                if column + 1 < columns {
+                    values[i + columns + 1] += error / 16.0;
+                }
+            }
+        }
+    }
+    dots
+}
+
+/// Connect dots into strokes, always continuing with the nearest remaining
+/// dot. A new stroke is started if that dot is farther away than `max_jump`.
+fn connect_dots(dots: &[CoordinatePair], max_jump: f64) -> Vec<Polyline> {
+    // Sort the dots into cells, so only neighbouring cells need to be searched
+    let cell = |point: &CoordinatePair| ((point.x / max_jump).floor() as i64, (point.y / max_jump).floor() as i64);
+    let mut cells: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (i, dot) in dots.iter().enumerate() {
+        cells.entry(cell(dot)).or_default().push(i);
+    }
+    let take = |i: usize, cells: &mut HashMap<(i64, i64), Vec<usize>>| {
+        if let Some(candidates) = cells.get_mut(&cell(&dots[i])) {
+            candidates.retain(|&j| j != i);
+        }
+    };
+
+    let mut polylines = vec![];
+    for first in 0..dots.len() {
+        if !cells.get(&cell(&dots[first])).is_some_and(|candidates| candidates.contains(&first)) {
+            continue;
+        }
+        take(first, &mut cells);
+        let mut stroke = vec![dots[first]];
+        loop {
+            let current = stroke[stroke.len() - 1];
+            let (cx, cy) = cell(&current);
+            let mut nearest: Option<(f64, usize)> = None;
+            for x in cx - 1..=cx + 1 {
+                for y in cy - 1..=cy + 1 {
+                    for &i in cells.get(&(x, y)).into_iter().flatten() {
+                        let d = (dots[i].x - current.x).hypot(dots[i].y - current.y);
+                        if d <= max_jump && nearest.is_none_or(|(best, _)| d < best) {
+                            nearest = Some((d, i));
+                        }
+                    }
+                }
+            }
+            match nearest {
+                Some((_, i)) => {
+                    take(i, &mut cells);
+                    stroke.push(dots[i]);
+                },
+                None => break,
+            }
+        }
+        polylines.push(stroke);
+    }
+    polylines
+}
+
+/// Decode base64 data, optionally as a data URL (`data:image/png;base64,…`).
+pub fn decode_base64(data: &str) -> Result<Vec<u8>, String> {
+    let data = match data.find(";base64,") {
+        Some(index) if data.starts_with("data:") => &data[index + 8..],
+        _ => data,
+    };
+    base64::decode(data.trim()).map_err(|e| format!("Invalid base64 data: {}", e))
+}
+
+/// Convert a PNG or JPEG image into polylines (in pixel coordinates).
+///
+/// The line spacing of the style is converted to pixels as if the image was
+/// fitted into the bounds (in millimeters). Outlines and centerlines are
+/// traced from the image thresholded to black and white.
+pub fn trace_image(data: &[u8], style: &RasterStyle, bounds: &Bounds) -> Result<Vec<Polyline>, String> {
+    let (width, height) = Reader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| format!("Could not read image: {}", e))?
+        .into_dimensions()
+        .map_err(|e| format!("Could not read image: {}", e))?;
+    if width > MAX_DIMENSION || height > MAX_DIMENSION || u64::from(width) * u64::from(height) > MAX_PIXELS {
+        return Err(format!(
+            "Image is too large ({} x {} pixels, at most {} x {} and {} megapixels)",
+            width, height, MAX_DIMENSION, MAX_DIMENSION, MAX_PIXELS / 1_000_000,
+        ));
+    }
+
+    let image = image::load_from_memory(data)
+        .map_err(|e| format!("Could not read image: {}", e))?
+        .to_luma_alpha8();
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Err("Image is empty".into());
+    }
+
+    // Convert millimeters on the board to pixels
+    let millimeters_per_pixel = (bounds.x.spread() / f64::from(width)).min(bounds.y.spread() / f64::from(height));
+    let pixels = |millimeters: f64| millimeters / millimeters_per_pixel;
+    let spacing = |spacing: f64| {
+        if spacing.is_finite() {
+            Ok(pixels(spacing.max(MIN_SPACING)))
+        } else {
+            Err("Invalid spacing".to_string())
+        }
+    };
+    let step = pixels(SAMPLE_STEP);
+    let darkness = || Darkness::new(&image, step.floor() as u32);
+
+    let polylines = match *style {
+        RasterStyle::Hatching { spacing: s, layers } => hatch(&darkness(), spacing(s)?, step, layers),
+        RasterStyle::Squiggle { spacing: s } => squiggle(&darkness(), spacing(s)?, step),
+        RasterStyle::Stipple { spacing: s } => {
+            let spacing = spacing(s)?;
+            let mut strokes = connect_dots(&stipple_dots(&darkness(), spacing), 2.0 * spacing);
+            // Draw unconnected dots as short strokes
+            for stroke in strokes.iter_mut().filter(|stroke| stroke.len() == 1) {
+                let end = CoordinatePair { x: stroke[0].x + pixels(DOT_LENGTH), y: stroke[0].y };
+                stroke.push(end);
+            }
+            strokes
+        },
+        RasterStyle::Outline { threshold } => trace::trace_outlines(&Bitmap::from_image(&image, threshold)),
+        RasterStyle::Centerline { threshold } => trace::trace_centerlines(&Bitmap::from_image(&image, threshold)),
+    };
+    Ok(polylines.iter()
+        .map(|polyline| simplify::ramer_douglas_peucker(polyline, pixels(TOLERANCE)))
+        .collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, ImageOutputFormat, LumaA};
+
+    use scaling::Range;
+
+    use super::*;
+
+    /// An image that is white on the left half and black on the right half.
+    fn half_black(width: u32, height: u32) -> GrayAlphaImage {
+        ImageBuffer::from_fn(width, height, |x, _| LumaA([if x < width / 2 { 255 } else { 0 }, 255]))
+    }
+
+    fn png(image: &GrayAlphaImage) -> Vec<u8> {
+        let mut data = vec![];
+        image::DynamicImage::ImageLumaA8(image.clone())
+            .write_to(&mut data, ImageOutputFormat::Png)
+            .unwrap();
+        data
+    }
+
+    /// Bounds in which one pixel of a 100 × 50 image is one millimeter.
+    fn bounds() -> Bounds {
+        Bounds { x: Range { min: 0.0, max: 100.0 }, y: Range { min: 0.0, max: 100.0 } }
+    }
+
+    #[test]
+    fn test_decode_base64() {
+        assert_eq!(decode_base64("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(decode_base64("data:image/png;base64,aGVsbG8=").unwrap(), b"hello");
+        assert!(decode_base64("no base64!").is_err());
+    }
+
+    #[test]
+    fn test_darkness() {
+        let mut image = half_black(4, 2);
+        image.put_pixel(0, 0, LumaA([0, 0])); // Transparent
+        let darkness = Darkness::new(&image, 2);
+        assert_eq!(darkness.at(0.5, 0.5), Some(0.0));
+        assert_eq!(darkness.at(3.9, 1.9), Some(1.0));
+        assert_eq!(darkness.at(4.0, 0.0), None);
+    }
+
+    #[test]
+    fn test_hatching() {
+        let data = png(&half_black(100, 50));
+        let style = RasterStyle::Hatching { spacing: 5.0, layers: 1 };
+        let polylines = trace_image(&data, &style, &bounds()).unwrap();
+
+        // Ten horizontal lines on the right half, alternating in direction
+        assert_eq!(polylines.len(), 10);
+        assert_eq!(polylines[0], vec![CoordinatePair { x: 50.0, y: 2.5 }, CoordinatePair { x: 99.75, y: 2.5 }]);
+        assert_eq!(polylines[1], vec![CoordinatePair { x: 99.75, y: 7.5 }, CoordinatePair { x: 50.0, y: 7.5 }]);
+
+        // More layers for dark areas
+        let style = RasterStyle::Hatching { spacing: 5.0, layers: 4 };
+        assert!(trace_image(&data, &style, &bounds()).unwrap().len() > 30);
+    }
+
+    #[test]
+    fn test_squiggle() {
+        let data = png(&half_black(100, 50));
+        let style = RasterStyle::Squiggle { spacing: 0.1 };
+        let polylines = trace_image(&data, &style, &bounds()).unwrap();
+
+        // One continuous line with 50 rows (the spacing is limited)
+        assert_eq!(polylines.len(), 1);
+        let points = &polylines[0];
+        assert!(points.iter().all(|p| p.x >= 0.0 && p.x <= 100.0 && p.y >= 0.0 && p.y <= 50.0));
+        assert_eq!(points[points.len() - 1].y, 49.5);
+        // The white half is drawn as a straight line
+        assert_eq!(points[0], CoordinatePair { x: 0.0, y: 0.5 });
+        assert_eq!(points[1].y, 0.5);
+        assert!(points[1].x >= 49.0);
+    }
+
+    #[test]
+    fn test_stipple() {
+        let data = png(&half_black(100, 50));
+        let style = RasterStyle::Stipple { spacing: 2.0 };
+        let polylines = trace_image(&data, &style, &bounds()).unwrap();
+        let dots: Vec<_> = polylines.iter().flatten().collect();
+        assert!(dots.iter().all(|p| p.x > 49.0));
+        // The dots are connected into strokes
+        assert!(polylines.len() * 10 < dots.len());
+
+        // A single dot is drawn as a short stroke
+        let single = ImageBuffer::from_fn(20, 20, |x, y| LumaA([if (x, y) == (10, 10) { 0 } else { 255 }, 255]));
+        let style = RasterStyle::Stipple { spacing: 1.0 };
+        let square = Bounds { x: Range { min: 0.0, max: 20.0 }, y: Range { min: 0.0, max: 20.0 } };
+        let polylines = trace_image(&png(&single), &style, &square).unwrap();
+        assert_eq!(polylines.len(), 1);
+        assert_eq!(polylines[0].len(), 2);
+        assert!((polylines[0][1].x - polylines[0][0].x - DOT_LENGTH).abs() < 1e-9);
+        assert!(trace_image(b"not an image", &style, &bounds()).is_err());
+
+        // Too large images are rejected before decoding
+        let wide = png(&half_black(MAX_DIMENSION + 1, 1));
+        assert!(trace_image(&wide, &style, &bounds()).unwrap_err().contains("too large"));
+    }
+
+    #[test]
+    fn test_connect_dots() {
+        let dots = vec![
+            CoordinatePair { x: 0.0, y: 0.0 },
+            CoordinatePair { x: 10.0, y: 0.0 },
+            CoordinatePair { x: 2.0, y: 0.0 },
+            CoordinatePair { x: 1.0, y: 0.0 },
+        ];
+        assert_eq!(connect_dots(&dots, 3.0), vec![
+            vec![dots[0], dots[3], dots[2]],
+            vec![dots[1]],
+        ]);
+    }
+}
@@ -0,0 +1,248 @@
+//! Rendering of the path the robot takes, decoded from the commands sent to
+//! it. Unlike the preview in the browser, this shows the effect of clipping
+//! to the board and of rounding to robot units.
+use std::fmt::Write;
+use std::mem;
+
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+
+use board::Board;
+use robot::Command;
+
+/// Pixels per millimeter of rendered PNG images.
+const PNG_SCALE: f64 = 4.0;
+
+/// What the robot does while moving.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tool {
+    /// Moving with the pen lifted.
+    Travel,
+    Pen,
+    Eraser,
+}
+
+impl Tool {
+    fn color(self) -> [u8; 3] {
+        match self {
+            Tool::Travel => [59, 130, 246],
+            Tool::Pen => [0, 0, 0],
+            Tool::Eraser => [255, 224, 178],
+        }
+    }
+
+    /// The width of the line in millimeters.
+    fn width(self, eraser_width: f64) -> f64 {
+        match self {
+            Tool::Travel => 0.2,
+            Tool::Pen => 0.5,
+            Tool::Eraser => eraser_width,
+        }
+    }
+}
+
+/// A continuous movement of the robot with the same tool, in robot
+/// coordinates (0.1 mm, origin in the corner of the robot).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stroke {
+    pub tool: Tool,
+    pub points: Vec<(u16, u16)>,
+}
+
+/// Follow the commands and return the movements of the robot. The robot is
+/// assumed to start at `(0, 0)` with the pen lifted.
+pub fn strokes(commands: &[Command]) -> Vec<Stroke> {
+    let mut strokes = vec![];
+    let mut position = (0, 0);
+    let mut current = Stroke { tool: Tool::Travel, points: vec![] };
+    for command in commands {
+        let tool = match *command {
+            Command::PenLift => Tool::Travel,
+            Command::PenDown => Tool::Pen,
+            Command::EnableEraser => Tool::Eraser,
+            Command::Move(x, y) => {
+                if current.points.is_empty() {
+                    current.points.push(position);
+                }
+                position = (x, y);
+                current.points.push(position);
+                continue;
+            },
+            _ => continue,
+        };
+        if tool != current.tool {
+            let next = Stroke { tool, points: vec![] };
+            let previous = mem::replace(&mut current, next);
+            if previous.points.len() > 1 {
+                strokes.push(previous);
+            }
+        }
+    }
+    if current.points.len() > 1 {
+        strokes.push(current);
+    }
+    strokes
+}
+
+/// Strokes in the order they are drawn: Eraser passes at the bottom, pen
+/// strokes on top.
+fn layered(strokes: &[Stroke]) -> impl Iterator<Item=&Stroke> {
+    [Tool::Eraser, Tool::Travel, Tool::Pen].iter()
+        .flat_map(move |&tool| strokes.iter().filter(move |stroke| stroke.tool == tool))
+}
+
+/// Render the strokes to an SVG image of the board. The eraser width is in
+/// millimeters.
+pub fn render_svg(strokes: &[Stroke], board: &Board, eraser_width: f64) -> String {
+    let (width, height) = board.robot_size();
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}mm" height="{}mm" viewBox="0 0 {} {}">"#,
+        board.width, board.height, width, height,
+    );
+    let _ = writeln!(svg, r##"<rect width="{}" height="{}" fill="white" stroke="#ccc" stroke-width="4"/>"##, width, height);
+    let _ = writeln!(svg, "<style>.travel {{ stroke-dasharray: 10 10; }}</style>");
+    let _ = writeln!(svg, r#"<g fill="none" stroke-linecap="round" stroke-linejoin="round">"#);
+    for stroke in layered(strokes) {
+        let [r, g, b] = stroke.tool.color();
+        let class = match stroke.tool {
+            Tool::Travel => "travel",
+            Tool::Pen => "pen",
+            Tool::Eraser => "eraser",
+        };
+        let points: Vec<String> = stroke.points.iter()
+            .map(|&point| {
+                let (x, y) = board.robot_to_image(point);
+                format!("{},{}", x, y)
+            })
+            .collect();
+        let _ = writeln!(
+            svg,
+            r#"<polyline class="{}" stroke="rgb({},{},{})" stroke-width="{}" points="{}"/>"#,
+            class, r, g, b, stroke.tool.width(eraser_width) * 10.0, points.join(" "),
+        );
+    }
+    svg.push_str("</g>\n</svg>\n");
+    svg
+}
+
+/// Return the distance of a point to the line segment.
+fn distance_to_segment(point: (f64, f64), from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared > 0.0 {
+        (((point.0 - from.0) * dx + (point.1 - from.1) * dy) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (point.0 - from.0 - t * dx).hypot(point.1 - from.1 - t * dy)
+}
+
+/// Draw a line with round ends. The pixels around points along the line are
+/// checked, so long lines don't need to check their whole bounding box.
+fn draw_line(image: &mut RgbImage, from: (f64, f64), to: (f64, f64), width: f64, color: [u8; 3]) {
+    let radius = (width / 2.0).max(0.5);
+    let length = (to.0 - from.0).hypot(to.1 - from.1);
+    let steps = (length / radius).ceil().max(1.0) as usize;
+    let r = radius.ceil() as i64 + 1;
+    for step in 0..=steps {
+        let t = step as f64 / steps as f64;
+        let (cx, cy) = (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t);
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let (x, y) = (cx.round() as i64 + dx, cy.round() as i64 + dy);
+                if x < 0 || y < 0 || x >= i64::from(image.width()) || y >= i64::from(image.height()) {
+                    continue;
+                }
+                if distance_to_segment((x as f64, y as f64), from, to) <= radius {
+                    image.put_pixel(x as u32, y as u32, Rgb(color));
+                }
+            }
+        }
+    }
+}
+
+/// Render the strokes to a PNG image of the board. The eraser width is in
+/// millimeters.
+pub fn render_png(strokes: &[Stroke], board: &Board, eraser_width: f64) -> Result<Vec<u8>, String> {
+    let width = (board.width * PNG_SCALE) as u32;
+    let height = (board.height * PNG_SCALE) as u32;
+    let mut image = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+    let to_pixels = |&point: &(u16, u16)| {
+        let (x, y) = board.robot_to_image(point);
+        (f64::from(x) / 10.0 * PNG_SCALE, f64::from(y) / 10.0 * PNG_SCALE)
+    };
+    for stroke in layered(strokes) {
+        let line_width = stroke.tool.width(eraser_width) * PNG_SCALE;
+        for segment in stroke.points.windows(2) {
+            draw_line(&mut image, to_pixels(&segment[0]), to_pixels(&segment[1]), line_width, stroke.tool.color());
+        }
+    }
+
+    let mut png = vec![];
+    DynamicImage::ImageRgb8(image)
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .map_err(|e| format!("Could not encode PNG: {}", e))?;
+    Ok(png)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use image;
+
+    use super::*;
+
+    fn commands() -> Vec<Command> {
+        vec![
+            Command::StartDrawing,
+            Command::PenLift,
+            Command::Move(0, 1230),
+            Command::EnableEraser,
+            Command::Move(3580, 1230),
+            Command::PenLift,
+            Command::Move(100, 100),
+            Command::PenDown,
+            Command::Move(200, 100),
+            Command::Move(200, 200),
+            Command::PenLift,
+            Command::Move(0, 0),
+            Command::StopDrawing,
+        ]
+    }
+
+    #[test]
+    fn test_strokes() {
+        assert_eq!(strokes(&commands()), vec![
+            Stroke { tool: Tool::Travel, points: vec![(0, 0), (0, 1230)] },
+            Stroke { tool: Tool::Eraser, points: vec![(0, 1230), (3580, 1230)] },
+            Stroke { tool: Tool::Travel, points: vec![(3580, 1230), (100, 100)] },
+            Stroke { tool: Tool::Pen, points: vec![(100, 100), (200, 100), (200, 200)] },
+            Stroke { tool: Tool::Travel, points: vec![(200, 200), (0, 0)] },
+        ]);
+    }
+
+    #[test]
+    fn test_render_svg() {
+        let svg = render_svg(&strokes(&commands()), &Board::default(), 10.0);
+        // Eraser first, pen last, with the y axis flipped
+        let eraser = svg.find(r#"class="eraser""#).unwrap();
+        let travel = svg.find(r#"class="travel""#).unwrap();
+        let pen = svg.find(r#"class="pen""#).unwrap();
+        assert!(eraser < travel && travel < pen);
+        assert!(svg.contains(".travel { stroke-dasharray: 10 10; }"));
+        assert!(svg.contains(r#"stroke-width="100" points="0,0 3580,0""#));
+        assert!(svg.contains(r#"points="100,1130 200,1130 200,1030""#));
+    }
+
+    #[test]
+    fn test_render_png() {
+        let png = render_png(&strokes(&commands()), &Board::default(), 10.0).unwrap();
+        let image = image::load_from_memory(&png).unwrap().to_rgb8();
+        assert_eq!(image.dimensions(), (1432, 492));
+        // Pen stroke from (10, 10) to (20, 10) mm, eraser along the top edge
+        assert_eq!(image.get_pixel(60, 452), &Rgb([0, 0, 0]));
+        assert_eq!(image.get_pixel(700, 10), &Rgb(Tool::Eraser.color()));
+        assert_eq!(image.get_pixel(700, 300), &Rgb([255, 255, 255]));
+    }
+}
@@ -1,6 +1,6 @@
-use std::collections::VecDeque;
+use std::borrow::Cow;
 use std::io::{self, BufRead, Write};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{channel, Sender, RecvTimeoutError};
 use std::thread;
@@ -9,39 +9,92 @@ use std::time::Duration;
 use bufstream::BufStream;
 use regex::Regex;
 use scheduled_executor::CoreExecutor;
-use scheduled_executor::executor::TaskHandle;
-use serial::{self, BaudRate, PortSettings, SerialPort};
+use serial::BaudRate;
 use svg2polylines::Polyline;
 use time;
 
 use ::TimeLimits;
+use agenda::{self, AgendaConfig};
+use board::Board;
+use calibration::Calibration;
+use clip;
+use clock::{self, ClockConfig};
+use erase::{self, EraseStrategy, SweepPattern};
+use estimate::TimingModel;
+use events::Event;
+use history::{Drawing, Source};
+use jobs::{Job, JobKind};
+use scaling::Bounds;
+use status::SharedState;
+use transport;
 
-pub(crate) const IBB_WIDTH: u16 = 358;
-pub(crate) const IBB_HEIGHT: u16 = 123;
-const TIMEOUT_MS_SERIAL: u64 = 1000;
 const TIMEOUT_MS_CHANNEL: u64 = 50;
 
-type Block = Vec<u8>;
+pub(crate) type Block = Vec<u8>;
 
 pub struct Sketch<'a> {
     buf: Vec<u8>,
     block_size: usize,
     polylines: &'a [Polyline],
+    erase_pattern: SweepPattern,
+    board: Board,
+    calibration: Option<&'a Calibration>,
 }
 
 #[derive(Debug)]
 pub enum PrintTask {
     /// Schedule a print task once.
-    Once(Vec<Polyline>),
-    /// Schedule one or more print tasks every `Duration`.
-    /// If multiple tasks are specified, then another one is printed for every
-    /// iteration.
-    Scheduled(Duration, Vec<Vec<Polyline>>),
+    Once(Drawing, EraseStrategy),
+    /// Schedule a print task every `Duration`.
+    Scheduled(Duration, ScheduledContent, EraseStrategy),
+    /// Print blocks that were prepared before (e.g. read from a block file)
+    /// once.
+    Blocks(Drawing, Vec<Block>),
 }
 
+/// What a scheduled print task prints.
 #[derive(Debug)]
+pub enum ScheduledContent {
+    /// One or more drawings. If multiple drawings are specified, then another
+    /// one is printed for every iteration.
+    Drawings(Vec<Drawing>),
+    /// The current time, rendered within the bounds.
+    Clock(ClockConfig, Bounds),
+    /// Today's agenda, rendered within the bounds.
+    Agenda(AgendaConfig, Bounds),
+}
+
+impl ScheduledContent {
+    /// Return the drawing to print in the specified iteration.
+    fn drawing(&self, iteration: usize) -> Option<Drawing> {
+        match *self {
+            ScheduledContent::Drawings(ref drawings) if drawings.is_empty() => None,
+            ScheduledContent::Drawings(ref drawings) => Some(drawings[iteration % drawings.len()].clone()),
+            ScheduledContent::Clock(ref config, ref bounds) => {
+                match clock::render_clock(&time::now(), config, bounds) {
+                    Ok(polylines) => Some(Drawing::new(polylines, Source::Clock, None)),
+                    Err(e) => {
+                        error!("Could not render clock: {}", e);
+                        None
+                    },
+                }
+            },
+            ScheduledContent::Agenda(ref config, ref bounds) => {
+                match agenda::render_agenda(config, &time::now(), bounds) {
+                    Ok(polylines) => Some(Drawing::new(polylines, Source::Agenda, None)),
+                    Err(e) => {
+                        error!("Could not render agenda: {}", e);
+                        None
+                    },
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[allow(dead_code)]
-enum Command {
+pub enum Command {
     /// Start of block
     BlockStart,
     /// This is block number n
@@ -63,8 +116,8 @@ enum Command {
 }
 
 impl Command {
-    pub fn to_bytes(&self) -> [u8; 3] {
-        match *self {
+    pub fn to_bytes(self) -> [u8; 3] {
+        match self {
             Command::BlockStart => [0xfa, 0x9f, 0xa1],
             Command::BlockNumber(num) => {
                 if num >= 4000 { panic!("Block number must be <4000"); };
@@ -92,32 +145,44 @@ impl Command {
             Command::EnableEraser => [0xfa, 0x50, 0x00],
         }
     }
-}
 
-/// Clamp x coordinate.
-fn fix_x(x: f64) -> f64 {
-    if x < 0.0 {
-        0.0
-    } else if x > (IBB_WIDTH as f64) {
-        IBB_WIDTH as f64
-    } else {
-        x
+    /// Parse a single command from its byte representation.
+    ///
+    /// Return `None` if the bytes don't represent a known command.
+    pub fn from_bytes(bytes: [u8; 3]) -> Option<Command> {
+        if bytes[0] != 0xfa {
+            let x = (u16::from(bytes[0]) << 4) | (u16::from(bytes[1]) >> 4);
+            let y = (u16::from(bytes[1] & 0x0f) << 8) | u16::from(bytes[2]);
+            return Some(Command::Move(x, y));
+        }
+        match (bytes[1], bytes[2]) {
+            (0x9f, 0xa1) => Some(Command::BlockStart),
+            (0x1f, 0xa1) => Some(Command::StartDrawing),
+            (0x20, 0x00) => Some(Command::StopDrawing),
+            (0x30, 0x00) => Some(Command::PenLift),
+            (0x40, 0x00) => Some(Command::PenDown),
+            (0x50, 0x00) => Some(Command::EnableEraser),
+            (0x60, seconds) if seconds <= 30 => Some(Command::Wait(seconds)),
+            (b1, b2) if b1 >> 4 == 0x09 => {
+                let num = (u16::from(b1 & 0x0f) << 8) | u16::from(b2);
+                if num < 4000 { Some(Command::BlockNumber(num)) } else { None }
+            },
+            _ => None,
+        }
     }
 }
 
-/// Invert and clamp y coordinate.
-fn fix_y(y: f64) -> f64 {
-    // Invert y coordinate, since SVG uses the top left as 0 coordinate,
-    // while the IBB uses the bottom left as 0 coordinate.
-    let yy = (IBB_HEIGHT as f64) - y;
-
-    if yy < 0.0 {
-        0.0
-    } else if yy > (IBB_HEIGHT as f64) {
-        IBB_HEIGHT as f64
-    } else {
-        yy
-    }
+/// Return a block that safely ends an interrupted drawing: Lift the pen, move
+/// back to the start and stop drawing.
+pub(crate) fn cancel_block(number: u16) -> Block {
+    let commands = [
+        Command::BlockStart,
+        Command::BlockNumber(number),
+        Command::PenLift,
+        Command::Move(0, 0),
+        Command::StopDrawing,
+    ];
+    commands.iter().flat_map(|command| command.to_bytes().to_vec()).collect()
 }
 
 impl<'a> Sketch<'a> {
@@ -126,84 +191,83 @@ impl<'a> Sketch<'a> {
             buf: vec![],
             block_size: 768,
             polylines,
+            erase_pattern: SweepPattern::default(),
+            board: Board::default(),
+            calibration: None,
         }
     }
 
-    /// Add a command to the internal command buffer.
-    fn add_command(&mut self, command: Command) {
-        self.buf.extend_from_slice(&command.to_bytes());
+    /// Set the geometry of the board.
+    pub fn with_board(mut self, board: Board) -> Self {
+        self.board = board;
+        self
     }
 
-    /// Erase the entire board.
-    /// Note that this does not contain the `StartDrawing` and `Stop Drawing`
-    /// commands!
-    fn erase_all(&mut self) {
-        self.add_command(Command::PenLift);
-        self.add_command(Command::Move(0, IBB_HEIGHT * 10));
-        self.add_command(Command::EnableEraser);
-        let mut y = IBB_HEIGHT;
-        let y_step = 10;
-        loop {
-            // Loop until we're at the bottom
-            if y <= 0 {
-                break;
-            }
+    /// Correct the drawing with the calibration of the robot.
+    pub fn with_calibration(mut self, calibration: &'a Calibration) -> Self {
+        self.calibration = Some(calibration);
+        self
+    }
 
-            // Move to right and step down
-            self.add_command(Command::Move(IBB_WIDTH * 10, y * 10));
-            if y > y_step {
-                y -= y_step;
-            } else {
-                y = 0;
-            }
+    /// Set the pattern used for erasing the board.
+    pub fn with_erase_pattern(mut self, pattern: SweepPattern) -> Self {
+        self.erase_pattern = pattern;
+        self
+    }
 
-            // Move back to left and step down
-            self.add_command(Command::Move(IBB_WIDTH * 10, y * 10));
-            self.add_command(Command::Move(0, y * 10));
-            if y > y_step {
-                y -= y_step;
-            } else {
-                y = 0;
-            }
-            self.add_command(Command::Move(0, y * 10));
-        }
-        self.add_command(Command::PenLift);
-        self.add_command(Command::Move(0, 0));
+    /// Add a command to the internal command buffer.
+    fn add_command(&mut self, command: Command) {
+        self.buf.extend_from_slice(&command.to_bytes());
     }
 
     /// Convert the sketch into one or more byte vectors (blocks), ready to be
     /// sent to the robot via serial.
-    pub fn into_blocks(mut self, erase: bool) -> Vec<Block> {
+    ///
+    /// If an erase strategy is specified, the board is erased first.
+    pub fn into_blocks(mut self, erase: Option<EraseStrategy>) -> Vec<Block> {
+        // Correct the drawing, so that the erased area matches the corrected
+        // drawing as well
+        let polylines: Cow<[Polyline]> = match self.calibration {
+            Some(calibration) => Cow::Owned(calibration.apply_polylines(self.polylines, &self.board)),
+            None => Cow::Borrowed(self.polylines),
+        };
+
+        // Lift the pen where the drawing leaves the board
+        let (polylines, stats) = clip::clip_polylines(&polylines, &self.board.bounds());
+        if stats.clipped_polylines > 0 {
+            debug!("Clipped {:.1} mm of {} polylines", stats.clipped_length, stats.clipped_polylines);
+        }
+
         // Start a new drawing
         self.add_command(Command::StartDrawing);
 
-        // First, erase the entire board.
-        if erase {
-            self.erase_all();
-        } else {
-            // If we used the eraser, we're already at `(0, 0)` coordinates.
-            self.add_command(Command::PenLift);
-            self.add_command(Command::Move(0, 0));
+        // First, erase the board.
+        match erase.and_then(|strategy| strategy.area(&polylines, &self.board)) {
+            Some(area) => {
+                for command in erase::erase_commands(&area, &self.erase_pattern) {
+                    self.add_command(command);
+                }
+            },
+            None => {
+                // If we used the eraser, we're already at `(0, 0)` coordinates.
+                self.add_command(Command::PenLift);
+                self.add_command(Command::Move(0, 0));
+            },
         }
 
         // Now add the drawing commands to the buffer
-        for polyline in self.polylines {
+        for polyline in polylines.iter() {
             if polyline.len() < 2 {
                 warn!("Skipping polyline with less than 2 coordinate pairs");
                 continue;
             }
 
-            let start = polyline[0];
-            self.add_command(Command::Move(
-                (fix_x(start.x) * 10.0) as u16,
-                (fix_y(start.y) * 10.0) as u16,
-            ));
+            let (x, y) = self.board.robot_coords(&polyline[0]);
+            self.add_command(Command::Move(x, y));
             self.add_command(Command::PenDown);
             for point in polyline[1..].iter() {
-                self.add_command(Command::Move(
-                    (fix_x(point.x) * 10.0) as u16,
-                    (fix_y(point.y) * 10.0) as u16,
-                ));
+                let (x, y) = self.board.robot_coords(point);
+                self.add_command(Command::Move(x, y));
             }
             self.add_command(Command::PenLift);
         }
@@ -225,34 +289,25 @@ impl<'a> Sketch<'a> {
     }
 }
 
-/// Configure the serial port
-fn setup_serial<P: SerialPort>(port: &mut P, baud_rate: BaudRate) -> io::Result<()> {
-    port.configure(&PortSettings {
-        baud_rate,
-        char_size: serial::Bits8,
-        parity: serial::ParityNone,
-        stop_bits: serial::Stop1,
-        flow_control: serial::FlowNone,
-    })?;
-    port.set_timeout(Duration::from_millis(TIMEOUT_MS_SERIAL))?;
-    Ok(())
-}
-
-/// Spawn a thread that communicates with the robot over serial.
+/// Spawn a thread that communicates with the robot over serial (or with a
+/// simulated robot, if the device is `sim://`).
 ///
 /// The return value is the sending end of a channel. Over this channel, a list
-/// of polylines can be sent.
+/// of polylines can be sent. An error is returned if the device could not be
+/// opened.
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn communicate(
     device: &str,
     baud_rate: BaudRate,
     time_limits: Option<TimeLimits>,
-) -> Sender<PrintTask> {
-    // Connect to serial device
-    info!("Connecting to {} with baud rate {}...", device, baud_rate.speed());
-    let mut port = serial::open(device)
-        .expect(&format!("Could not open serial device {}", device));
-    setup_serial(&mut port, baud_rate)
-        .expect("Could not configure serial port");
+    erase_pattern: SweepPattern,
+    board: Board,
+    calibration: Calibration,
+    timing: TimingModel,
+    state: SharedState,
+) -> io::Result<Sender<PrintTask>> {
+    // Connect to device
+    let port = transport::open(device, baud_rate, board)?;
 
     // Wrap port into a buffered stream
     let mut ser = BufStream::new(port);
@@ -272,15 +327,11 @@ pub(crate) fn communicate(
     // Main loop
     let (tx, rx) = channel();
     thread::spawn(move || {
-        // A queue for blocks that should be printed.
-        let blocks_queue: Arc<Mutex<VecDeque<Block>>> = Arc::new(Mutex::new(VecDeque::new()));
-
         // The current block number (used for ACKs).
         let mut current_block: u32 = 0;
 
         // Initialize the job scheduler
         let executor = CoreExecutor::with_name("iboardbot_scheduler").unwrap();
-        let mut current_job: Option<TaskHandle> = None;
         let iteration = Arc::new(AtomicUsize::new(0));
 
         loop {
@@ -289,42 +340,53 @@ pub(crate) fn communicate(
                 rx.recv_timeout(Duration::from_millis(TIMEOUT_MS_CHANNEL));
             match task {
                 Ok(task) => {
-                    if let Some(ref handle) = current_job {
-                        // Handle existing job
-                        info!("Cancelling old print job");
-                        handle.stop();
-                    }
-                    // Reset iteration count
-                    iteration.store(0, Ordering::SeqCst);
-
                     info!("Received print task");
                     match task {
-                        PrintTask::Once(polylines) => {
-                            info!("-> Task: Scheduling once");
-                            let sketch = Sketch::new(&polylines);
-                            match blocks_queue.lock() {
-                                Ok(mut queue) => {
-                                    for block in sketch.into_blocks(true) {
-                                        queue.push_back(block);
-                                    }
+                        PrintTask::Once(drawing, erase) => {
+                            info!("-> Task: Printing once");
+                            let blocks = Sketch::new(&drawing.polylines)
+                                .with_erase_pattern(erase_pattern)
+                                .with_board(board)
+                                .with_calibration(&calibration)
+                                .into_blocks(Some(erase));
+                            let job = Job::new(JobKind::Once, drawing, blocks, &timing);
+                            match state.lock() {
+                                Ok(mut state) => {
+                                    state.jobs.push(job);
                                 },
-                                Err(e) => error!("Could not unlock blocks queue mutex: {}", e),
+                                Err(e) => error!("Could not unlock robot state mutex: {}", e),
                             }
                         },
-                        PrintTask::Scheduled(interval, polylines_vec) => {
-                            if polylines_vec.is_empty() {
-                                warn!("Could not schedule print task: polylines_vec is empty");
-                                return;
+                        PrintTask::Blocks(drawing, blocks) => {
+                            info!("-> Task: Printing {} prepared blocks once", blocks.len());
+                            let job = Job::new(JobKind::Once, drawing, blocks, &timing);
+                            match state.lock() {
+                                Ok(mut state) => {
+                                    state.jobs.push(job);
+                                },
+                                Err(e) => error!("Could not unlock robot state mutex: {}", e),
+                            }
+                        },
+                        PrintTask::Scheduled(interval, content, erase) => {
+                            if let ScheduledContent::Drawings(ref drawings) = content {
+                                if drawings.is_empty() {
+                                    warn!("Could not schedule print task: drawings is empty");
+                                    continue;
+                                }
                             }
+                            // Reset iteration count
+                            iteration.store(0, Ordering::SeqCst);
+
                             info!("-> Task: Scheduling every {} minutes", interval.as_secs() / 60);
                             if let Some(limits) = time_limits {
                                 info!("-> Task: Time limits: {}", limits);
                             } else {
                                 info!("-> Task: No time limits");
                             };
-                            let blocks_queue = blocks_queue.clone();
+                            let scheduler_state = state.clone();
                             let iteration_clone = iteration.clone();
-                            current_job = Some(executor.schedule_fixed_rate(
+                            let scheduler_calibration = calibration.clone();
+                            let handle = executor.schedule_fixed_rate(
                                 Duration::from_secs(2), // Wait 2 seconds before scheduling the first task
                                 interval, // After that, schedule in a fixed interval
                                 move |_handle| {
@@ -332,36 +394,45 @@ pub(crate) fn communicate(
                                     if let Some(limits) = time_limits {
                                         if !limits.is_within_limits(&time::now()) {
                                             info!("Scheduler: Skipping print (outside of time limits)");
+                                            if let Ok(state) = scheduler_state.lock() {
+                                                state.events.publish(Event::PrintSkipped);
+                                            }
                                             return;
                                         }
                                     }
 
                                     info!("Scheduler: Starting scheduled print");
 
-                                    // Determine which polylines to print
+                                    // Determine which drawing to print
                                     let i = iteration_clone.fetch_add(1, Ordering::SeqCst);
-                                    let index = i % polylines_vec.len();
-                                    let polylines = &polylines_vec[index];
+                                    let drawing = match content.drawing(i) {
+                                        Some(drawing) => drawing,
+                                        None => return,
+                                    };
 
                                     // Create and enqueue sketch
-                                    let sketch = Sketch::new(polylines);
-                                    match blocks_queue.lock() {
-                                        Ok(mut queue) => {
-                                            for block in sketch.into_blocks(true) {
-                                                queue.push_back(block);
-                                            }
+                                    let blocks = Sketch::new(&drawing.polylines)
+                                        .with_erase_pattern(erase_pattern)
+                                        .with_board(board)
+                                        .with_calibration(&scheduler_calibration)
+                                        .into_blocks(Some(erase));
+                                    let job = Job::new(JobKind::Scheduled, drawing, blocks, &timing);
+                                    match scheduler_state.lock() {
+                                        Ok(mut state) => {
+                                            state.jobs.push(job);
                                         },
-                                        Err(e) => error!("Could not unlock blocks queue mutex: {}", e),
+                                        Err(e) => error!("Could not unlock robot state mutex: {}", e),
                                     }
                                 }
-                            ));
+                            );
+
+                            // A new schedule replaces the existing one
+                            match state.lock() {
+                                Ok(mut state) => state.set_schedule(handle),
+                                Err(e) => error!("Could not unlock robot state mutex: {}", e),
+                            }
                         },
                     }
-                    if let Ok(queue) = blocks_queue.lock() {
-                        info!("{} block(s) in queue", queue.len());
-                    } else {
-                        warn!("Could not unlock blocks queue mutex");
-                    }
                 },
                 Err(RecvTimeoutError::Timeout) => {
                     // We didn't get a new task.
@@ -374,17 +445,22 @@ pub(crate) fn communicate(
             };
 
             // Talk to robot over serial
-            if let Ok(_) = ser.read_line(&mut buf) {
+            if ser.read_line(&mut buf).is_ok() {
                 let line = buf.trim();
 
                 // Debug print of all serial input
                 debug!("< {}", line);
 
-                // If there are blocks to be sent and we got a new CL command
+                // If there are jobs to be printed and we got a new CL command
                 // from the robot...
-                match blocks_queue.lock() {
-                    Ok(mut queue) => {
-                        if queue.len() > 0 && line.starts_with("CL ") {
+                match state.lock() {
+                    Ok(mut state) => {
+                        if !line.is_empty() {
+                            state.message_received(line);
+                        }
+                        let events = state.events.clone();
+                        let jobs = &mut state.jobs;
+                        if jobs.has_work() && line.starts_with("CL ") {
                             let mut send_next = false;
 
                             if line == "CL STATUS=READY" {
@@ -394,10 +470,15 @@ pub(crate) fn communicate(
                             } else if let Some(captures) = ack_re.captures(line) {
                                 let number_str = captures.get(1).unwrap().as_str();
                                 info!("< Ack: {}", number_str);
-                                match number_str.parse::<u32>() {
+                                let parsed = number_str.parse::<u32>();
+                                if let Ok(number) = parsed {
+                                    events.publish(Event::AckReceived { block: number });
+                                }
+                                match parsed {
                                     Ok(number) if number == current_block => {
                                         // Acked number is our current block, so we can safely
                                         // send the next one.
+                                        jobs.block_acked(number);
                                         send_next = true;
                                     },
                                     Ok(number) if current_block == 0 => {
@@ -417,36 +498,56 @@ pub(crate) fn communicate(
                             }
 
                             if send_next {
-                                info!("> Print block {}", current_block + 1);
-                                let block = queue.pop_front().expect("Could not pop block from non-empty queue");
-                                ser.write_all(&block)
-                                    .unwrap_or_else(|e| error!("Could not write data to serial: {}", e));
-                                ser.flush()
-                                    .unwrap_or_else(|e| error!("Could not flush serial buffer: {}", e));
-                                current_block += 1;
+                                if let Some((number, block)) = jobs.next_block() {
+                                    info!("> Print block {}", number);
+                                    let result = ser.write_all(block).and_then(|_| ser.flush());
+                                    match result {
+                                        Ok(_) => {
+                                            current_block = number;
+                                            if let Some(job) = jobs.current() {
+                                                events.publish(Event::BlockSent {
+                                                    job_id: job.id,
+                                                    block: number,
+                                                    blocks_total: job.blocks_total(),
+                                                });
+                                            }
+                                        },
+                                        Err(e) => {
+                                            error!("Could not write data to serial: {}", e);
+                                            jobs.fail_current();
+                                        },
+                                    }
+                                }
                             }
                         }
                     },
-                    Err(e) => error!("Could not unlock blocks queue mutex: {}", e),
+                    Err(e) => error!("Could not unlock robot state mutex: {}", e),
                 }
             }
             buf.clear();
+
+            if let Ok(mut state) = state.lock() {
+                state.check_connection();
+            }
         }
     });
-    tx
+    Ok(tx)
 }
 
 
 #[cfg(test)]
 mod test {
     use svg2polylines::{Polyline, CoordinatePair};
+
+    use board::Origin;
+    use calibration::Affine;
     use super::*;
 
     #[test]
     fn test_empty_sketch() {
         let polylines: Vec<Polyline> = vec![];
         let sketch = Sketch::new(&polylines);
-        let blocks = sketch.into_blocks(false);
+        let blocks = sketch.into_blocks(None);
         assert_eq!(blocks.len(), 1);
         assert_eq!(blocks[0], vec![
             0xfa, 0x9f, 0xa1, // Block start
@@ -468,7 +569,7 @@ mod test {
             ]
         ];
         let sketch = Sketch::new(&polylines);
-        let blocks = sketch.into_blocks(false);
+        let blocks = sketch.into_blocks(None);
         assert_eq!(blocks.len(), 1);
         assert_eq!(blocks[0], vec![
             0xfa, 0x9f, 0xa1, // Block start
@@ -494,7 +595,7 @@ mod test {
         }
         let polylines = vec![polyline];
         let sketch = Sketch::new(&polylines);
-        let blocks = sketch.into_blocks(false);
+        let blocks = sketch.into_blocks(None);
         assert_eq!(blocks.len(), 1);
         assert_eq!(blocks[0].len(), 768);
     }
@@ -508,7 +609,7 @@ mod test {
         }
         let polylines = vec![polyline];
         let sketch = Sketch::new(&polylines);
-        let blocks = sketch.into_blocks(false);
+        let blocks = sketch.into_blocks(None);
         assert_eq!(blocks.len(), 2);
         assert_eq!(blocks[0].len(), 768);
         assert_eq!(blocks[1].len(), 12);
@@ -516,4 +617,80 @@ mod test {
         assert_eq!(blocks[1][3..6], [0xfa, 0x90, 0x02]); // Block 2
     }
 
+    #[test]
+    fn test_erase_bounding_box() {
+        let polylines: Vec<Polyline> = vec![
+            vec![
+                CoordinatePair::from((12.5, 45.5)),
+                CoordinatePair::from((14.5, 47.5)),
+            ]
+        ];
+        let full = Sketch::new(&polylines).into_blocks(Some(EraseStrategy::Full));
+        let partial = Sketch::new(&polylines)
+            .into_blocks(Some(EraseStrategy::BoundingBox { margin: 2.5 }));
+        assert!(partial.concat().len() < full.concat().len());
+        assert_eq!(partial[0][9..18], [
+            0xfa, 0x30, 0x00, // Pen lift
+            0x06, 0x43, 0x20, // Move to 100,800
+            0xfa, 0x50, 0x00, // Enable eraser
+        ]);
+    }
+
+    #[test]
+    fn test_board_origin() {
+        let polylines: Vec<Polyline> = vec![
+            vec![
+                CoordinatePair::from((12.3, 45.6)),
+                CoordinatePair::from((190.0, 47.6)),
+            ]
+        ];
+        let board = Board { width: 200.0, height: 100.0, origin: Origin::TopRight, ..Board::default() };
+        let blocks = Sketch::new(&polylines).with_board(board).into_blocks(None);
+        assert_eq!(blocks[0][15..24], [
+            0x75, 0x51, 0xc8, // Move to 1877,456
+            0xfa, 0x40, 0x00, // Pen down
+            0x06, 0x41, 0xdc, // Move to 100,476
+        ]);
+    }
+
+    #[test]
+    fn test_clipping() {
+        // Leaves the board to the right and comes back
+        let polylines: Vec<Polyline> = vec![
+            vec![
+                CoordinatePair::from((10.0, 10.0)),
+                CoordinatePair::from((400.0, 10.0)),
+                CoordinatePair::from((400.0, 20.0)),
+                CoordinatePair::from((10.0, 20.0)),
+            ]
+        ];
+        let blocks = Sketch::new(&polylines).into_blocks(None);
+        assert_eq!(blocks[0][15..39], [
+            0x06, 0x44, 0x6a, // Move to 100,1130
+            0xfa, 0x40, 0x00, // Pen down
+            0xdf, 0xc4, 0x6a, // Move to 3580,1130
+            0xfa, 0x30, 0x00, // Pen lift
+            0xdf, 0xc4, 0x06, // Move to 3580,1030
+            0xfa, 0x40, 0x00, // Pen down
+            0x06, 0x44, 0x06, // Move to 100,1030
+            0xfa, 0x30, 0x00, // Pen lift
+        ]);
+    }
+
+    #[test]
+    fn test_calibration() {
+        let polylines: Vec<Polyline> = vec![
+            vec![
+                CoordinatePair::from((12.3, 45.6)),
+                CoordinatePair::from((14.3, 45.6)),
+            ]
+        ];
+        let calibration = Calibration {
+            affine: Affine { offset_x: 10.0, ..Affine::default() },
+            grid: None,
+        };
+        let blocks = Sketch::new(&polylines).with_calibration(&calibration).into_blocks(None);
+        assert_eq!(blocks[0][15..18], [0x0d, 0xf3, 0x06]); // Move to 223,774
+    }
+
 }
@@ -1,8 +1,8 @@
-//! Code for resizing, scaling and fitting polylines.
-use svg2polylines::Polyline;
+//! Code for resizing, scaling, rotating and fitting polylines.
+use svg2polylines::{CoordinatePair, Polyline};
 
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Range {
     pub min: f64,
     pub max: f64,
@@ -14,7 +14,7 @@ impl Range {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Bounds {
     pub x: Range,
     pub y: Range,
@@ -78,13 +78,116 @@ fn partial_min<T: PartialOrd>(v1: T, v2: T) -> T {
     if v1 <= v2 { v1 } else { v2 }
 }
 
-/// Scale polylines using the specified scaling factor.
-pub fn scale_polylines(polylines: &mut Vec<Polyline>, offset: (f64, f64), scale: (f64, f64)) {
-    info!("Scaling polylines with offset {:?} and scale factor {:?}", offset, scale);
-    for polyline in polylines {
-        for coord in polyline {
-            coord.x = scale.0 * coord.x + offset.0;
-            coord.y = scale.1 * coord.y + offset.1;
+/// An affine transformation `[[a, b, c], [d, e, f]]` that maps `(x, y)` to
+/// `(a x + b y + c, d x + e y + f)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform(pub [[f64; 3]; 2]);
+
+impl Transform {
+    pub fn translate(dx: f64, dy: f64) -> Self {
+        Transform([[1.0, 0.0, dx], [0.0, 1.0, dy]])
+    }
+
+    pub fn scale(sx: f64, sy: f64) -> Self {
+        Transform([[sx, 0.0, 0.0], [0.0, sy, 0.0]])
+    }
+
+    /// Rotate clockwise (the y axis points down) around the origin. The angle
+    /// is in degrees.
+    pub fn rotate(degrees: f64) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Transform([[cos, -sin, 0.0], [sin, cos, 0.0]])
+    }
+
+    /// Return the transformation that applies `self` first and then `next`.
+    pub fn then(&self, next: &Transform) -> Self {
+        let [[a, b, c], [d, e, f]] = next.0;
+        let [[g, h, i], [j, k, l]] = self.0;
+        Transform([
+            [a * g + b * j, a * h + b * k, a * i + b * l + c],
+            [d * g + e * j, d * h + e * k, d * i + e * l + f],
+        ])
+    }
+
+    pub fn apply(&self, point: &CoordinatePair) -> CoordinatePair {
+        let [[a, b, c], [d, e, f]] = self.0;
+        CoordinatePair {
+            x: a * point.x + b * point.y + c,
+            y: d * point.x + e * point.y + f,
+        }
+    }
+
+    /// Return the inverse transformation, or `None` if the transformation
+    /// collapses the drawing onto a line or a point or isn't finite.
+    pub fn inverse(&self) -> Option<Self> {
+        let [[a, b, c], [d, e, f]] = self.0;
+        let determinant = a * e - b * d;
+        if !determinant.is_normal() || !c.is_finite() || !f.is_finite() {
+            return None;
+        }
+        let (a, b, d, e) = (e / determinant, -b / determinant, -d / determinant, a / determinant);
+        Some(Transform([
+            [a, b, -(a * c + b * f)],
+            [d, e, -(d * c + e * f)],
+        ]))
+    }
+}
+
+/// How a drawing is turned before it is scaled and moved to the board.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Orientation {
+    /// Clockwise rotation in degrees.
+    pub rotation: f64,
+    /// Mirror left to right.
+    pub mirror_x: bool,
+    /// Mirror top to bottom.
+    pub mirror_y: bool,
+    /// A general transformation, see `Transform`.
+    pub matrix: Option<[[f64; 3]; 2]>,
+}
+
+impl Orientation {
+    /// Return the transformation for the polylines (in drawing coordinates):
+    /// First they are mirrored and rotated around the center of their
+    /// bounds, then the matrix is applied.
+    pub fn transform(&self, polylines: &Vec<Polyline>) -> Result<Transform, String> {
+        let (cx, cy) = get_bounds(polylines)
+            .map(|bounds| ((bounds.x.min + bounds.x.max) / 2.0, (bounds.y.min + bounds.y.max) / 2.0))
+            .unwrap_or((0.0, 0.0));
+        let mirror = Transform::scale(
+            if self.mirror_x { -1.0 } else { 1.0 },
+            if self.mirror_y { -1.0 } else { 1.0 },
+        );
+        let transform = Transform::translate(-cx, -cy)
+            .then(&mirror)
+            .then(&Transform::rotate(self.rotation))
+            .then(&Transform::translate(cx, cy));
+        let transform = match self.matrix {
+            Some(matrix) => transform.then(&Transform(matrix)),
+            None => transform,
+        };
+        match transform.inverse() {
+            Some(_) => Ok(transform),
+            None => Err("Invalid rotation or transformation matrix".to_string()),
+        }
+    }
+}
+
+/// Transform polylines in place.
+pub fn transform_polylines(polylines: &mut [Polyline], transform: &Transform) {
+    info!("Transforming polylines with {:?}", transform.0);
+    for coord in polylines.iter_mut().flatten() {
+        *coord = transform.apply(coord);
+    }
+}
+
+/// Mirror polylines vertically within their bounds. This converts drawings
+/// whose y axis points up (like those of most plotters) to board coordinates.
+pub fn flip_vertically(polylines: &mut Vec<Polyline>) {
+    if let Some(bounds) = get_bounds(polylines) {
+        for coord in polylines.iter_mut().flatten() {
+            coord.y = bounds.y.min + bounds.y.max - coord.y;
         }
     }
 }
@@ -100,7 +203,7 @@ pub fn fit_polylines(polylines: &mut Vec<Polyline>, target_bounds: &Bounds) -> R
     }
 
     // Calculate current bounds
-    let current_bounds = get_bounds(&polylines)
+    let current_bounds = get_bounds(polylines)
         .ok_or("Could not calculate bounds".to_string())?;
 
     // Calculate scale factor
@@ -132,14 +235,80 @@ pub fn fit_polylines(polylines: &mut Vec<Polyline>, target_bounds: &Bounds) -> R
 mod tests {
     use svg2polylines::CoordinatePair;
 
+    use test_util::assert_close;
+
     use super::*;
 
+    #[test]
+    fn test_transform() {
+        let point = CoordinatePair { x: 2.0, y: 1.0 };
+        let transform = Transform::scale(2.0, 3.0)
+            .then(&Transform::rotate(90.0))
+            .then(&Transform::translate(10.0, 20.0));
+        // Scaled to (4, 3), rotated clockwise to (-3, 4), then moved
+        assert_close(transform.apply(&point), CoordinatePair { x: 7.0, y: 24.0 });
+        let inverse = transform.inverse().unwrap();
+        assert_close(inverse.apply(&transform.apply(&point)), point);
+        assert!(Transform::scale(1.0, 0.0).inverse().is_none());
+        assert!(Transform::scale(1.0, f64::NAN).inverse().is_none());
+        assert!(Transform::translate(f64::INFINITY, 0.0).inverse().is_none());
+    }
+
+    #[test]
+    fn test_orientation() {
+        // A portrait rectangle with its center at (2, 4)
+        let polylines = vec![vec![
+            CoordinatePair { x: 0.0, y: 0.0 },
+            CoordinatePair { x: 4.0, y: 8.0 },
+        ]];
+        let rotated = Orientation { rotation: 90.0, ..Orientation::default() };
+        let transform = rotated.transform(&polylines).unwrap();
+        assert_close(transform.apply(&polylines[0][0]), CoordinatePair { x: 6.0, y: 2.0 });
+        assert_close(transform.apply(&polylines[0][1]), CoordinatePair { x: -2.0, y: 6.0 });
+
+        let mirrored = Orientation {
+            mirror_x: true,
+            matrix: Some([[1.0, 0.0, 5.0], [0.0, 2.0, 0.0]]),
+            ..Orientation::default()
+        };
+        let transform = mirrored.transform(&polylines).unwrap();
+        assert_close(transform.apply(&polylines[0][0]), CoordinatePair { x: 9.0, y: 0.0 });
+
+        let invalid = Orientation { matrix: Some([[1.0, 2.0, 0.0], [2.0, 4.0, 0.0]]), ..Orientation::default() };
+        assert!(invalid.transform(&polylines).is_err());
+        let invalid = Orientation { rotation: f64::NAN, ..Orientation::default() };
+        assert!(invalid.transform(&polylines).is_err());
+    }
+
     #[test]
     fn test_get_bounds_empty() {
         let polylines = vec![];
         assert!(get_bounds(&polylines).is_none()); 
     }
 
+    #[test]
+    fn test_flip_vertically() {
+        let mut polylines = vec![
+            vec![
+                CoordinatePair { x: 1.0, y: 1.0 },
+                CoordinatePair { x: 2.0, y: 4.0 },
+            ],
+            vec![
+                CoordinatePair { x: 0.0, y: 2.0 },
+            ],
+        ];
+        flip_vertically(&mut polylines);
+        assert_eq!(polylines, vec![
+            vec![
+                CoordinatePair { x: 1.0, y: 4.0 },
+                CoordinatePair { x: 2.0, y: 1.0 },
+            ],
+            vec![
+                CoordinatePair { x: 0.0, y: 3.0 },
+            ],
+        ]);
+    }
+
     #[test]
     fn test_get_bounds_1() {
         let polylines = vec![
@@ -0,0 +1,242 @@
+//! A simulated iBoardBot.
+//!
+//! The simulated robot speaks the same protocol as the real one: While idle,
+//! it polls for work with `CL STATUS=READY`. Every received block is decoded,
+//! applied to a simulated pen and acknowledged with `CL STATUS=ACK&NUM=n`.
+//!
+//! A block ends when the host flushes the stream.
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::thread;
+use std::time::Duration;
+
+use board::Board;
+use decode;
+use robot::Command;
+use transport::SIM_PREFIX;
+
+const DEFAULT_POLL_MS: u64 = 1000;
+
+/// The state of the simulated pen.
+///
+/// Coordinates are in robot units (0.1 mm), with the origin in the corner of
+/// the robot.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PenState {
+    pub x: u16,
+    pub y: u16,
+    pub pen_down: bool,
+    pub eraser: bool,
+    pub drawing: bool,
+}
+
+#[derive(Debug)]
+pub struct SimRobot {
+    /// Bytes received since the last flush.
+    input: Vec<u8>,
+    /// Bytes to be read by the host.
+    output: VecDeque<u8>,
+    /// How long to wait before polling while idle.
+    poll_interval: Duration,
+    /// How long it takes to "draw" a block.
+    block_delay: Duration,
+    /// Number of blocks received so far.
+    blocks_received: usize,
+    /// Size of the board in robot units.
+    board_size: (u16, u16),
+    state: PenState,
+}
+
+impl SimRobot {
+    pub fn new(poll_interval: Duration, block_delay: Duration) -> Self {
+        SimRobot {
+            input: vec![],
+            output: VecDeque::new(),
+            poll_interval,
+            block_delay,
+            blocks_received: 0,
+            board_size: Board::default().robot_size(),
+            state: PenState::default(),
+        }
+    }
+
+    /// Set the geometry of the board, to warn about moves outside of it.
+    pub fn with_board(mut self, board: &Board) -> Self {
+        self.board_size = board.robot_size();
+        self
+    }
+
+    /// Create a simulated robot from a device string.
+    ///
+    /// The device string looks like `sim://` or
+    /// `sim://?poll_ms=1000&block_delay_ms=500`.
+    pub fn from_device(device: &str) -> Result<Self, String> {
+        if !device.starts_with(SIM_PREFIX) {
+            return Err(format!("Simulated device must start with {}", SIM_PREFIX));
+        }
+        let mut poll_ms = DEFAULT_POLL_MS;
+        let mut block_delay_ms = 0;
+        let params = device[SIM_PREFIX.len()..].trim_start_matches('?');
+        for param in params.split('&').filter(|p| !p.is_empty()) {
+            let mut parts = param.splitn(2, '=');
+            let key = parts.next().unwrap_or("");
+            let value = parts.next()
+                .and_then(|v| v.parse::<u64>().ok())
+                .ok_or_else(|| format!("Invalid simulator parameter: {}", param))?;
+            match key {
+                "poll_ms" => poll_ms = value,
+                "block_delay_ms" => block_delay_ms = value,
+                _ => return Err(format!("Unknown simulator parameter: {}", key)),
+            }
+        }
+        Ok(Self::new(Duration::from_millis(poll_ms), Duration::from_millis(block_delay_ms)))
+    }
+
+    fn send_line(&mut self, line: &str) {
+        debug!("Sim > {}", line);
+        self.output.extend(line.bytes());
+        self.output.extend(b"\r\n");
+    }
+
+    /// Apply a single drawing command to the simulated pen.
+    fn apply(&mut self, command: Command) {
+        debug!("Sim: {:?}", command);
+        match command {
+            Command::BlockStart | Command::BlockNumber(_) => {},
+            Command::StartDrawing => self.state.drawing = true,
+            Command::StopDrawing => self.state.drawing = false,
+            Command::PenLift => {
+                self.state.pen_down = false;
+                self.state.eraser = false;
+            },
+            Command::PenDown => {
+                self.state.pen_down = true;
+                self.state.eraser = false;
+            },
+            Command::EnableEraser => {
+                self.state.pen_down = false;
+                self.state.eraser = true;
+            },
+            Command::Move(x, y) => {
+                if x > self.board_size.0 || y > self.board_size.1 {
+                    warn!("Sim: Move to ({}, {}) is outside of the board", x, y);
+                }
+                self.state.x = x;
+                self.state.y = y;
+            },
+            Command::Wait(_) => {},
+        }
+    }
+
+    /// Decode and apply the block received since the last flush.
+    fn process_block(&mut self) {
+        let data = mem::take(&mut self.input);
+        if data.is_empty() {
+            return;
+        }
+        let mut number = None;
+        for (offset, result) in decode::decode(&data) {
+            match result {
+                Ok(Command::BlockNumber(n)) => number = Some(n),
+                Ok(command) => self.apply(command),
+                Err(e) => warn!("Sim: {} at byte {}", e, offset),
+            }
+        }
+
+        match number {
+            Some(n) => {
+                self.blocks_received += 1;
+                thread::sleep(self.block_delay);
+                info!("Sim: Processed block {}, pen at ({}, {})", n, self.state.x, self.state.y);
+                self.send_line(&format!("CL STATUS=ACK&NUM={}", n));
+            },
+            None => warn!("Sim: Ignoring block without block number"),
+        }
+    }
+}
+
+impl Read for SimRobot {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.output.is_empty() {
+            // Nothing to say, poll for work like the real robot does
+            thread::sleep(self.poll_interval);
+            self.send_line("CL STATUS=READY");
+        }
+        let count = cmp::min(buf.len(), self.output.len());
+        for (target, byte) in buf.iter_mut().zip(self.output.drain(..count)) {
+            *target = byte;
+        }
+        Ok(count)
+    }
+}
+
+impl Write for SimRobot {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.input.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.process_block();
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+
+    use svg2polylines::{CoordinatePair, Polyline};
+
+    use robot::Sketch;
+    use super::*;
+
+    fn read_line(sim: &mut SimRobot) -> String {
+        let mut line = String::new();
+        BufReader::new(sim).read_line(&mut line).unwrap();
+        line.trim().to_string()
+    }
+
+    #[test]
+    fn test_from_device() {
+        let sim = SimRobot::from_device("sim://?poll_ms=10&block_delay_ms=20").unwrap();
+        assert_eq!(sim.poll_interval, Duration::from_millis(10));
+        assert_eq!(sim.block_delay, Duration::from_millis(20));
+        assert!(SimRobot::from_device("sim://").is_ok());
+        assert!(SimRobot::from_device("sim://?speed=fast").is_err());
+        assert!(SimRobot::from_device("/dev/ttyACM0").is_err());
+    }
+
+    #[test]
+    fn test_poll_when_idle() {
+        let mut sim = SimRobot::from_device("sim://?poll_ms=0").unwrap();
+        assert_eq!(read_line(&mut sim), "CL STATUS=READY");
+    }
+
+    #[test]
+    fn test_ack_blocks() {
+        let polylines: Vec<Polyline> = vec![
+            vec![
+                CoordinatePair::from((12.3, 45.6)),
+                CoordinatePair::from((14.3, 47.6)),
+            ]
+        ];
+        let mut sim = SimRobot::from_device("sim://?poll_ms=0").unwrap();
+        for block in Sketch::new(&polylines).into_blocks(None) {
+            sim.write_all(&block).unwrap();
+            sim.flush().unwrap();
+            assert_eq!(read_line(&mut sim), "CL STATUS=ACK&NUM=1");
+        }
+        assert_eq!(sim.blocks_received, 1);
+        assert_eq!(sim.state, PenState {
+            x: 0,
+            y: 0,
+            pen_down: false,
+            eraser: false,
+            drawing: false,
+        });
+    }
+}
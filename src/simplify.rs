@@ -0,0 +1,137 @@
+//! Code for simplifying polylines before they are sent to the robot.
+use svg2polylines::{CoordinatePair, Polyline};
+
+use board::Board;
+
+/// Return the distance between `point` and the line segment `start`–`end`.
+fn segment_distance(point: &CoordinatePair, start: &CoordinatePair, end: &CoordinatePair) -> f64 {
+    let (dx, dy) = (end.x - start.x, end.y - start.y);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared > 0.0 {
+        (((point.x - start.x) * dx + (point.y - start.y) * dy) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let (px, py) = (start.x + t * dx, start.y + t * dy);
+    ((point.x - px).powi(2) + (point.y - py).powi(2)).sqrt()
+}
+
+/// Simplify a polyline using the Ramer–Douglas–Peucker algorithm.
+///
+/// No point of the original polyline will be further away from the
+/// simplified polyline than `tolerance`.
+pub fn ramer_douglas_peucker(polyline: &[CoordinatePair], tolerance: f64) -> Polyline {
+    if polyline.len() < 3 {
+        return polyline.to_vec();
+    }
+
+    // Mark the points to keep. Use an explicit stack instead of recursion,
+    // polylines can get very long.
+    let mut keep = vec![false; polyline.len()];
+    keep[0] = true;
+    keep[polyline.len() - 1] = true;
+    let mut stack = vec![(0, polyline.len() - 1)];
+    while let Some((first, last)) = stack.pop() {
+        let mut max_distance = 0.0;
+        let mut max_index = first;
+        for i in first + 1..last {
+            let distance = segment_distance(&polyline[i], &polyline[first], &polyline[last]);
+            if distance > max_distance {
+                max_distance = distance;
+                max_index = i;
+            }
+        }
+        if max_distance > tolerance {
+            keep[max_index] = true;
+            stack.push((first, max_index));
+            stack.push((max_index, last));
+        }
+    }
+
+    polyline.iter()
+        .zip(keep)
+        .filter(|&(_, keep)| keep)
+        .map(|(point, _)| *point)
+        .collect()
+}
+
+/// Remove consecutive points that result in the same robot coordinates.
+///
+/// Strokes shorter than the robot resolution keep their first and last
+/// point, so they are drawn as dots instead of being dropped.
+pub fn dedup_robot_coords(polyline: &mut Polyline, board: &Board) {
+    let last = polyline.last().cloned();
+    let len = polyline.len();
+    polyline.dedup_by(|a, b| board.robot_coords(a) == board.robot_coords(b));
+    if len > 1 && polyline.len() == 1 {
+        polyline.extend(last);
+    }
+}
+
+/// Check that a simplification tolerance is a finite, non-negative number.
+pub fn validate_tolerance(tolerance: f64) -> Result<(), String> {
+    if !(tolerance.is_finite() && tolerance >= 0.0) {
+        return Err("Simplify tolerance must be a non-negative number".to_string());
+    }
+    Ok(())
+}
+
+/// Simplify polylines (in board coordinates) with the specified tolerance
+/// in millimeters.
+pub fn simplify_polylines(polylines: &mut Vec<Polyline>, tolerance: f64, board: &Board) {
+    let count = |polylines: &Vec<Polyline>| polylines.iter().map(Vec::len).sum::<usize>();
+    let before = count(polylines);
+    for polyline in polylines.iter_mut() {
+        *polyline = ramer_douglas_peucker(polyline, tolerance);
+        dedup_robot_coords(polyline, board);
+    }
+    info!("Simplified polylines from {} to {} points", before, count(polylines));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use test_util::points;
+
+    use super::*;
+
+    #[test]
+    fn test_rdp_straight_line() {
+        let polyline = points(&[(0.0, 0.0), (1.0, 0.05), (2.0, -0.05), (3.0, 0.0)]);
+        assert_eq!(
+            ramer_douglas_peucker(&polyline, 0.1),
+            points(&[(0.0, 0.0), (3.0, 0.0)])
+        );
+    }
+
+    #[test]
+    fn test_rdp_keeps_corners() {
+        let polyline = points(&[(0.0, 0.0), (5.0, 0.1), (10.0, 0.0), (10.0, 10.0)]);
+        assert_eq!(
+            ramer_douglas_peucker(&polyline, 0.5),
+            points(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)])
+        );
+        assert_eq!(ramer_douglas_peucker(&polyline, 0.0), polyline);
+    }
+
+    #[test]
+    fn test_dedup_robot_coords() {
+        let mut polyline = points(&[(1.0, 1.0), (1.01, 1.0), (1.04, 1.0), (1.2, 1.0)]);
+        dedup_robot_coords(&mut polyline, &Board::default());
+        assert_eq!(polyline, points(&[(1.0, 1.0), (1.2, 1.0)]));
+
+        // Too short for the robot, but still drawn
+        let mut polyline = points(&[(1.0, 1.0), (1.01, 1.0), (1.02, 1.0)]);
+        dedup_robot_coords(&mut polyline, &Board::default());
+        assert_eq!(polyline, points(&[(1.0, 1.0), (1.02, 1.0)]));
+    }
+
+    #[test]
+    fn test_validate_tolerance() {
+        assert!(validate_tolerance(0.0).is_ok());
+        assert!(validate_tolerance(0.5).is_ok());
+        assert!(validate_tolerance(-0.5).is_err());
+        assert!(validate_tolerance(f64::NAN).is_err());
+        assert!(validate_tolerance(f64::INFINITY).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! State of the robot thread, shared with the web server for status
+//! reporting.
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use scheduled_executor::executor::TaskHandle;
+use time::{self, Timespec};
+
+use ::TimeLimits;
+use estimate::TimingModel;
+use events::{Event, SharedEventBus};
+use history::History;
+use jobs::{Job, JobId, JobKind, JobQueue, JobState, QueuePolicy};
+
+/// If the robot didn't send anything for this many seconds, plus the
+/// estimated duration of the block it is executing, it's considered
+/// disconnected.
+const CONNECTION_TIMEOUT_SECS: i64 = 30;
+
+pub type SharedState = Arc<Mutex<RobotState>>;
+
+/// Handle of the active print schedule.
+struct Schedule(TaskHandle);
+
+impl fmt::Debug for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Schedule")
+    }
+}
+
+#[derive(Debug)]
+pub struct RobotState {
+    pub jobs: JobQueue,
+    pub events: SharedEventBus,
+    /// The last line received from the robot, and when it was received.
+    last_message: Option<(String, Timespec)>,
+    /// Whether the robot was connected at the last check.
+    connected: bool,
+    /// The active print schedule (in headless mode).
+    schedule: Option<Schedule>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobStatus {
+    pub id: JobId,
+    pub kind: JobKind,
+    pub state: JobState,
+    pub blocks_sent: usize,
+    pub blocks_acked: usize,
+    pub blocks_total: usize,
+    /// Progress in percent, based on the acknowledged blocks.
+    pub progress: f64,
+    /// Estimated duration of the job in seconds.
+    pub estimated_seconds: f64,
+    /// Estimated time in seconds until the job is printed.
+    pub remaining_seconds: f64,
+    /// Estimated time when the job is printed (RFC 3339, UTC).
+    pub eta: String,
+}
+
+impl<'a> From<&'a Job> for JobStatus {
+    fn from(job: &'a Job) -> Self {
+        let progress = if job.blocks_total() == 0 {
+            100.0
+        } else {
+            job.blocks_acked() as f64 / job.blocks_total() as f64 * 100.0
+        };
+        let remaining_seconds = job.remaining_seconds();
+        let eta = time::get_time() + time::Duration::milliseconds((remaining_seconds * 1000.0) as i64);
+        JobStatus {
+            id: job.id,
+            kind: job.kind,
+            state: job.state,
+            blocks_sent: job.blocks_sent(),
+            blocks_acked: job.blocks_acked(),
+            blocks_total: job.blocks_total(),
+            progress,
+            estimated_seconds: job.estimated_seconds(),
+            remaining_seconds,
+            eta: time::at_utc(eta).rfc3339().to_string(),
+        }
+    }
+}
+
+/// A snapshot of the robot state.
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub connected: bool,
+    pub current_job: Option<JobStatus>,
+    pub queued_jobs: usize,
+    /// Estimated time in seconds until the current and all queued jobs are
+    /// printed.
+    pub remaining_seconds: f64,
+    pub paused: bool,
+    pub schedule_active: bool,
+    pub last_message: Option<String>,
+    /// Time of the last message (RFC 3339, UTC).
+    pub last_message_time: Option<String>,
+    /// Whether scheduled prints are currently skipped because of the
+    /// configured time limits.
+    pub outside_time_limits: bool,
+}
+
+impl RobotState {
+    pub fn new(queue_policy: QueuePolicy, events: SharedEventBus, history: History, timing: TimingModel) -> Self {
+        RobotState {
+            jobs: JobQueue::new(queue_policy, events.clone(), history, timing),
+            events,
+            last_message: None,
+            connected: false,
+            schedule: None,
+        }
+    }
+
+    /// Return whether the robot sent a message recently.
+    ///
+    /// The robot is silent while it executes a block, so the estimated
+    /// duration of the running block is added to the timeout.
+    fn is_connected(&self) -> bool {
+        let now = time::get_time();
+        let block_seconds = self.jobs.current()
+            .and_then(Job::running_block_seconds)
+            .unwrap_or(0.0);
+        let timeout_ms = (CONNECTION_TIMEOUT_SECS as f64 + block_seconds) * 1000.0;
+        self.last_message.as_ref()
+            .map(|&(_, at)| ((now - at).num_milliseconds() as f64) < timeout_ms)
+            .unwrap_or(false)
+    }
+
+    /// Record a line received from the robot.
+    pub fn message_received(&mut self, line: &str) {
+        self.last_message = Some((line.to_string(), time::get_time()));
+        if !self.connected {
+            self.connected = true;
+            self.events.publish(Event::RobotConnected);
+        }
+    }
+
+    /// Check whether the robot is still connected. Emit an event if it
+    /// disconnected since the last check.
+    pub fn check_connection(&mut self) {
+        if self.connected && !self.is_connected() {
+            warn!("Robot did not send anything in {} seconds", CONNECTION_TIMEOUT_SECS);
+            self.connected = false;
+            self.events.publish(Event::RobotDisconnected);
+        }
+    }
+
+    /// Set the active print schedule. A previous schedule is stopped.
+    pub fn set_schedule(&mut self, handle: TaskHandle) {
+        if self.stop_schedule() {
+            info!("Replaced old schedule");
+        }
+        self.schedule = Some(Schedule(handle));
+    }
+
+    /// Stop the active print schedule and cancel its queued jobs. Return
+    /// whether a schedule was active.
+    pub fn stop_schedule(&mut self) -> bool {
+        match self.schedule.take() {
+            Some(Schedule(handle)) => {
+                handle.stop();
+                self.jobs.cancel_queued(JobKind::Scheduled);
+                self.events.publish(Event::ScheduleStopped);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Return a snapshot of the current state.
+    pub fn status(&self, time_limits: Option<TimeLimits>) -> Status {
+        let connected = self.is_connected();
+        let outside_time_limits = time_limits
+            .map(|limits| !limits.is_within_limits(&time::now()))
+            .unwrap_or(false);
+        Status {
+            connected,
+            current_job: self.jobs.current().map(JobStatus::from),
+            queued_jobs: self.jobs.queued_count(),
+            remaining_seconds: self.jobs.remaining_seconds(),
+            paused: self.jobs.is_paused(),
+            schedule_active: self.schedule.is_some(),
+            last_message: self.last_message.as_ref().map(|(line, _)| line.clone()),
+            last_message_time: self.last_message.as_ref()
+                .map(|&(_, at)| time::at_utc(at).rfc3339().to_string()),
+            outside_time_limits,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use events::EventBus;
+    use history::{Drawing, Source};
+    use super::*;
+
+    #[test]
+    fn test_status() {
+        let timing = TimingModel { block_seconds: 3.0, ..TimingModel::default() };
+        let mut state = RobotState::new(QueuePolicy::Fifo, Arc::new(EventBus::new()), History::in_memory(), timing);
+        let status = state.status(None);
+        assert!(!status.connected);
+        assert!(status.current_job.is_none());
+
+        state.message_received("CL STATUS=READY");
+        let drawing = Drawing::new(vec![], Source::Api, None);
+        state.jobs.push(Job::new(JobKind::Once, drawing, vec![vec![1], vec![2]], &timing));
+        state.jobs.next_block();
+        state.jobs.block_acked(1);
+        let status = state.status(None);
+        assert!(status.connected);
+        assert_eq!(status.last_message, Some("CL STATUS=READY".to_string()));
+        let job = status.current_job.unwrap();
+        assert_eq!((job.blocks_sent, job.blocks_acked, job.blocks_total), (1, 1, 2));
+        assert_eq!(job.progress, 50.0);
+        assert_eq!((job.estimated_seconds, job.remaining_seconds), (6.0, 3.0));
+        assert_eq!(status.remaining_seconds, 3.0);
+    }
+
+    #[test]
+    fn test_connected_during_long_block() {
+        let timing = TimingModel { block_seconds: 600.0, ..TimingModel::default() };
+        let mut state = RobotState::new(QueuePolicy::Fifo, Arc::new(EventBus::new()), History::in_memory(), timing);
+        let drawing = Drawing::new(vec![], Source::Api, None);
+        state.jobs.push(Job::new(JobKind::Once, drawing, vec![vec![1], vec![2]], &timing));
+        state.message_received("CL STATUS=READY");
+        state.jobs.next_block();
+
+        // The robot is still executing the first block
+        let silent_since = |secs| Some(("CL STATUS=READY".to_string(), time::get_time() - time::Duration::seconds(secs)));
+        state.last_message = silent_since(300);
+        state.check_connection();
+        assert!(state.status(None).connected);
+
+        // The block took much longer than estimated
+        state.last_message = silent_since(600 + CONNECTION_TIMEOUT_SECS);
+        state.check_connection();
+        assert!(!state.status(None).connected);
+
+        // Without a running block, the plain timeout applies
+        state.jobs.block_acked(1);
+        state.last_message = silent_since(CONNECTION_TIMEOUT_SECS + 1);
+        assert!(!state.status(None).connected);
+    }
+}
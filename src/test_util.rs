@@ -0,0 +1,31 @@
+//! Helpers shared by the unit tests.
+use svg2polylines::{CoordinatePair, Polyline};
+
+/// Return the point at `(x, y)`.
+pub fn point(x: f64, y: f64) -> CoordinatePair {
+    CoordinatePair { x, y }
+}
+
+/// Return a polyline through the specified coordinates.
+pub fn points(coords: &[(f64, f64)]) -> Polyline {
+    coords.iter().map(|&(x, y)| point(x, y)).collect()
+}
+
+/// Assert that two points are equal, apart from rounding errors.
+pub fn assert_close(actual: CoordinatePair, expected: CoordinatePair) {
+    assert!(
+        (actual.x - expected.x).abs() < 1e-9 && (actual.y - expected.y).abs() < 1e-9,
+        "{:?} != {:?}", actual, expected,
+    );
+}
+
+/// Assert that two lists of polylines are equal, apart from rounding errors.
+pub fn assert_polylines_close(actual: &[Polyline], expected: &[Polyline]) {
+    assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+    for (a, e) in actual.iter().zip(expected) {
+        assert_eq!(a.len(), e.len(), "{:?}", actual);
+        for (a, e) in a.iter().zip(e) {
+            assert!((a.x - e.x).abs() < 1e-9 && (a.y - e.y).abs() < 1e-9, "{:?}", actual);
+        }
+    }
+}
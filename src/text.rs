@@ -0,0 +1,185 @@
+//! Code for rendering text as polylines, using a single-stroke font.
+use svg2polylines::{CoordinatePair, Polyline};
+
+use hershey::{self, CAP_HEIGHT, CAP_TOP};
+use scaling::Bounds;
+
+/// How far descenders reach below the baseline, in font units.
+const DESCENT: f64 = 7.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextStyle {
+    /// Height of a capital letter, in millimeters.
+    pub size: f64,
+    /// Distance between the baselines of two lines, relative to the size.
+    pub line_spacing: f64,
+    pub align: Align,
+}
+
+/// Return the width of a line of text, in font units.
+pub fn text_width(text: &str) -> i32 {
+    text.chars().map(|c| i32::from(hershey::glyph(c).advance())).sum()
+}
+
+/// Break text into lines that are at most `max_width` font units wide.
+///
+/// Lines are broken at whitespace. Words that are too long for a line are
+/// broken up as well. Line breaks in the text are preserved.
+fn wrap(text: &str, max_width: i32) -> Vec<String> {
+    let mut lines = vec![];
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+            if text_width(&candidate) <= max_width {
+                line = candidate;
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(line);
+                line = String::new();
+            }
+            for c in word.chars() {
+                let advance = i32::from(hershey::glyph(c).advance());
+                if !line.is_empty() && text_width(&line) + advance > max_width {
+                    lines.push(line);
+                    line = String::new();
+                }
+                line.push(c);
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Render text into polylines (in board coordinates) within the bounds.
+///
+/// The first line starts at the top of the bounds. Return an error if the
+/// text is empty or does not fit.
+pub fn render_text(text: &str, style: &TextStyle, bounds: &Bounds) -> Result<Vec<Polyline>, String> {
+    if text.trim().is_empty() {
+        return Err("Text is empty".into());
+    }
+    if !style.size.is_normal() || style.size < 0.0 {
+        return Err("Invalid text size".into());
+    }
+    if !style.line_spacing.is_finite() || style.line_spacing <= 0.0 {
+        return Err("Invalid line spacing".into());
+    }
+    let scale = style.size / CAP_HEIGHT;
+    let max_width = (bounds.x.spread() / scale).floor() as i32;
+    let lines = wrap(text, max_width);
+
+    // Lines are only too wide if a single character does not fit
+    let width = lines.iter().map(|line| text_width(line)).max().unwrap_or(0);
+    if width > max_width {
+        return Err(format!(
+            "Text needs {:.1} mm width, but only {:.1} mm are available",
+            f64::from(width) * scale, bounds.x.spread(),
+        ));
+    }
+
+    // Check whether the descenders of the last line are within the bounds
+    let line_height = style.size * style.line_spacing;
+    let height = (lines.len() - 1) as f64 * line_height + style.size + DESCENT * scale;
+    if height > bounds.y.spread() {
+        return Err(format!("Text needs {:.1} mm, but only {:.1} mm are available", height, bounds.y.spread()));
+    }
+
+    let mut polylines = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let width = f64::from(text_width(line)) * scale;
+        let mut x = match style.align {
+            Align::Left => bounds.x.min,
+            Align::Center => bounds.x.min + (bounds.x.spread() - width) / 2.0,
+            Align::Right => bounds.x.max - width,
+        };
+        let top = bounds.y.min + i as f64 * line_height;
+        for c in line.chars() {
+            let glyph = hershey::glyph(c);
+            for stroke in &glyph.strokes {
+                polylines.push(stroke.iter()
+                    .map(|&(gx, gy)| CoordinatePair {
+                        x: x + f64::from(gx - glyph.left) * scale,
+                        y: top + (f64::from(gy) - CAP_TOP) * scale,
+                    })
+                    .collect());
+            }
+            x += f64::from(glyph.advance()) * scale;
+        }
+    }
+    Ok(polylines)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use scaling::Range;
+
+    use super::*;
+
+    fn bounds(width: f64, height: f64) -> Bounds {
+        Bounds {
+            x: Range { min: 10.0, max: 10.0 + width },
+            y: Range { min: 20.0, max: 20.0 + height },
+        }
+    }
+
+    fn style(align: Align) -> TextStyle {
+        TextStyle { size: 21.0, line_spacing: 1.5, align }
+    }
+
+    #[test]
+    fn test_wrap() {
+        // Every letter of "HI" is 18 or 12 units wide, a space 12 units
+        assert_eq!(wrap("HI HI", 100), vec!["HI HI"]);
+        assert_eq!(wrap("HI HI", 40), vec!["HI", "HI"]);
+        assert_eq!(wrap("HIHI", 40), vec!["HI", "HI"]);
+        assert_eq!(wrap("HI\n\nHI", 100), vec!["HI", "", "HI"]);
+    }
+
+    #[test]
+    fn test_render_text() {
+        // With a size of 21 mm, one font unit is one millimeter
+        let polylines = render_text("T", &style(Align::Left), &bounds(100.0, 50.0)).unwrap();
+        assert_eq!(polylines, vec![
+            vec![CoordinatePair { x: 13.0, y: 20.0 }, CoordinatePair { x: 27.0, y: 20.0 }],
+            vec![CoordinatePair { x: 20.0, y: 20.0 }, CoordinatePair { x: 20.0, y: 41.0 }],
+        ]);
+
+        let polylines = render_text("T", &style(Align::Right), &bounds(100.0, 50.0)).unwrap();
+        assert_eq!(polylines[1][0], CoordinatePair { x: 100.0, y: 20.0 });
+        let polylines = render_text("T", &style(Align::Center), &bounds(100.0, 50.0)).unwrap();
+        assert_eq!(polylines[1][0], CoordinatePair { x: 60.0, y: 20.0 });
+    }
+
+    #[test]
+    fn test_render_lines() {
+        let polylines = render_text("T T", &style(Align::Left), &bounds(30.0, 100.0)).unwrap();
+        assert_eq!(polylines.len(), 4);
+        assert_eq!(polylines[3][0], CoordinatePair { x: 20.0, y: 20.0 + 31.5 });
+
+        // The second line does not fit
+        assert!(render_text("T T", &style(Align::Left), &bounds(30.0, 50.0)).is_err());
+        assert!(render_text("T", &TextStyle { size: 0.0, ..style(Align::Left) }, &bounds(30.0, 50.0)).is_err());
+
+        // A single letter is wider than the bounds
+        assert!(render_text("T", &style(Align::Left), &bounds(10.0, 50.0)).is_err());
+    }
+
+    #[test]
+    fn test_render_empty() {
+        assert!(render_text("", &style(Align::Left), &bounds(100.0, 50.0)).is_err());
+        assert!(render_text(" \n ", &style(Align::Left), &bounds(100.0, 50.0)).is_err());
+    }
+}
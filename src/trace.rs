@@ -0,0 +1,425 @@
+//! Code for tracing black-and-white images (e.g. logos or scanned drawings)
+//! into polylines.
+//!
+//! Outlines follow the borders between black and white pixels, similar to
+//! potrace. Centerlines follow the skeleton of the black areas, so that every
+//! line of a drawing becomes a single stroke. All coordinates are in pixels.
+use std::collections::HashSet;
+
+use image::GrayAlphaImage;
+use svg2polylines::{CoordinatePair, Polyline};
+
+use simplify;
+
+/// Outlines enclosing fewer pixels than this are dropped as noise.
+const SPECKLE_AREA: f64 = 4.0;
+
+/// Tolerance for simplifying the traced polylines, in pixels. This removes
+/// the staircase of the pixel grid from slanted lines, whose corners are up
+/// to 0.71 pixels away from the line.
+const TOLERANCE: f64 = 0.75;
+
+/// Directions on the pixel grid (with the y axis pointing down): right, down,
+/// left and up.
+const DIRECTIONS: [(i64, i64); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+/// A thresholded image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bitmap {
+    width: usize,
+    height: usize,
+    /// `true` for black pixels, row by row.
+    pixels: Vec<bool>,
+}
+
+impl Bitmap {
+    /// Pixels that are darker than the threshold (from 0 for white to 1 for
+    /// black) are black. Transparent pixels are white.
+    pub fn from_image(image: &GrayAlphaImage, threshold: f64) -> Self {
+        let (width, height) = image.dimensions();
+        let pixels = image.pixels()
+            .map(|pixel| (1.0 - f64::from(pixel[0]) / 255.0) * f64::from(pixel[1]) / 255.0 > threshold)
+            .collect();
+        Bitmap { width: width as usize, height: height as usize, pixels }
+    }
+
+    /// Return whether a pixel is black. Pixels outside of the image are white.
+    fn get(&self, x: i64, y: i64) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+            && self.pixels[y as usize * self.width + x as usize]
+    }
+
+    /// Return the coordinates of all black pixels, row by row.
+    fn black_pixels(&self) -> Vec<(i64, i64)> {
+        (0..self.pixels.len())
+            .filter(|&i| self.pixels[i])
+            .map(|i| ((i % self.width) as i64, (i / self.width) as i64))
+            .collect()
+    }
+}
+
+/// Return the area enclosed by a closed path (positive for clockwise paths,
+/// as the y axis points down).
+fn area(path: &[(i64, i64)]) -> f64 {
+    let twice: i64 = path.iter()
+        .zip(path.iter().cycle().skip(1))
+        .map(|(a, b)| a.0 * b.1 - b.0 * a.1)
+        .sum();
+    twice as f64 / 2.0
+}
+
+/// Trace the outlines of all black areas, and of the holes within them.
+///
+/// Every outline is a closed polyline. Diagonally touching black pixels are
+/// treated as connected.
+pub fn trace_outlines(bitmap: &Bitmap) -> Vec<Polyline> {
+    // The border edges leaving every pixel corner, as bit masks of directions.
+    // Edges run clockwise around black areas.
+    let columns = bitmap.width + 1;
+    let mut edges = vec![0u8; columns * (bitmap.height + 1)];
+    let corner = |x: i64, y: i64| y as usize * columns + x as usize;
+    for (x, y) in bitmap.black_pixels() {
+        if !bitmap.get(x, y - 1) {
+            edges[corner(x, y)] |= 1 << 0;
+        }
+        if !bitmap.get(x + 1, y) {
+            edges[corner(x + 1, y)] |= 1 << 1;
+        }
+        if !bitmap.get(x, y + 1) {
+            edges[corner(x + 1, y + 1)] |= 1 << 2;
+        }
+        if !bitmap.get(x - 1, y) {
+            edges[corner(x, y + 1)] |= 1 << 3;
+        }
+    }
+
+    let mut polylines = vec![];
+    for start in 0..edges.len() {
+        while edges[start] != 0 {
+            // Follow the border until all edges at the current corner are used,
+            // which happens when the path is back at its start
+            let (mut x, mut y) = ((start % columns) as i64, (start / columns) as i64);
+            let mut direction = edges[start].trailing_zeros() as usize;
+            let mut path = vec![];
+            loop {
+                edges[corner(x, y)] &= !(1 << direction);
+                path.push((x, y));
+                x += DIRECTIONS[direction].0;
+                y += DIRECTIONS[direction].1;
+
+                // Where two black pixels touch diagonally, turn left to stay
+                // on the same black area
+                let available = edges[corner(x, y)];
+                match [3, 0, 1].iter().map(|turn| (direction + turn) % 4).find(|&d| available & (1 << d) != 0) {
+                    Some(next) => direction = next,
+                    None => break,
+                }
+            }
+            if area(&path).abs() < SPECKLE_AREA {
+                continue;
+            }
+
+            polylines.push(smooth_outline(&path));
+        }
+    }
+    polylines
+}
+
+/// Turn a closed path along the pixel borders into a closed polyline without
+/// the steps of the pixel grid.
+///
+/// Like in potrace, a turn between two straight runs of at least two edges is
+/// a corner, which is kept exactly. Between the corners, the centers of the
+/// edges are connected and simplified, which turns steps into slanted lines.
+fn smooth_outline(path: &[(i64, i64)]) -> Polyline {
+    let n = path.len();
+    let direction = |i: usize| {
+        let (a, b) = (path[i % n], path[(i + 1) % n]);
+        (b.0 - a.0, b.1 - a.1)
+    };
+    let vertex = |i: usize| CoordinatePair { x: path[i % n].0 as f64, y: path[i % n].1 as f64 };
+    let midpoint = |i: usize| {
+        let (a, b) = (path[i % n], path[(i + 1) % n]);
+        CoordinatePair { x: (a.0 + b.0) as f64 / 2.0, y: (a.1 + b.1) as f64 / 2.0 }
+    };
+
+    // The length of the straight run that every edge belongs to. A closed
+    // path always turns somewhere, start counting there.
+    let first_turn = (0..n).find(|&i| direction(i) != direction(i + n - 1)).unwrap_or(0);
+    let mut run_lengths = vec![0; n];
+    let mut counted = 0;
+    while counted < n {
+        let first = first_turn + counted;
+        let mut length = 1;
+        while counted + length < n && direction(first + length) == direction(first) {
+            length += 1;
+        }
+        for i in first..first + length {
+            run_lengths[i % n] = length;
+        }
+        counted += length;
+    }
+    let corners: Vec<usize> = (0..n)
+        .filter(|&i| direction(i) != direction(i + n - 1))
+        .filter(|&i| run_lengths[i] >= 2 && run_lengths[(i + n - 1) % n] >= 2)
+        .collect();
+
+    if corners.is_empty() {
+        let mut polyline: Polyline = (0..=n).map(midpoint).collect();
+        polyline[n] = polyline[0];
+        return simplify::ramer_douglas_peucker(&polyline, TOLERANCE);
+    }
+    let mut polyline = vec![vertex(corners[0])];
+    for (k, &corner) in corners.iter().enumerate() {
+        let next = corners.get(k + 1).cloned().unwrap_or(corners[0] + n);
+        let mut piece = vec![vertex(corner)];
+        piece.extend((corner..next).map(midpoint));
+        piece.push(vertex(next));
+        polyline.extend(simplify::ramer_douglas_peucker(&piece, TOLERANCE).into_iter().skip(1));
+    }
+    polyline
+}
+
+/// Thin the black areas to lines that are one pixel wide, using the
+/// Zhang–Suen algorithm.
+fn thin(bitmap: &mut Bitmap) {
+    loop {
+        let mut changed = false;
+        for step in 0..2 {
+            let remove: Vec<(i64, i64)> = bitmap.black_pixels().into_iter()
+                .filter(|&(x, y)| {
+                    // The neighbours, clockwise starting at the top
+                    let n = [
+                        bitmap.get(x, y - 1), bitmap.get(x + 1, y - 1),
+                        bitmap.get(x + 1, y), bitmap.get(x + 1, y + 1),
+                        bitmap.get(x, y + 1), bitmap.get(x - 1, y + 1),
+                        bitmap.get(x - 1, y), bitmap.get(x - 1, y - 1),
+                    ];
+                    let black = n.iter().filter(|&&b| b).count();
+                    let transitions = (0..8).filter(|&i| !n[i] && n[(i + 1) % 8]).count();
+                    let (top, right, bottom, left) = (n[0], n[2], n[4], n[6]);
+                    let keep = if step == 0 {
+                        right && bottom && (top || left)
+                    } else {
+                        top && left && (right || bottom)
+                    };
+                    (2..=6).contains(&black) && transitions == 1 && !keep
+                })
+                .collect();
+            for &(x, y) in &remove {
+                bitmap.pixels[y as usize * bitmap.width + x as usize] = false;
+            }
+            changed |= !remove.is_empty();
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Return the neighbours of a pixel of a thinned image. Diagonal neighbours
+/// that are also reachable through an orthogonal neighbour don't count, so
+/// that corners don't form little triangles.
+fn neighbours(skeleton: &Bitmap, (x, y): (i64, i64)) -> Vec<(i64, i64)> {
+    let mut neighbours = vec![];
+    for &(dx, dy) in &[(0, -1), (1, -1), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1)] {
+        if !skeleton.get(x + dx, y + dy) {
+            continue;
+        }
+        if dx != 0 && dy != 0 && (skeleton.get(x + dx, y) || skeleton.get(x, y + dy)) {
+            continue;
+        }
+        neighbours.push((x + dx, y + dy));
+    }
+    neighbours
+}
+
+/// Trace the centerlines of all black areas.
+///
+/// Lines end at line ends and where lines meet. Closed lines (e.g. the
+/// centerline of a ring) become closed polylines.
+pub fn trace_centerlines(bitmap: &Bitmap) -> Vec<Polyline> {
+    let mut skeleton = bitmap.clone();
+    thin(&mut skeleton);
+    let pixels = skeleton.black_pixels();
+
+    // Walk along the line from `start` through `next`, until a line end or a
+    // junction is reached
+    let mut used = HashSet::new();
+    let edge = |a: (i64, i64), b: (i64, i64)| if a < b { (a, b) } else { (b, a) };
+    let walk = |start: (i64, i64), next: (i64, i64), used: &mut HashSet<_>| {
+        used.insert(edge(start, next));
+        let mut path = vec![start, next];
+        let (mut previous, mut current) = (start, next);
+        loop {
+            let n = neighbours(&skeleton, current);
+            if n.len() != 2 {
+                break;
+            }
+            let following = if n[0] == previous { n[1] } else { n[0] };
+            if !used.insert(edge(current, following)) {
+                break;
+            }
+            path.push(following);
+            previous = current;
+            current = following;
+        }
+        path
+    };
+
+    // Start at line ends and junctions first, the remaining lines are closed
+    let mut paths = vec![];
+    for &only_closed in &[false, true] {
+        for &pixel in &pixels {
+            let n = neighbours(&skeleton, pixel);
+            if n.len() == 2 && !only_closed {
+                continue;
+            }
+            if n.is_empty() {
+                paths.push(vec![pixel]);
+            }
+            for next in n {
+                if !used.contains(&edge(pixel, next)) {
+                    let path = walk(pixel, next, &mut used);
+                    paths.push(path);
+                }
+            }
+        }
+    }
+
+    paths.iter()
+        .map(|path| {
+            let polyline: Polyline = path.iter()
+                .map(|&(x, y)| CoordinatePair { x: x as f64 + 0.5, y: y as f64 + 0.5 })
+                .collect();
+            simplify::ramer_douglas_peucker(&polyline, TOLERANCE)
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, LumaA};
+
+    use robot::Sketch;
+    use scaling::{self, Bounds, Range};
+    use test_util::points;
+
+    use super::*;
+
+    /// Create a bitmap from rows of `#` (black) and `.` (white).
+    fn bitmap(rows: &[&str]) -> Bitmap {
+        Bitmap {
+            width: rows[0].len(),
+            height: rows.len(),
+            pixels: rows.iter().flat_map(|row| row.chars().map(|c| c == '#')).collect(),
+        }
+    }
+
+    #[test]
+    fn test_from_image() {
+        let image: GrayAlphaImage = ImageBuffer::from_fn(3, 1, |x, _| match x {
+            0 => LumaA([0, 255]),
+            1 => LumaA([200, 255]),
+            _ => LumaA([0, 0]),
+        });
+        assert_eq!(Bitmap::from_image(&image, 0.5).pixels, vec![true, false, false]);
+    }
+
+    #[test]
+    fn test_outlines() {
+        let polylines = trace_outlines(&bitmap(&[
+            "......",
+            ".####.",
+            ".#..#.",
+            ".#..#.",
+            ".####.",
+            "......",
+            "....#.",
+        ]));
+
+        // The outer border is clockwise, the hole counter-clockwise. The
+        // single pixel is dropped.
+        assert_eq!(polylines, vec![
+            points(&[(1.0, 1.0), (5.0, 1.0), (5.0, 5.0), (1.0, 5.0), (1.0, 1.0)]),
+            points(&[(2.0, 2.0), (2.0, 4.0), (4.0, 4.0), (4.0, 2.0), (2.0, 2.0)]),
+        ]);
+    }
+
+    #[test]
+    fn test_outline_slanted() {
+        // The steps of a slanted edge are removed, the slanted line runs
+        // through their middle
+        let polylines = trace_outlines(&bitmap(&[
+            "#...",
+            "##..",
+            "###.",
+            "####",
+        ]));
+        assert_eq!(polylines, vec![
+            points(&[(0.0, 4.0), (0.5, 0.0), (4.0, 3.5), (0.0, 4.0)]),
+        ]);
+    }
+
+    #[test]
+    fn test_outline_diagonal() {
+        // Diagonally touching pixels belong to the same outline
+        let polylines = trace_outlines(&bitmap(&[
+            "##..",
+            "##..",
+            "..##",
+            "..##",
+        ]));
+        assert_eq!(polylines.len(), 1);
+        assert_eq!(polylines[0].first(), polylines[0].last());
+    }
+
+    #[test]
+    fn test_centerlines() {
+        // A thick L becomes a single stroke
+        let polylines = trace_centerlines(&bitmap(&[
+            "###.......",
+            "###.......",
+            "###.......",
+            "###.......",
+            "###.......",
+            "##########",
+            "##########",
+            "##########",
+        ]));
+        assert_eq!(polylines.len(), 1);
+        let polyline = &polylines[0];
+        assert!(polyline.len() <= 4, "{:?}", polyline);
+        let (first, last) = (polyline[0], polyline[polyline.len() - 1]);
+        let ends = if first.y < last.y { (first, last) } else { (last, first) };
+        assert!(ends.0.y < 2.0 && (ends.0.x - 1.5).abs() <= 1.0, "{:?}", ends);
+        assert!(ends.1.x > 7.0 && (ends.1.y - 6.5).abs() <= 1.0, "{:?}", ends);
+    }
+
+    #[test]
+    fn test_centerline_ring() {
+        let polylines = trace_centerlines(&bitmap(&[
+            ".......",
+            ".#####.",
+            ".#####.",
+            ".##.##.",
+            ".#####.",
+            ".#####.",
+            ".......",
+        ]));
+        assert_eq!(polylines.len(), 1);
+        assert_eq!(polylines[0].first(), polylines[0].last());
+    }
+
+    #[test]
+    fn test_fit_and_sketch() {
+        // Traced polylines are fitted and printed like the ones of an SVG
+        let mut polylines = trace_outlines(&bitmap(&["####", "####"]));
+        let bounds = Bounds { x: Range { min: 5.0, max: 353.0 }, y: Range { min: 5.0, max: 118.0 } };
+        scaling::fit_polylines(&mut polylines, &bounds).unwrap();
+        assert!(polylines[0].iter().all(|p| p.x >= 5.0 && p.x <= 353.0 && p.y >= 5.0 && p.y <= 118.0));
+        let blocks = Sketch::new(&polylines).into_blocks(None);
+        assert_eq!(blocks.len(), 1);
+    }
+}
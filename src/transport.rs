@@ -0,0 +1,60 @@
+//! Transports used to talk to the robot.
+//!
+//! Usually this is a serial port, but for development and testing a
+//! simulated robot can be used instead (see the `sim` module).
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use serial::{self, BaudRate, PortSettings, SerialPort};
+
+use board::Board;
+use sim::SimRobot;
+
+/// Device prefix that selects the simulated robot.
+pub(crate) const SIM_PREFIX: &str = "sim://";
+
+const TIMEOUT_MS_SERIAL: u64 = 1000;
+
+/// A bidirectional byte stream connected to an iBoardBot.
+///
+/// Reads should time out (with an error) if no data is available, so that
+/// the robot thread does not block forever.
+pub trait Transport: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Transport for T {}
+
+/// Return whether the device string refers to the simulated robot.
+pub(crate) fn is_simulated(device: &str) -> bool {
+    device.starts_with(SIM_PREFIX)
+}
+
+/// Configure the serial port
+fn setup_serial<P: SerialPort>(port: &mut P, baud_rate: BaudRate) -> io::Result<()> {
+    port.configure(&PortSettings {
+        baud_rate,
+        char_size: serial::Bits8,
+        parity: serial::ParityNone,
+        stop_bits: serial::Stop1,
+        flow_control: serial::FlowNone,
+    })?;
+    port.set_timeout(Duration::from_millis(TIMEOUT_MS_SERIAL))?;
+    Ok(())
+}
+
+/// Open the transport for the specified device.
+///
+/// If the device starts with `sim://`, a simulated robot is returned.
+/// Otherwise the device is treated as a serial port.
+pub(crate) fn open(device: &str, baud_rate: BaudRate, board: Board) -> io::Result<Box<dyn Transport>> {
+    if is_simulated(device) {
+        let sim = SimRobot::from_device(device)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            .with_board(&board);
+        info!("Using simulated robot");
+        return Ok(Box::new(sim));
+    }
+    info!("Connecting to {} with baud rate {}...", device, baud_rate.speed());
+    let mut port = serial::open(device)?;
+    setup_serial(&mut port, baud_rate)?;
+    Ok(Box::new(port))
+}
@@ -8,3 +8,16 @@ function ready(fn) {
         document.addEventListener('DOMContentLoaded', fn);
     }
 }
+
+/**
+ * Format a duration in seconds, e.g. "1 h 5 min" or "45 s".
+ */
+function formatDuration(seconds) {
+    const minutes = Math.round(seconds / 60);
+    if (seconds < 60) {
+        return Math.round(seconds) + ' s';
+    } else if (minutes < 60) {
+        return minutes + ' min';
+    }
+    return Math.floor(minutes / 60) + ' h ' + (minutes % 60) + ' min';
+}
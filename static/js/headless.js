@@ -92,9 +92,58 @@ function loadConfig() {
     request.send();
 }
 
+/**
+ * Load the robot status.
+ */
+function loadStatus() {
+    const request = new XMLHttpRequest();
+    request.open('GET', '/status/', true);
+    request.setRequestHeader('Content-Type', 'application/json');
+    request.onload = function() {
+        const element = document.querySelector('#status');
+        // Hide loading text
+        element.querySelector('.loading').hidden = true;
+        if (this.status == 200) {
+            // Success
+            const status = JSON.parse(this.response);
+            const job = status.current_job;
+            const entries = [
+                {label: "Robot", value: status.connected ? 'Connected' : 'Disconnected'},
+                {label: "Current job", value: job ? '#' + job.id + ' (' + job.state + ')' : 'None'},
+                {label: "Progress", value: job ? job.blocks_acked + '/' + job.blocks_total + ' blocks (' + Math.round(job.progress) + '%)' : '-'},
+                {label: "Remaining", value: job ? formatDuration(job.remaining_seconds) + ' (done at ' + job.eta + ')' : '-'},
+                {label: "Queued jobs", value: status.queued_jobs + (status.queued_jobs ? ' (' + formatDuration(status.remaining_seconds) + ' in total)' : '')},
+                {label: "Last message", value: status.last_message ? status.last_message + ' (' + status.last_message_time + ')' : '-'},
+                {label: "Outside of time limits", value: status.outside_time_limits ? 'Yes' : 'No'},
+            ];
+            const items = element.querySelector('dl.items');
+            items.innerHTML = '';
+            for (const entry of entries) {
+                const key = document.createElement('dt');
+                key.appendChild(document.createTextNode(entry.label));
+                items.appendChild(key);
+                const value = document.createElement('dd');
+                value.appendChild(document.createTextNode(entry.value));
+                items.appendChild(value);
+            }
+            element.querySelector('.error').hidden = true;
+            items.hidden = false;
+        } else {
+            // Error
+            console.error('Error: HTTP', this.status);
+            const error = element.querySelector('.error');
+            error.innerText = 'Error fetching status (HTTP ' + this.status + ')';
+            error.hidden = false;
+        }
+    }
+    request.send();
+}
+
 ready(() => {
     console.info('Started.');
 
+    loadStatus();
+    setInterval(loadStatus, 10000);
     loadConfig();
     loadSvgList();
 });
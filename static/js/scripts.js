@@ -1,32 +1,92 @@
-const IBB_WIDTH = 358;
-const IBB_HEIGHT = 123;
 const PREVIEW_SCALE_FACTOR = 3; // Preview is scaled with a factor of 3
 
+// Board geometry in millimeters, replaced by the configured one on startup
+const board = {
+    width: 358,
+    height: 123,
+    padding: 5,
+};
+
+/**
+ * Load the board geometry from the server and resize the canvas to match.
+ */
+function loadBoard(canvas) {
+    const request = new XMLHttpRequest();
+    request.open('GET', '/config/', true);
+    request.onload = function() {
+        if (this.status == 200) {
+            Object.assign(board, JSON.parse(this.response).board);
+            canvas.setDimensions({
+                width: board.width * PREVIEW_SCALE_FACTOR,
+                height: board.height * PREVIEW_SCALE_FACTOR,
+            });
+        } else {
+            console.error('Could not load config: HTTP', this.status);
+        }
+    }
+    request.send();
+}
+
+/**
+ * Return the selected style for converting raster images.
+ */
+function rasterStyle() {
+    return {type: document.querySelector('select[name=raster-style]').value};
+}
+
+/**
+ * Return the selected rotation and mirroring.
+ */
+function orientation() {
+    return {
+        rotation: parseFloat(document.querySelector('select[name=rotation]').value),
+        mirror_x: document.querySelector('input[name=mirror-x]').checked,
+        mirror_y: document.querySelector('input[name=mirror-y]').checked,
+    };
+}
+
+/**
+ * Return the vector format of a file, based on its extension.
+ */
+function vectorFormat(filename) {
+    const extension = filename.split('.').pop().toLowerCase();
+    if (['gcode', 'gc', 'nc', 'ngc'].includes(extension)) {
+        return 'gcode';
+    } else if (['hpgl', 'hpg', 'plt'].includes(extension)) {
+        return 'hpgl';
+    }
+    return 'svg';
+}
+
 /**
- * Load an SVG file.
+ * Load an SVG, G-code or HPGL file or a raster image.
  */
 function loadSvg(ev, svg, canvas) {
-    if (svg.text) {
+    if (svg.text || svg.image) {
         const request = new XMLHttpRequest();
         request.open('POST', '/preview/', true);
         request.setRequestHeader('Content-Type', 'application/json');
         request.onload = function() {
             if (this.status == 200) {
                 // Success
-                const polylines = JSON.parse(this.response);
+                const preview = JSON.parse(this.response);
                 canvas.clear();
-                drawPreview(canvas, polylines);
+                drawPreview(canvas, preview.polylines);
             } else {
                 // Error
                 console.error('Error: HTTP', this.status);
                 if (this.status == 400) {
-                    alert('Error. Did you upload a valid SVG file?');
+                    alert('Error. Did you upload a valid SVG, G-code, HPGL, PNG or JPEG file?');
                 } else {
                     alert('Error (HTTP ' + this.status + ')');
                 }
             }
         }
-        request.send(JSON.stringify({svg: svg.text}));
+        if (svg.image) {
+            request.send(JSON.stringify({image: svg.image, raster_style: rasterStyle(), ...orientation()}));
+        } else {
+            request.send(JSON.stringify({[svg.format]: svg.text, ...orientation()}));
+        }
     }
 }
 
@@ -58,9 +118,9 @@ function drawPreview(canvas, polylines) {
     const groupObj = new fabric.Group(group);
 
     // Re-scale group to fit and center it in viewport
-    const offset = 5 * PREVIEW_SCALE_FACTOR;
-    const height = IBB_HEIGHT * PREVIEW_SCALE_FACTOR;
-    const width = IBB_WIDTH * PREVIEW_SCALE_FACTOR;
+    const offset = board.padding * PREVIEW_SCALE_FACTOR;
+    const height = board.height * PREVIEW_SCALE_FACTOR;
+    const width = board.width * PREVIEW_SCALE_FACTOR;
     if ((groupObj.height / groupObj.width) > (height / width)) {
         groupObj.scaleToHeight(height - offset * 2);
     } else {
@@ -73,6 +133,57 @@ function drawPreview(canvas, polylines) {
     canvas.add(groupObj);
 }
 
+/**
+ * Return the request to print (or render) a canvas object.
+ */
+function printRequest(svg, obj) {
+    const optimize = document.querySelector('input[name=optimize]').checked;
+    const partialErase = document.querySelector('input[name=partial-erase]').checked;
+    const dx = (obj.left - obj._originalLeft) / PREVIEW_SCALE_FACTOR;
+    const dy = (obj.top - obj._originalTop) / PREVIEW_SCALE_FACTOR;
+    return {
+        [svg.format]: svg.text,
+        'image': svg.image,
+        'raster_style': rasterStyle(),
+        'offset_x': dx,
+        'offset_y': dy,
+        'scale_x': obj.scaleX,
+        'scale_y': obj.scaleY,
+        'optimize': optimize,
+        'erase_strategy': partialErase ? {'type': 'bounding_box'} : null,
+        ...orientation(),
+    };
+}
+
+/**
+ * Render the path the robot will take and show it in a new window.
+ */
+function renderObject(svg, canvas) {
+    return function(clickEvent) {
+        const obj = canvas.getObjects()[0];
+        if (obj === undefined) {
+            alert('No object loaded. Please choose an SVG, G-code, HPGL, PNG or JPEG file first.');
+            return;
+        }
+
+        const request = new XMLHttpRequest();
+        request.open('POST', '/render/', true);
+        request.setRequestHeader('Content-Type', 'application/json');
+        request.responseType = 'blob';
+        request.onload = function() {
+            if (this.status == 200) {
+                window.open(URL.createObjectURL(this.response));
+            } else {
+                console.error('Error: HTTP', this.status);
+                alert('Error (HTTP ' + this.status + ')');
+            }
+        }
+        const body = printRequest(svg, obj);
+        body['format'] = 'svg';
+        request.send(JSON.stringify(body));
+    }
+}
+
 /**
  * Send the object to the printer.
  */
@@ -81,7 +192,7 @@ function printObject(svg, canvas) {
         const printMode = document.querySelector('input[name=mode]:checked').value;
 
         if (canvas.getObjects().length == 0) {
-            alert('No object loaded. Please choose an SVG file first.');
+            alert('No object loaded. Please choose an SVG, G-code, HPGL, PNG or JPEG file first.');
             return;
         }
 
@@ -96,31 +207,34 @@ function printObject(svg, canvas) {
             request.open('POST', '/print/', true);
             request.setRequestHeader('Content-Type', 'application/json');
             request.onload = function() {
-                if (this.status == 204) {
-                    // Success TODO
+                if (this.status == 200) {
+                    // Success
+                    const response = JSON.parse(this.response);
+                    const duration = ' Estimated time: ' + formatDuration(response.estimate.seconds);
+                    let note = '';
+                    if (response.clipping.clipped_length > 0) {
+                        const percent = 100 * response.clipping.clipped_length / response.clipping.total_length;
+                        note = '\nNote: ' + percent.toFixed(0) + '% of the drawing is outside of the board and will not be printed.';
+                    }
                     if (printMode == 'once') {
-                        alert('Printing!');
+                        alert('Printing!' + duration + note);
                     } else {
-                        alert('Scheduled printing!');
+                        alert('Scheduled printing!' + duration + ' per print' + note);
                     }
                 } else {
                     // Error
                     console.error('Error: HTTP', this.status);
                     if (this.status == 400) {
-                        alert('Error. Did you upload a valid SVG file?');
+                        alert('Error. Did you upload a valid SVG, G-code, HPGL, PNG or JPEG file?');
                     } else {
                         alert('Error (HTTP ' + this.status + ')');
                     }
                 }
             }
-            request.send(JSON.stringify({
-                'svg': svg.text,
-                'offset_x': dx,
-                'offset_y': dy,
-                'scale_x': obj.scaleX,
-                'scale_y': obj.scaleY,
-                'mode': printMode,
-            }));
+            const body = printRequest(svg, obj);
+            body['mode'] = printMode;
+            body['source'] = 'upload';
+            request.send(JSON.stringify(body));
         });
     }
 }
@@ -130,23 +244,47 @@ ready(() => {
 
     // Fabric.js canvas object
     const canvas = new fabric.Canvas('preview');
+    loadBoard(canvas);
     let svg = {
         text: '',
+        format: 'svg',
+        image: null,
     }
 
     const fileInput = document.querySelector('input[name=file]');
-    fileInput.addEventListener('change', (changeEvent) => {
+    const loadFile = (changeEvent) => {
         const file = fileInput.files[0];
         if (file !== undefined) {
+            // Raster images are sent as data URLs and converted on the server
+            const isRaster = file.type == 'image/png' || file.type == 'image/jpeg';
             const fr = new FileReader();
             fr.onload = function(ev) {
-                svg.text = ev.target.result;
+                svg.text = isRaster ? '' : ev.target.result;
+                svg.format = vectorFormat(file.name);
+                svg.image = isRaster ? ev.target.result : null;
                 loadSvg.bind(this)(ev, svg, canvas);
             }
-            fr.readAsText(file);
+            if (isRaster) {
+                fr.readAsDataURL(file);
+            } else {
+                fr.readAsText(file);
+            }
         }
-    });
+    };
+    fileInput.addEventListener('change', loadFile);
+
+    // Convert the image again if the style changes
+    const rasterStyleSelect = document.querySelector('select[name=raster-style]');
+    rasterStyleSelect.addEventListener('change', loadFile);
+
+    // The preview shows the rotated and mirrored drawing
+    document.querySelector('select[name=rotation]').addEventListener('change', loadFile);
+    document.querySelector('input[name=mirror-x]').addEventListener('change', loadFile);
+    document.querySelector('input[name=mirror-y]').addEventListener('change', loadFile);
 
     const print = document.querySelector('input#print');
     print.addEventListener('click', printObject(svg, canvas));
+
+    const render = document.querySelector('input#render');
+    render.addEventListener('click', renderObject(svg, canvas));
 });